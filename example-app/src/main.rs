@@ -14,7 +14,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     .unwrap();
     window.limit_update_rate(Some(std::time::Duration::from_secs_f64(1.0 / TARGET_FPS)));
 
-    let mut hot_reloaded = hot_reloaded_state::owner(
+    let mut hot_reloaded = hot_reloaded_state::HotReloaded::owner(
         hot_reloaded_state::Arguments {
             window_width,
             window_height,
@@ -28,7 +28,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         if window.get_mouse_pos(MouseMode::Discard).is_some() {
             let is_mouse_down = window.get_mouse_down(MouseButton::Left);
             if !is_mouse_down && mouse_was_down {
-                hot_reloaded.channel_to_impl.send(&());
+                hot_reloaded.channel_to_impl.force_send(&());
             }
             mouse_was_down = is_mouse_down;
         }
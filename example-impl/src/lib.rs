@@ -1,7 +1,7 @@
 use arraystring::CacheString;
 
 pub fn render() {
-  let mut hot_reloaded = hot_reloaded_state::reloadable().unwrap();
+  let mut hot_reloaded = hot_reloaded_state::HotReloaded::reloadable().unwrap();
   let window_len = hot_reloaded.buffer.length;
   hot_reloaded
     .buffer
@@ -17,7 +17,7 @@ pub fn render() {
     clicks_since_start += 1;
     hot_reloaded
       .channel_from_impl
-      .send(&CacheString::from_str_truncate(format!(
+      .force_send(&CacheString::from_str_truncate(format!(
         "{}",
         clicks_since_start
       )));
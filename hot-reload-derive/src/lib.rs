@@ -0,0 +1,850 @@
+//! The `#[hot_reload::state]` attribute, which turns an ordinary struct into the state shared
+//! between the owner and the reloadable processes, and `#[hot_reload::service]` for traits
+//! called through it. Use them through the `hot-reload` crate.
+
+extern crate proc_macro;
+
+mod service;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote, ToTokens};
+use syn::parse::{Parse, ParseStream};
+use syn::spanned::Spanned;
+use syn::{
+    parse_macro_input, Attribute, Error, Expr, Fields, GenericParam, Ident, ItemStruct, ItemTrait,
+    LitStr, Token, Type, Visibility,
+};
+
+/// `quote!` with `mixed_site` hygiene for the code of a state, so that its locals (`prefix`,
+/// `arguments`, `fields`, `name`, the sizes of the fields, ...) can't clash with the fields of
+/// the state, which are bound as locals too
+macro_rules! quote_hygienic {
+    ($($tokens:tt)*) => {
+        quote::quote_spanned!(Span::mixed_site()=> $($tokens)*)
+    };
+}
+
+/// Turns a struct into the state shared between the owner and the reloadable processes.
+///
+/// Every field is a value, a slice or a channel of the type it is declared with:
+///
+/// - `name: T` is a single value, starting out zeroed
+/// - `#[slice(len = ...)] name: [T]` is a slice, which the owner can resize
+/// - `#[channel] name: T` is a channel of `T` holding one value, `#[channel(capacity = ...)]`
+///   makes room for more. Sending to a full channel waits for the receiver, `force_send()`
+///   drops the oldest value instead.
+/// - `#[queue(capacity = ...)] name: T` is a channel of `T` any number of threads can send to
+///   at the same time, holding `capacity` values (one without a capacity). Sending to a full
+///   queue waits for the receiver.
+/// - `#[ring(capacity = ...)] name: T` is a channel of `T` from one thread to another without
+///   locks, for values sent at a high rate, holding `capacity` values (one without a capacity).
+///   Sending to a full ring waits for the receiver.
+/// - `#[pool(len = ..., blocks = ...)] name: T` is a pool of `blocks` blocks of `len` values of
+///   `T`, written in place by one process and sent to the other through a channel of
+///   `hot_reload::PoolBlock`
+/// - `#[broadcast(capacity = ...)] name: T` is a channel of `T` where every subscriber receives
+///   each value, keeping the last `capacity` values (one without a capacity). Only on Unix.
+/// - `#[rpc] name: fn(Req) -> Resp` is a call from one process answered by the other, see
+///   `hot_reload::SharedRpc`
+/// - `#[service] name: dyn Trait` calls the methods of a `#[hot_reload::service]` trait
+/// - `#[nested] name: Other` holds the fields of `Other`, a state declared without a
+///   reloadable package
+///
+/// Marking a field with `#[array]` and giving it the type of an array makes it an array of
+/// such fields, e.g. `#[channel] #[array] players: [Input; 4]` is four channels of `Input`.
+/// The shared memory of nested fields is named after their path, e.g. `player/input` or
+/// `players/3`.
+///
+/// The length of slices and the capacity of channels are expressions, or closures called
+/// with the arguments of the state.
+///
+/// A value or slice can be marked with `#[writer(owner)]` or `#[writer(reloadable)]`. Only
+/// that process may then write it, the other one maps the memory read only and its `get()`
+/// returns a shared reference.
+///
+/// The attribute takes the package of the reloadable process, the type of the arguments
+/// passed to it and optionally `arena` to lay out all the fields in a single shared memory
+/// segment (see `HotReload::use_arena()`). A state nested in others only takes the type of
+/// the arguments, the same as theirs, and implements `hot_reload::SharedFields`.
+///
+/// The outermost struct gets `owner()`, `persistent_owner()` and `reloadable()` constructors and its
+/// fields become handles to the shared memory. Any data in the state must not contain
+/// pointers.
+///
+/// # Examples
+///
+/// ```ignore
+/// #[hot_reload::state(reloadable = "example-impl", args = Arguments, arena)]
+/// pub struct HotReloaded {
+///     /// The pixels of the window
+///     #[slice(len = |arguments: &Arguments| arguments.window_width * arguments.window_height)]
+///     #[writer(reloadable)]
+///     pub buffer: [u32],
+///     #[channel]
+///     pub channel_to_impl: (),
+///     #[channel(capacity = 8)]
+///     pub channel_from_impl: CacheString,
+///     #[nested]
+///     #[array]
+///     pub players: [Player; 4],
+/// }
+///
+/// #[hot_reload::state(args = Arguments)]
+/// pub struct Player {
+///     pub score: u32,
+///     #[channel(capacity = 16)]
+///     pub input: Input,
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn state(args: TokenStream, input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as StateArgs);
+    let item = parse_macro_input!(input as ItemStruct);
+    expand(args, item)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+/// Lets the methods of a trait be called from one process and answered by an implementation
+/// in the other one, through a `#[service] name: dyn Trait` field of a state.
+///
+/// Generates `{Trait}Request` and `{Trait}Response` enums with a variant per method, implements
+/// `hot_reload::Service` for `dyn Trait` and adds a `{Trait}Client` trait with the methods of
+/// the trait for `hot_reload::SharedService<dyn Trait>`. Its methods return `None` when the other
+/// process doesn't answer in time. The methods take `&self` or `&mut self` and their arguments
+/// and results must be `Copy` without pointers, like any data in the state.
+///
+/// # Examples
+///
+/// ```ignore
+/// #[hot_reload::service]
+/// pub trait Renderer {
+///     fn render(&mut self, time: f64);
+///     fn on_click(&mut self, x: u32, y: u32) -> bool;
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn service(args: TokenStream, input: TokenStream) -> TokenStream {
+    if !args.is_empty() {
+        return Error::new(
+            Span::call_site(),
+            "#[hot_reload::service] takes no arguments",
+        )
+        .to_compile_error()
+        .into();
+    }
+    let item = parse_macro_input!(input as ItemTrait);
+    service::expand(item)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+struct StateArgs {
+    /// None for a state only nested in other states
+    reloadable: Option<LitStr>,
+    args: Type,
+    arena: bool,
+}
+
+impl Parse for StateArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut reloadable = None;
+        let mut args = None;
+        let mut arena = false;
+        while !input.is_empty() {
+            let key: Ident = input.parse()?;
+            match key.to_string().as_str() {
+                "reloadable" => {
+                    input.parse::<Token![=]>()?;
+                    reloadable = Some(input.parse()?);
+                }
+                "args" => {
+                    input.parse::<Token![=]>()?;
+                    args = Some(input.parse()?);
+                }
+                "arena" => arena = true,
+                _ => {
+                    return Err(Error::new(
+                        key.span(),
+                        "expected `reloadable = \"package\"`, `args = Type` or `arena`",
+                    ))
+                }
+            }
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
+            }
+        }
+        let missing = |what: &str| {
+            Error::new(
+                Span::call_site(),
+                format!("missing `{}` in #[hot_reload::state(..)]", what),
+            )
+        };
+        if arena && reloadable.is_none() {
+            return Err(Error::new(
+                Span::call_site(),
+                "only the outermost state, with `reloadable = \"package\"`, can be an arena",
+            ));
+        }
+        Ok(StateArgs {
+            reloadable,
+            args: args.ok_or_else(|| missing("args = Type"))?,
+            arena,
+        })
+    }
+}
+
+enum Kind {
+    Value,
+    Slice {
+        len: Expr,
+    },
+    Channel {
+        capacity: Option<Expr>,
+    },
+    Queue {
+        capacity: Option<Expr>,
+    },
+    Ring {
+        capacity: Option<Expr>,
+    },
+    Pool {
+        len: Box<Expr>,
+        blocks: Box<Expr>,
+    },
+    Broadcast {
+        capacity: Option<Expr>,
+    },
+    Rpc {
+        response: Type,
+    },
+    Service,
+    /// Another state, declared without a reloadable package
+    Nested,
+}
+
+enum Writer {
+    Both,
+    Owner,
+    Reloadable,
+}
+
+struct Field {
+    /// Attributes that aren't ours, e.g. doc comments
+    attrs: Vec<Attribute>,
+    vis: Visibility,
+    ident: Ident,
+    /// Type of the value, of the elements of the slice, of the values sent through the channel,
+    /// of the requests of the rpc, the trait object of the service or of the nested state. For arrays, of every element.
+    ty: Type,
+    kind: Kind,
+    /// Length of an array of fields
+    array: Option<Expr>,
+    writer: Writer,
+}
+
+impl Field {
+    fn parse(field: syn::Field) -> syn::Result<Self> {
+        let ident = field
+            .ident
+            .clone()
+            .ok_or_else(|| Error::new(field.span(), "the fields of the state must be named"))?;
+        if ident == "hot_reload" || ident == "side" {
+            return Err(Error::new(
+                ident.span(),
+                format!("`{}` is reserved for the internals of the state", ident),
+            ));
+        }
+
+        let mut attrs = Vec::new();
+        let mut kind = None;
+        let mut writer = None;
+        let mut array = false;
+        for attr in field.attrs {
+            let new_kind = if attr.path.is_ident("value") {
+                Kind::Value
+            } else if attr.path.is_ident("slice") {
+                let len = attr.parse_args_with(|input: ParseStream| parse_setting(input, "len"))?;
+                Kind::Slice { len }
+            } else if attr.path.is_ident("channel") {
+                Kind::Channel {
+                    capacity: parse_capacity(&attr)?,
+                }
+            } else if attr.path.is_ident("queue") {
+                Kind::Queue {
+                    capacity: parse_capacity(&attr)?,
+                }
+            } else if attr.path.is_ident("ring") {
+                Kind::Ring {
+                    capacity: parse_capacity(&attr)?,
+                }
+            } else if attr.path.is_ident("pool") {
+                attr.parse_args_with(|input: ParseStream| {
+                    let len = Box::new(parse_setting(input, "len")?);
+                    input.parse::<Token![,]>()?;
+                    let blocks = Box::new(parse_setting(input, "blocks")?);
+                    Ok(Kind::Pool { len, blocks })
+                })?
+            } else if attr.path.is_ident("broadcast") {
+                Kind::Broadcast {
+                    capacity: parse_capacity(&attr)?,
+                }
+            } else if attr.path.is_ident("rpc") {
+                // The response type is known once the type of the field is parsed
+                Kind::Rpc {
+                    response: syn::parse_quote!(()),
+                }
+            } else if attr.path.is_ident("service") {
+                Kind::Service
+            } else if attr.path.is_ident("nested") {
+                Kind::Nested
+            } else if attr.path.is_ident("array") {
+                array = true;
+                continue;
+            } else if attr.path.is_ident("writer") {
+                let side: Ident = attr.parse_args()?;
+                writer = Some(match side.to_string().as_str() {
+                    "owner" => Writer::Owner,
+                    "reloadable" => Writer::Reloadable,
+                    _ => {
+                        return Err(Error::new(
+                            side.span(),
+                            "expected `#[writer(owner)]` or `#[writer(reloadable)]`",
+                        ))
+                    }
+                });
+                continue;
+            } else {
+                attrs.push(attr);
+                continue;
+            };
+            if kind.is_some() {
+                return Err(Error::new_spanned(
+                    attr,
+                    "a field is only one of a value, a slice, a channel, a queue, a ring, a pool, a \
+                     broadcast, an rpc, a service or a nested state",
+                ));
+            }
+            kind = Some(new_kind);
+        }
+        let mut kind = kind.unwrap_or(Kind::Value);
+
+        let (ty, array) = match (array, field.ty) {
+            (false, ty) => (ty, None),
+            (true, Type::Array(array)) => (*array.elem, Some(array.len)),
+            (true, ty) => {
+                return Err(Error::new_spanned(
+                    ty,
+                    "an #[array] field has the type of an array, e.g. `[T; 4]`",
+                ))
+            }
+        };
+        let ty = match (&mut kind, ty) {
+            (Kind::Slice { .. }, Type::Slice(slice)) => *slice.elem,
+            (Kind::Slice { .. }, ty) => {
+                return Err(Error::new_spanned(
+                    ty,
+                    "a #[slice] field has the type of a slice, e.g. `[u32]`",
+                ))
+            }
+            (_, Type::Slice(slice)) => {
+                return Err(Error::new_spanned(
+                    slice,
+                    "a slice field needs its length, e.g. `#[slice(len = 1024)]`",
+                ))
+            }
+            (Kind::Rpc { response }, Type::BareFn(function)) if function.inputs.len() == 1 => {
+                if let syn::ReturnType::Type(_, ty) = &function.output {
+                    *response = (**ty).clone();
+                }
+                function.inputs[0].ty.clone()
+            }
+            (Kind::Rpc { .. }, ty) => {
+                return Err(Error::new_spanned(
+                    ty,
+                    "an #[rpc] field has the type of a function taking the request and returning \
+                     the response, e.g. `fn(Point) -> bool`",
+                ))
+            }
+            (Kind::Service, Type::TraitObject(object)) => Type::TraitObject(object),
+            (Kind::Service, ty) => {
+                return Err(Error::new_spanned(
+                    ty,
+                    "a #[service] field has the type of the trait of the service, e.g. \
+                     `dyn Renderer`",
+                ))
+            }
+            (Kind::Nested, Type::Path(mut path)) => {
+                // The nested state is on the same side as the one containing it
+                let last = path.path.segments.last_mut().unwrap();
+                match &mut last.arguments {
+                    syn::PathArguments::None => {
+                        last.arguments = syn::PathArguments::AngleBracketed(syn::parse_quote!(<S>))
+                    }
+                    syn::PathArguments::AngleBracketed(arguments) => {
+                        arguments.args.push(syn::parse_quote!(S))
+                    }
+                    syn::PathArguments::Parenthesized(_) => {
+                        return Err(Error::new_spanned(path, "expected the name of a state"))
+                    }
+                }
+                Type::Path(path)
+            }
+            (Kind::Nested, ty) => {
+                return Err(Error::new_spanned(ty, "expected the name of a state"))
+            }
+            (_, ty) => ty,
+        };
+        if let (
+            Kind::Channel { .. }
+            | Kind::Queue { .. }
+            | Kind::Ring { .. }
+            | Kind::Pool { .. }
+            | Kind::Broadcast { .. }
+            | Kind::Rpc { .. }
+            | Kind::Service,
+            Some(_),
+        ) = (&kind, &writer)
+        {
+            return Err(Error::new(
+                ident.span(),
+                "both processes write to a channel, a queue, a ring, a pool, a broadcast, an rpc or a \
+                 service, it can't have a #[writer]",
+            ));
+        }
+        if let (Kind::Nested, Some(_)) = (&kind, &writer) {
+            return Err(Error::new(
+                ident.span(),
+                "the writers are set on the fields of the nested state, it can't have a #[writer]",
+            ));
+        }
+
+        Ok(Field {
+            attrs,
+            vis: field.vis,
+            ident,
+            ty,
+            kind,
+            array,
+            writer: writer.unwrap_or(Writer::Both),
+        })
+    }
+
+    fn handle_type(&self) -> TokenStream2 {
+        let ty = &self.ty;
+        let access = match self.writer {
+            Writer::Both => quote!(::hot_reload::ReadWrite),
+            Writer::Owner => {
+                quote!(<S as ::hot_reload::AccessTo<::hot_reload::OwnerSide>>::Access)
+            }
+            Writer::Reloadable => {
+                quote!(<S as ::hot_reload::AccessTo<::hot_reload::ReloadableSide>>::Access)
+            }
+        };
+        let handle = match self.kind {
+            Kind::Value => quote!(Box<::hot_reload::SharedMemorySimple<#ty, #access>>),
+            Kind::Slice { .. } => quote!(Box<::hot_reload::SharedMemorySlice<#ty, #access>>),
+            Kind::Channel { .. } => quote!(Box<::hot_reload::SharedChannel<#ty>>),
+            Kind::Queue { .. } => quote!(Box<::hot_reload::SharedQueue<#ty>>),
+            Kind::Ring { .. } => quote!(Box<::hot_reload::SharedRing<#ty>>),
+            Kind::Pool { .. } => quote!(Box<::hot_reload::SharedPool<#ty>>),
+            Kind::Broadcast { .. } => quote!(Box<::hot_reload::SharedBroadcast<#ty>>),
+            Kind::Rpc { ref response } => quote!(Box<::hot_reload::SharedRpc<#ty, #response>>),
+            Kind::Service => quote!(Box<::hot_reload::SharedService<#ty>>),
+            Kind::Nested => quote!(#ty),
+        };
+        match &self.array {
+            Some(len) => quote!([#handle; #len]),
+            None => handle,
+        }
+    }
+
+    /// Variable holding the length of a slice, the capacity of a channel or the length and
+    /// number of blocks of a pool
+    fn size_variable(&self) -> Ident {
+        format_ident!("{}_size", self.ident, span = Span::mixed_site())
+    }
+
+    /// Evaluates the length of a slice, the capacity of a channel or the blocks of a pool, once
+    /// for the arena and the field
+    fn size(&self) -> Option<TokenStream2> {
+        let size = match &self.kind {
+            Kind::Value | Kind::Rpc { .. } | Kind::Service | Kind::Nested => return None,
+            Kind::Slice { len } => with_arguments(len),
+            Kind::Pool { len, blocks } => {
+                let (variable, len, blocks) = (
+                    self.size_variable(),
+                    with_arguments(len),
+                    with_arguments(blocks),
+                );
+                return Some(quote_hygienic!(let #variable: (usize, usize) = (#len, #blocks);));
+            }
+            Kind::Channel { capacity: None }
+            | Kind::Queue { capacity: None }
+            | Kind::Ring { capacity: None }
+            | Kind::Broadcast { capacity: None } => quote_hygienic!(1),
+            Kind::Channel {
+                capacity: Some(capacity),
+            }
+            | Kind::Queue {
+                capacity: Some(capacity),
+            }
+            | Kind::Ring {
+                capacity: Some(capacity),
+            }
+            | Kind::Broadcast {
+                capacity: Some(capacity),
+            } => with_arguments(capacity),
+        };
+        let variable = self.size_variable();
+        Some(quote_hygienic!(let #variable: usize = #size;))
+    }
+
+    /// Adds the field, or every element of an array, to `fields`
+    fn layout(&self) -> TokenStream2 {
+        let (ty, size) = (&self.ty, self.size_variable());
+        let push = match self.kind {
+            Kind::Value => {
+                quote_hygienic!(fields.push(::hot_reload::ArenaField::value::<#ty>(&name)))
+            }
+            Kind::Slice { .. } => {
+                quote_hygienic!(fields.push(::hot_reload::ArenaField::slice::<#ty>(&name, #size)))
+            }
+            Kind::Channel { .. } => {
+                quote_hygienic!(fields.push(::hot_reload::ArenaField::channel::<#ty>(&name, #size)))
+            }
+            Kind::Queue { .. } => {
+                quote_hygienic!(fields.push(::hot_reload::ArenaField::queue::<#ty>(&name, #size)))
+            }
+            Kind::Ring { .. } => {
+                quote_hygienic!(fields.push(::hot_reload::ArenaField::ring::<#ty>(&name, #size)))
+            }
+            Kind::Pool { .. } => quote_hygienic!(fields.push(::hot_reload::ArenaField::pool::<#ty>(
+                &name, #size.0, #size.1,
+            ))),
+            Kind::Broadcast { .. } => {
+                quote_hygienic!(fields.push(::hot_reload::ArenaField::broadcast::<#ty>(&name, #size)))
+            }
+            Kind::Rpc { ref response } => {
+                quote_hygienic!(fields.extend(::hot_reload::ArenaField::rpc::<#ty, #response>(&name)))
+            }
+            Kind::Service => {
+                quote_hygienic!(fields.extend(::hot_reload::ArenaField::service::<#ty>(&name)))
+            }
+            Kind::Nested => quote_hygienic!(<#ty as ::hot_reload::SharedFields>::layout(
+                &format!("{}/", name),
+                arguments,
+                fields,
+            )),
+        };
+        let name = self.ident.to_string();
+        match &self.array {
+            Some(len) => quote_hygienic! {
+                for index in 0..#len {
+                    let name = format!("{}{}/{}", prefix, #name, index);
+                    #push;
+                }
+            },
+            None => quote_hygienic! {
+                {
+                    let name = format!("{}{}", prefix, #name);
+                    #push;
+                }
+            },
+        }
+    }
+
+    /// Creates or opens the field, or every element of an array
+    fn definition(&self) -> TokenStream2 {
+        let (ty, size) = (&self.ty, self.size_variable());
+        let open = match self.kind {
+            Kind::Value => quote_hygienic!(hot_reload.value_with_access(&name)?),
+            Kind::Slice { .. } => quote_hygienic!(hot_reload.slice_with_access(&name, #size)?),
+            Kind::Channel { .. } => quote_hygienic!(hot_reload.channel(&name, #size)?),
+            Kind::Queue { .. } => quote_hygienic!(hot_reload.queue(&name, #size)?),
+            Kind::Ring { .. } => quote_hygienic!(hot_reload.ring(&name, #size)?),
+            Kind::Pool { .. } => quote_hygienic!(hot_reload.pool(&name, #size.0, #size.1)?),
+            Kind::Broadcast { .. } => quote_hygienic!(hot_reload.broadcast(&name, #size)?),
+            Kind::Rpc { .. } => quote_hygienic!(hot_reload.rpc(&name)?),
+            Kind::Service => quote_hygienic!(hot_reload.service(&name)?),
+            Kind::Nested => quote_hygienic!(<#ty as ::hot_reload::SharedFields>::open(
+                &hot_reload,
+                &format!("{}/", name),
+                arguments,
+            )?),
+        };
+        let name = self.ident.to_string();
+        match &self.array {
+            Some(len) => quote_hygienic! {{
+                let elements = (0..#len)
+                    .map(|index| -> ::hot_reload::Result<_> {
+                        let name = format!("{}{}/{}", prefix, #name, index);
+                        let element = #open;
+                        Ok(element)
+                    })
+                    .collect::<::hot_reload::Result<Vec<_>>>()?;
+                ::std::convert::TryInto::try_into(elements)
+                    .map_err(|_| "unexpected length of an array of fields")?
+            }},
+            None => quote_hygienic! {{
+                let name = format!("{}{}", prefix, #name);
+                #open
+            }},
+        }
+    }
+}
+
+/// Parses `name = expression`
+fn parse_setting(input: ParseStream, name: &str) -> syn::Result<Expr> {
+    let key: Ident = input.parse()?;
+    if key != name {
+        return Err(Error::new(key.span(), format!("expected `{} = ...`", name)));
+    }
+    input.parse::<Token![=]>()?;
+    input.parse()
+}
+
+/// Parses the optional `(capacity = expression)` of a channel, queue, ring or broadcast
+fn parse_capacity(attr: &Attribute) -> syn::Result<Option<Expr>> {
+    if attr.tokens.is_empty() {
+        return Ok(None);
+    }
+    attr.parse_args_with(|input: ParseStream| parse_setting(input, "capacity"))
+        .map(Some)
+}
+
+/// Closures are called with the arguments of the state, other expressions are used as is
+fn with_arguments(expr: &Expr) -> TokenStream2 {
+    match expr {
+        // `macro_rules!` wraps forwarded `$e:expr` fragments in an invisible group
+        Expr::Group(group) => with_arguments(&group.expr),
+        Expr::Closure(_) => quote_hygienic!(::hot_reload::with_arguments(arguments, #expr)),
+        _ => quote_hygienic!(#expr),
+    }
+}
+
+fn expand(args: StateArgs, item: ItemStruct) -> syn::Result<TokenStream2> {
+    let named = match item.fields {
+        Fields::Named(named) => named,
+        fields => {
+            return Err(Error::new_spanned(
+                fields,
+                "the state must be a struct with named fields",
+            ))
+        }
+    };
+    let mut fields = Vec::new();
+    let mut errors: Option<Error> = None;
+    for field in named.named {
+        match Field::parse(field) {
+            Ok(field) => fields.push(field),
+            Err(e) => match errors.as_mut() {
+                Some(errors) => errors.combine(e),
+                None => errors = Some(e),
+            },
+        }
+    }
+    if let Some(errors) = errors {
+        return Err(errors);
+    }
+
+    let StateArgs {
+        reloadable,
+        args,
+        arena,
+    } = args;
+    let (attrs, vis, name) = (&item.attrs, &item.vis, &item.ident);
+    let where_clause = &item.generics.where_clause;
+
+    // The state is generic over the side of the process using it, after its own parameters.
+    // The constructors are only implemented for the owner side, the default, so that they can
+    // be called without naming the side.
+    let mut struct_generics = item.generics.clone();
+    struct_generics.params.push(syn::parse_quote!(
+        S: ::hot_reload::Side = ::hot_reload::OwnerSide
+    ));
+    let mut side_generics = item.generics.clone();
+    side_generics
+        .params
+        .push(syn::parse_quote!(S: ::hot_reload::Side));
+    let (side_impl_generics, side_type_generics, _) = side_generics.split_for_impl();
+    let (impl_generics, _, _) = item.generics.split_for_impl();
+    let params: Vec<TokenStream2> = item
+        .generics
+        .params
+        .iter()
+        .map(|param| match param {
+            GenericParam::Type(param) => param.ident.to_token_stream(),
+            GenericParam::Lifetime(param) => param.lifetime.to_token_stream(),
+            GenericParam::Const(param) => param.ident.to_token_stream(),
+        })
+        .collect();
+
+    let field_declarations = fields.iter().map(|field| {
+        let (attrs, vis, ident, ty) = (&field.attrs, &field.vis, &field.ident, field.handle_type());
+        quote!(#(#attrs)* #vis #ident: #ty)
+    });
+    let sizes: Vec<TokenStream2> = fields.iter().filter_map(Field::size).collect();
+    let layouts = fields.iter().map(Field::layout);
+    let idents: Vec<&Ident> = fields.iter().map(|field| &field.ident).collect();
+    let definitions = fields.iter().map(Field::definition);
+
+    let reloadable = match reloadable {
+        Some(reloadable) => reloadable,
+        None => {
+            return Ok(quote_hygienic! {
+                #(#attrs)*
+                #vis struct #name #struct_generics #where_clause {
+                    side: ::std::marker::PhantomData<S>,
+                    #(#field_declarations,)*
+                }
+
+                impl #side_impl_generics ::hot_reload::SharedFields for #name #side_type_generics
+                #where_clause
+                {
+                    type Arguments = #args;
+
+                    #[allow(unused_variables)]
+                    fn layout(
+                        prefix: &str,
+                        arguments: &#args,
+                        fields: &mut Vec<::hot_reload::ArenaField>,
+                    ) {
+                        #(#sizes)*
+                        #(#layouts)*
+                    }
+
+                    #[allow(unused_variables)]
+                    fn open(
+                        hot_reload: &::hot_reload::HotReload,
+                        prefix: &str,
+                        arguments: &#args,
+                    ) -> ::hot_reload::Result<Self> {
+                        #(#sizes)*
+                        #(let #idents = #definitions;)*
+                        Ok(#name {
+                            side: ::std::marker::PhantomData,
+                            #(#idents,)*
+                        })
+                    }
+                }
+            })
+        }
+    };
+    let use_arena = if arena {
+        quote_hygienic! {
+            let fields = &mut Vec::new();
+            #(#layouts)*
+            hot_reload.use_arena(fields)?;
+        }
+    } else {
+        quote_hygienic!()
+    };
+
+    Ok(quote_hygienic! {
+        #(#attrs)*
+        #vis struct #name #struct_generics #where_clause {
+            hot_reload: ::hot_reload::HotReload,
+            side: ::std::marker::PhantomData<S>,
+            #(#field_declarations,)*
+        }
+
+        impl #side_impl_generics #name #side_type_generics #where_clause {
+            /// The single segment holding all the fields, if the state is laid out in an arena
+            #[allow(dead_code)]
+            pub fn arena(&self) -> Option<&::hot_reload::Arena> {
+                self.hot_reload.arena()
+            }
+
+            #[allow(unused_mut, unused_variables)]
+            fn setup(
+                mut hot_reload: ::hot_reload::HotReload,
+                arguments: &#args,
+            ) -> ::hot_reload::Result<Self> {
+                let prefix = "";
+                #(#sizes)*
+                #use_arena
+                #(let #idents = #definitions;)*
+                Ok(#name {
+                    hot_reload,
+                    side: ::std::marker::PhantomData,
+                    #(#idents,)*
+                })
+            }
+        }
+
+        impl #impl_generics #name<#(#params,)* ::hot_reload::OwnerSide> #where_clause {
+            /// Creates the state and starts the reloadable process, or runs `implementation` in
+            /// a thread when hot reloading isn't enabled
+            pub fn owner<F>(arguments: #args, implementation: F) -> ::hot_reload::Result<Self>
+            where
+                F: FnOnce(),
+                F: Send + 'static,
+            {
+                Self::start(
+                    ::hot_reload::HotReload::new(::hot_reload::Process::Owner),
+                    arguments,
+                    implementation,
+                )
+            }
+
+            /// Same as `owner()`, but the state is kept in files inside `dir` and restored from
+            /// them when the owner starts again. Only values and slices are restored, channels
+            /// and the like start out empty.
+            #[allow(dead_code)]
+            pub fn persistent_owner<F, P>(
+                arguments: #args,
+                dir: P,
+                implementation: F,
+            ) -> ::hot_reload::Result<Self>
+            where
+                F: FnOnce(),
+                F: Send + 'static,
+                P: AsRef<::std::path::Path>,
+            {
+                Self::start(
+                    ::hot_reload::HotReload::persistent(::hot_reload::Process::Owner, dir)?,
+                    arguments,
+                    implementation,
+                )
+            }
+
+            #[allow(unused_variables)]
+            fn start<F>(
+                hot_reload: ::hot_reload::HotReload,
+                arguments: #args,
+                implementation: F,
+            ) -> ::hot_reload::Result<Self>
+            where
+                F: FnOnce(),
+                F: Send + 'static,
+            {
+                let mut state = Self::setup(hot_reload, &arguments)?;
+                #[cfg(feature = "enabled")]
+                state.hot_reload.start(#reloadable, &arguments)?;
+                #[cfg(not(feature = "enabled"))]
+                state.hot_reload.skip(implementation, &arguments)?;
+                Ok(state)
+            }
+
+            /// Opens the state created by the owner, from the reloadable process, and tells
+            /// the `reloads()` of the owner that it started
+            pub fn reloadable(
+            ) -> ::hot_reload::Result<#name<#(#params,)* ::hot_reload::ReloadableSide>> {
+                let state = #name::setup(
+                    ::hot_reload::HotReload::new(::hot_reload::Process::Reloadable),
+                    &Self::args()?,
+                )?;
+                state.hot_reload.announce_start()?;
+                Ok(state)
+            }
+
+            /// The arguments the owner passed to the reloadable process
+            pub fn args() -> ::hot_reload::Result<#args> {
+                ::hot_reload::reloadable_process_args()
+            }
+        }
+
+    })
+}
@@ -0,0 +1,177 @@
+//! `#[hot_reload::service]`, calling the methods of a trait through shared memory.
+
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::spanned::Spanned;
+use syn::{Error, FnArg, Ident, ItemTrait, Pat, ReturnType, TraitItem, Type};
+
+struct Method {
+    ident: Ident,
+    /// Name of the variants of the requests and responses
+    variant: Ident,
+    /// Doc comments, kept on the method of the client
+    docs: Vec<syn::Attribute>,
+    args: Vec<(Ident, Type)>,
+    output: Type,
+}
+
+impl Method {
+    fn parse(item: &TraitItem) -> syn::Result<Self> {
+        let method = match item {
+            TraitItem::Method(method) => method,
+            _ => return Err(Error::new_spanned(item, "a service only has methods")),
+        };
+        let sig = &method.sig;
+        if !sig.generics.params.is_empty() {
+            return Err(Error::new_spanned(
+                &sig.generics,
+                "the methods of a service can't be generic",
+            ));
+        }
+        let mut inputs = sig.inputs.iter();
+        match inputs.next() {
+            Some(FnArg::Receiver(receiver)) if receiver.reference.is_some() => {}
+            _ => {
+                return Err(Error::new(
+                    sig.span(),
+                    "the methods of a service take `&self` or `&mut self`",
+                ))
+            }
+        }
+        let args = inputs
+            .map(|input| match input {
+                FnArg::Typed(arg) => match &*arg.pat {
+                    Pat::Ident(pat) => Ok((pat.ident.clone(), (*arg.ty).clone())),
+                    pat => Err(Error::new_spanned(pat, "expected the name of the argument")),
+                },
+                FnArg::Receiver(receiver) => Err(Error::new_spanned(receiver, "unexpected self")),
+            })
+            .collect::<syn::Result<_>>()?;
+        let output = match &sig.output {
+            ReturnType::Default => syn::parse_quote!(()),
+            ReturnType::Type(_, ty) => (**ty).clone(),
+        };
+        Ok(Method {
+            ident: sig.ident.clone(),
+            variant: Ident::new(&camel_case(&sig.ident.to_string()), sig.ident.span()),
+            docs: method
+                .attrs
+                .iter()
+                .filter(|attr| attr.path.is_ident("doc"))
+                .cloned()
+                .collect(),
+            args,
+            output,
+        })
+    }
+}
+
+/// `on_click` becomes `OnClick`
+fn camel_case(name: &str) -> String {
+    name.split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+pub(crate) fn expand(item: ItemTrait) -> syn::Result<TokenStream2> {
+    if !item.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &item.generics,
+            "a service can't be generic",
+        ));
+    }
+    let mut methods = Vec::new();
+    let mut errors: Option<Error> = None;
+    for trait_item in &item.items {
+        match Method::parse(trait_item) {
+            Ok(method) => methods.push(method),
+            Err(e) => match errors.as_mut() {
+                Some(errors) => errors.combine(e),
+                None => errors = Some(e),
+            },
+        }
+    }
+    if let Some(errors) = errors {
+        return Err(errors);
+    }
+
+    let (vis, name) = (&item.vis, &item.ident);
+    let request = format_ident!("{}Request", name);
+    let response = format_ident!("{}Response", name);
+    let client = format_ident!("{}Client", name);
+    let request_doc = format!("A call of a method of `{}` through shared memory", name);
+    let response_doc = format!("What a method of `{}` returned", name);
+    let client_doc = format!(
+        "Calls the methods of `{}` in the process serving them, `None` when it didn't answer in \
+         time",
+        name
+    );
+
+    let idents: Vec<&Ident> = methods.iter().map(|method| &method.ident).collect();
+    let variants: Vec<&Ident> = methods.iter().map(|method| &method.variant).collect();
+    let outputs: Vec<&Type> = methods.iter().map(|method| &method.output).collect();
+    let docs: Vec<&Vec<syn::Attribute>> = methods.iter().map(|method| &method.docs).collect();
+    let arg_names: Vec<Vec<&Ident>> = methods
+        .iter()
+        .map(|method| method.args.iter().map(|(ident, _)| ident).collect())
+        .collect();
+    let arg_types: Vec<Vec<&Type>> = methods
+        .iter()
+        .map(|method| method.args.iter().map(|(_, ty)| ty).collect())
+        .collect();
+
+    Ok(quote! {
+        #item
+
+        #[doc = #request_doc]
+        #[derive(Clone, Copy)]
+        #vis enum #request {
+            #(#variants { #(#arg_names: #arg_types,)* },)*
+        }
+
+        #[doc = #response_doc]
+        #[derive(Clone, Copy)]
+        #vis enum #response {
+            #(#variants(#outputs),)*
+        }
+
+        impl ::hot_reload::Service for dyn #name {
+            type Request = #request;
+            type Response = #response;
+
+            fn dispatch(&mut self, request: &#request) -> #response {
+                match *request {
+                    #(#request::#variants { #(#arg_names,)* } => {
+                        #response::#variants(self.#idents(#(#arg_names),*))
+                    })*
+                }
+            }
+        }
+
+        #[doc = #client_doc]
+        #vis trait #client {
+            #(
+                #(#docs)*
+                fn #idents(&mut self, #(#arg_names: #arg_types),*) -> Option<#outputs>;
+            )*
+        }
+
+        impl #client for ::hot_reload::SharedService<dyn #name> {
+            #(
+                #[allow(unreachable_patterns)]
+                fn #idents(&mut self, #(#arg_names: #arg_types),*) -> Option<#outputs> {
+                    match self.call(&#request::#variants { #(#arg_names,)* })? {
+                        #response::#variants(value) => Some(value),
+                        _ => None,
+                    }
+                }
+            )*
+        }
+    })
+}
@@ -0,0 +1,29 @@
+use hot_reload::simple_shared_memory::*;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+// Unlike with `shared_channel` (see `channels.rs`), every subscriber waiting on a broadcast
+// receives each value.
+fn main() -> Result<()> {
+  let mut broadcast = shared_broadcast::<u32>(true, "example-broadcast", 4)?;
+
+  let subscribers: Vec<_> = (0..2)
+    .map(|i| {
+      std::thread::spawn(move || {
+        let mut broadcast = shared_broadcast::<u32>(false, "example-broadcast", 4).unwrap();
+        println!("\tSubscriber {} waiting", i);
+        for _ in 0..3 {
+          println!("\tSubscriber {} received {}", i, broadcast.recv());
+        }
+      })
+    })
+    .collect();
+  std::thread::sleep(std::time::Duration::from_secs(1));
+  for value in 1..=3 {
+    broadcast.send(&value);
+  }
+  for subscriber in subscribers {
+    subscriber.join().unwrap();
+  }
+  Ok(())
+}
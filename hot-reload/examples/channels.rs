@@ -9,17 +9,17 @@ type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 // how to share the mutex - although that might not be possible
 // across processes
 fn main() -> Result<()> {
-  let mut channel = shared_channel::<u32>(true, "example")?;
+  let mut channel = shared_channel::<u32>(true, "example", 1)?;
 
   let child = std::thread::spawn(move || {
-    let mut channel = shared_channel::<u32>(false, "example").unwrap();
+    let mut channel = shared_channel::<u32>(false, "example", 1).unwrap();
     println!("\tWaiting for event to be signaled !");
     let _ = channel.recv();
     println!("\tSignaled !");
   });
 
   let child2 = std::thread::spawn(move || {
-    let mut channel = shared_channel::<u32>(false, "example").unwrap();
+    let mut channel = shared_channel::<u32>(false, "example", 1).unwrap();
     println!("\tWaiting for event to be signaled !");
     let _ = channel.recv();
     println!("\tSignaled !");
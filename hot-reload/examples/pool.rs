@@ -0,0 +1,34 @@
+use hot_reload::simple_shared_memory::*;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+// A thread renders frames straight into the blocks of a pool and only sends which block holds
+// each frame, the other thread reads the frame in place and frees the block.
+fn main() -> Result<()> {
+  const WIDTH: usize = 640;
+  const HEIGHT: usize = 480;
+  let pool = shared_pool::<u32>(true, "example-pool", WIDTH * HEIGHT, 3)?;
+  let mut frames = shared_channel::<PoolBlock>(true, "example-pool-frames", 3)?;
+
+  let receiver = std::thread::spawn(|| {
+    let pool = shared_pool::<u32>(false, "example-pool", WIDTH * HEIGHT, 3).unwrap();
+    let mut frames = shared_channel::<PoolBlock>(false, "example-pool-frames", 3).unwrap();
+    for _ in 0..10 {
+      let frame = pool.get(frames.recv()).unwrap();
+      println!("Frame of {} pixels, the first one {:08X}", frame.len(), frame[0]);
+    }
+  });
+
+  for i in 0..10 {
+    // Waits for the receiver to free a block when all three hold frames
+    let mut frame = pool
+      .alloc_timeout(std::time::Duration::from_secs(1))
+      .ok_or("The receiver didn't free a block in time")?;
+    for pixel in frame.iter_mut() {
+      *pixel = 0xFF00_0000 | (i * 0x10_1010);
+    }
+    frames.send(&frame.share(WIDTH * HEIGHT));
+  }
+  receiver.join().unwrap();
+  Ok(())
+}
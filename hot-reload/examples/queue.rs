@@ -0,0 +1,32 @@
+use hot_reload::simple_shared_memory::*;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+// Several threads send to the same queue at once, e.g. an input and a network thread of the
+// owner, while another thread receives everything they sent.
+fn main() -> Result<()> {
+  let queue = shared_queue::<(u8, u32)>(true, "example-queue", 16)?;
+
+  let receiver = std::thread::spawn(|| {
+    let mut queue = shared_queue::<(u8, u32)>(false, "example-queue", 16).unwrap();
+    let mut received = [0; 4];
+    for _ in 0..4 * 1000 {
+      let (sender, _) = queue.recv();
+      received[sender as usize] += 1;
+    }
+    println!("Received from each sender: {:?}", received);
+  });
+
+  std::thread::scope(|scope| {
+    for sender in 0..4 {
+      let queue = &queue;
+      scope.spawn(move || {
+        for i in 0..1000 {
+          queue.send(&(sender, i));
+        }
+      });
+    }
+  });
+  receiver.join().unwrap();
+  Ok(())
+}
@@ -0,0 +1,26 @@
+use hot_reload::simple_shared_memory::*;
+use std::time::Instant;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+// A thread streams a million samples to another one through a ring, without any lock.
+fn main() -> Result<()> {
+  let mut ring = shared_ring::<f32>(true, "example-ring", 1024)?;
+
+  let receiver = std::thread::spawn(|| {
+    let mut ring = shared_ring::<f32>(false, "example-ring", 1024).unwrap();
+    let mut sum = 0.0;
+    for _ in 0..1_000_000 {
+      sum += f64::from(ring.recv());
+    }
+    println!("Average sample: {}", sum / 1_000_000.0);
+  });
+
+  let start = Instant::now();
+  for i in 0..1_000_000 {
+    ring.send(&(i as f32 / 1_000_000.0));
+  }
+  receiver.join().unwrap();
+  println!("Streamed in {:?}", start.elapsed());
+  Ok(())
+}
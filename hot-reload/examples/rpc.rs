@@ -0,0 +1,26 @@
+use hot_reload::simple_shared_memory::*;
+use std::time::Duration;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+// The owner asks another thread to square numbers. The first answer comes too late and is
+// discarded when the second call waits for its own answer.
+fn main() -> Result<()> {
+  let mut rpc = shared_rpc::<u32, u64>(true, "example-rpc")?;
+
+  let server = std::thread::spawn(move || {
+    let mut rpc = shared_rpc::<u32, u64>(false, "example-rpc").unwrap();
+    for delay in &[200, 0] {
+      rpc.serve(|x| {
+        std::thread::sleep(Duration::from_millis(*delay));
+        u64::from(*x) * u64::from(*x)
+      });
+    }
+  });
+
+  println!("3² = {:?}", rpc.call(&3, Duration::from_millis(50)));
+  std::thread::sleep(Duration::from_millis(300));
+  println!("4² = {:?}", rpc.call(&4, Duration::from_secs(1)));
+  server.join().unwrap();
+  Ok(())
+}
@@ -0,0 +1,49 @@
+use hot_reload::select::Select;
+use hot_reload::{HotReload, Process};
+use std::time::Duration;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+// The reloadable side waits for clicks and key presses at once, and ticks when neither comes for
+// a while. The owner waits for the reloadable side to start before sending.
+fn main() -> Result<()> {
+  let mut hot_reload = HotReload::new(Process::Owner);
+  let mut clicks = hot_reload.channel::<(u32, u32)>("clicks", 4)?;
+  let mut keys = hot_reload.channel::<char>("keys", 4)?;
+  let mut reloads = hot_reload.reloads()?;
+
+  hot_reload.skip(
+    || {
+      let hot_reload = HotReload::new(Process::Reloadable);
+      let mut clicks = hot_reload.channel::<(u32, u32)>("clicks", 4).unwrap();
+      let mut keys = hot_reload.channel::<char>("keys", 4).unwrap();
+      hot_reload.announce_start().unwrap();
+      let mut ticks = 0;
+      while ticks < 3 {
+        let mut select = Select::new();
+        let click = select.recv(&*clicks).unwrap();
+        let key = select.recv(&*keys).unwrap();
+        match select.ready_timeout(Duration::from_millis(100)) {
+          Some(ready) if ready == click => println!("Click at {:?}", clicks.try_recv().unwrap()),
+          Some(ready) if ready == key => println!("Key {:?}", keys.try_recv().unwrap()),
+          _ => {
+            ticks += 1;
+            println!("Tick {}", ticks);
+          }
+        }
+      }
+    },
+    &(),
+  )?;
+
+  let mut select = Select::new();
+  select.recv(&reloads)?;
+  select.ready();
+  println!("Reloadable started {:?} time(s)", reloads.try_recv());
+  clicks.send(&(10, 20));
+  std::thread::sleep(Duration::from_millis(50));
+  keys.send(&'a');
+  keys.send(&'b');
+  std::thread::sleep(Duration::from_millis(500));
+  Ok(())
+}
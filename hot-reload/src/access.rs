@@ -0,0 +1,49 @@
+//! Which process may write to a piece of shared state.
+//!
+//! Fields of a `#[hot_reload::state]` can be annotated with the side allowed to write
+//! them. The other side then maps the memory read only and gets a handle that only
+//! hands out shared references.
+
+/// Type level marker for the access a process has to a shared memory
+pub trait Access {
+    const WRITABLE: bool;
+}
+
+/// The process can read and write the memory
+pub struct ReadWrite;
+impl Access for ReadWrite {
+    const WRITABLE: bool = true;
+}
+
+/// The process can only read the memory, it is mapped without write permission
+pub struct ReadOnly;
+impl Access for ReadOnly {
+    const WRITABLE: bool = false;
+}
+
+/// The process that created the shared state
+pub struct OwnerSide;
+/// The process that is reloaded whenever its code changes
+pub struct ReloadableSide;
+
+/// Access a process on side `Self` gets to memory written by side `W`
+pub trait AccessTo<W> {
+    type Access: Access;
+}
+impl AccessTo<OwnerSide> for OwnerSide {
+    type Access = ReadWrite;
+}
+impl AccessTo<ReloadableSide> for OwnerSide {
+    type Access = ReadOnly;
+}
+impl AccessTo<OwnerSide> for ReloadableSide {
+    type Access = ReadOnly;
+}
+impl AccessTo<ReloadableSide> for ReloadableSide {
+    type Access = ReadWrite;
+}
+
+/// One of the two processes sharing a `#[hot_reload::state]`
+pub trait Side: AccessTo<OwnerSide> + AccessTo<ReloadableSide> {}
+impl Side for OwnerSide {}
+impl Side for ReloadableSide {}
@@ -0,0 +1,431 @@
+//! All the fields of a `#[hot_reload::state]` laid out in a single shared memory segment.
+//!
+//! The segment starts with a table of contents listing every field with its offset,
+//! size and a fingerprint of its type, followed by the fields themselves. The process
+//! that doesn't own the arena finds its fields through the table, which also catches
+//! fields whose type changed in only one of the processes.
+
+#[cfg(target_family = "unix")]
+use crate::simple_shared_memory::broadcast_size;
+use crate::simple_shared_memory::{
+    channel_size, get_shared_memory, pool_size, queue_size, ring_size, RpcMessage,
+};
+use crate::{Result, Service};
+use shared_memory::*;
+use std::rc::Rc;
+
+/// Identifies the table of contents of an arena ("HRAR")
+const TOC_MAGIC: u32 = 0x4852_4152;
+
+/// Longest field name kept in the table of contents, longer names are truncated
+const TOC_NAME_LEN: usize = 48;
+
+/// Every field starts on its own cache line
+const FIELD_ALIGN: usize = 64;
+
+/// Low bits of a segment tag holding the kind of the field, the other bits hold the fingerprint
+/// of its type. There is room for many more kinds, so that adding one doesn't change the tags of
+/// existing segments.
+const KIND_BITS: u32 = 8;
+const KIND_MASK: u64 = (1 << KIND_BITS) - 1;
+
+/// Tag of a segment holding an arena, low bits that no kind of field uses (see `segment_tag()`)
+pub(crate) const ARENA_TAG: u64 = KIND_MASK;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct TocHeader {
+    magic: u32,
+    count: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct TocEntry {
+    name: [u8; TOC_NAME_LEN],
+    /// Identifies the field, its name can be truncated
+    name_hash: u64,
+    kind: u32,
+    _reserved: u32,
+    offset: u64,
+    size: u64,
+    fingerprint: u64,
+}
+
+/// What a field of the arena holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArenaFieldKind {
+    Value,
+    Slice,
+    Channel,
+    Broadcast,
+    Queue,
+    Ring,
+    Pool,
+}
+
+impl ArenaFieldKind {
+    pub(crate) fn from_u32(kind: u32) -> Option<Self> {
+        match kind {
+            0 => Some(ArenaFieldKind::Value),
+            1 => Some(ArenaFieldKind::Slice),
+            2 => Some(ArenaFieldKind::Channel),
+            3 => Some(ArenaFieldKind::Broadcast),
+            4 => Some(ArenaFieldKind::Queue),
+            5 => Some(ArenaFieldKind::Ring),
+            6 => Some(ArenaFieldKind::Pool),
+            _ => None,
+        }
+    }
+}
+
+/// A field to lay out in the arena
+pub struct ArenaField {
+    name: String,
+    kind: ArenaFieldKind,
+    size: usize,
+    fingerprint: u64,
+}
+
+impl ArenaField {
+    pub fn value<T>(name: &str) -> Self {
+        Self::new::<T>(name, ArenaFieldKind::Value, std::mem::size_of::<T>())
+    }
+
+    pub fn slice<T>(name: &str, length: usize) -> Self {
+        Self::new::<T>(
+            name,
+            ArenaFieldKind::Slice,
+            std::mem::size_of::<T>() * length,
+        )
+    }
+
+    pub fn channel<T>(name: &str, capacity: usize) -> Self {
+        Self::new::<T>(name, ArenaFieldKind::Channel, channel_size::<T>(capacity))
+    }
+
+    #[cfg(target_family = "unix")]
+    pub fn broadcast<T>(name: &str, capacity: usize) -> Self {
+        Self::new::<T>(
+            name,
+            ArenaFieldKind::Broadcast,
+            broadcast_size::<T>(capacity),
+        )
+    }
+
+    pub fn queue<T>(name: &str, capacity: usize) -> Self {
+        Self::new::<T>(name, ArenaFieldKind::Queue, queue_size::<T>(capacity))
+    }
+
+    pub fn ring<T>(name: &str, capacity: usize) -> Self {
+        Self::new::<T>(name, ArenaFieldKind::Ring, ring_size::<T>(capacity))
+    }
+
+    pub fn pool<T>(name: &str, block_len: usize, blocks: usize) -> Self {
+        Self::new::<T>(
+            name,
+            ArenaFieldKind::Pool,
+            pool_size::<T>(block_len, blocks),
+        )
+    }
+
+    /// The channels of requests and responses of `HotReload::rpc()`
+    pub fn rpc<Req, Resp>(name: &str) -> [Self; 2] {
+        [
+            Self::channel::<RpcMessage<Req>>(&format!("{}/requests", name), 1),
+            Self::channel::<RpcMessage<Resp>>(&format!("{}/responses", name), 1),
+        ]
+    }
+
+    /// The channels of `HotReload::service()`
+    pub fn service<T: ?Sized + Service>(name: &str) -> [Self; 2] {
+        Self::rpc::<T::Request, T::Response>(name)
+    }
+
+    fn new<T>(name: &str, kind: ArenaFieldKind, size: usize) -> Self {
+        ArenaField {
+            name: String::from(name),
+            kind,
+            size,
+            fingerprint: fingerprint::<T>(),
+        }
+    }
+}
+
+/// A field as recorded in the table of contents of an arena
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArenaEntry {
+    /// Name of the field, truncated to 48 bytes
+    pub name: String,
+    pub kind: ArenaFieldKind,
+    /// Offset of the field from the start of the arena
+    pub offset: usize,
+    pub size: usize,
+    /// Hash of the name, size and alignment of the type of the field
+    pub fingerprint: u64,
+    name_hash: u64,
+}
+
+/// A single shared memory segment holding all the fields of a state
+pub struct Arena {
+    memory: Rc<Shmem>,
+    entries: Vec<ArenaEntry>,
+}
+
+impl Arena {
+    /// Creates the arena and lays out `fields` in it. When the arena is restored from a file
+    /// that was written for other fields, the restored data is discarded.
+    pub fn create(identifier: &str, fields: &[ArenaField]) -> Result<Self> {
+        let mut entries = Vec::with_capacity(fields.len());
+        let mut offset = toc_size(fields.len());
+        for field in fields {
+            entries.push(ArenaEntry {
+                name: truncated_name(&field.name),
+                kind: field.kind,
+                offset,
+                size: field.size,
+                fingerprint: field.fingerprint,
+                name_hash: stable_hash(field.name.as_bytes()),
+            });
+            offset += round_up(field.size.max(1), FIELD_ALIGN);
+        }
+
+        let memory = get_shared_memory(true, identifier, offset, true, ARENA_TAG)?;
+        let bytes = unsafe { std::slice::from_raw_parts_mut(memory.as_ptr(), memory.len()) };
+        if memory.is_restored() && read_toc(bytes).ok().as_ref() != Some(&entries) {
+            for byte in bytes.iter_mut() {
+                *byte = 0;
+            }
+        }
+        write_toc(bytes, &entries);
+        Ok(Arena {
+            memory: Rc::new(memory),
+            entries,
+        })
+    }
+
+    /// Opens the arena created by the owner
+    pub fn open(identifier: &str) -> Result<Self> {
+        let memory = get_shared_memory(false, identifier, 0, true, ARENA_TAG)?;
+        let entries = read_toc(unsafe { memory.as_slice() })?;
+        Ok(Arena {
+            memory: Rc::new(memory),
+            entries,
+        })
+    }
+
+    /// Returns the table of contents of the arena
+    pub fn entries(&self) -> &[ArenaEntry] {
+        &self.entries
+    }
+
+    /// Copies the whole arena, table of contents included. Channels, broadcasts, queues, rings
+    /// and pools are copied too, but are never restored.
+    pub fn snapshot(&self) -> Vec<u8> {
+        unsafe { self.memory.as_slice() }.to_vec()
+    }
+
+    /// Copies back the values and slices of a snapshot whose name, type and size still match
+    /// a field of the arena. Returns how many fields were restored.
+    pub fn restore(&self, snapshot: &[u8]) -> Result<usize> {
+        // A corrupt snapshot is refused before anything is copied
+        let saved_entries = read_toc(snapshot)?;
+        let mut restored = 0;
+        for saved in saved_entries {
+            let is_synchronized = matches!(
+                saved.kind,
+                ArenaFieldKind::Channel
+                    | ArenaFieldKind::Broadcast
+                    | ArenaFieldKind::Queue
+                    | ArenaFieldKind::Ring
+                    | ArenaFieldKind::Pool
+            );
+            if is_synchronized {
+                continue;
+            }
+            let entry = match self.entries.iter().find(|entry| {
+                entry.name_hash == saved.name_hash
+                    && entry.fingerprint == saved.fingerprint
+                    && entry.size == saved.size
+            }) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    snapshot.as_ptr().add(saved.offset),
+                    self.memory.as_ptr().add(entry.offset),
+                    entry.size,
+                );
+            }
+            restored += 1;
+        }
+        Ok(restored)
+    }
+
+    /// Finds the memory of a field, checking that both processes agree on its type and size
+    pub(crate) fn region(
+        &self,
+        name: &str,
+        kind: ArenaFieldKind,
+        size: usize,
+        fingerprint: u64,
+    ) -> Result<ArenaRegion> {
+        let name_hash = stable_hash(name.as_bytes());
+        let entry = self
+            .entries
+            .iter()
+            .find(|entry| entry.name_hash == name_hash)
+            .ok_or_else(|| format!("There is no field {} in the arena", name))?;
+        if entry.kind != kind || entry.fingerprint != fingerprint || entry.size != size {
+            return Err(format!(
+                "The field {} has another type or size in the other process",
+                name
+            )
+            .into());
+        }
+        Ok(ArenaRegion {
+            arena: Rc::clone(&self.memory),
+            offset: entry.offset,
+            size,
+        })
+    }
+}
+
+/// The part of an arena holding one field, keeps the arena mapped
+pub(crate) struct ArenaRegion {
+    arena: Rc<Shmem>,
+    offset: usize,
+    size: usize,
+}
+
+impl ArenaRegion {
+    pub(crate) fn as_ptr(&self) -> *mut u8 {
+        unsafe { self.arena.as_ptr().add(self.offset) }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.size
+    }
+
+    pub(crate) fn is_owner(&self) -> bool {
+        self.arena.is_owner()
+    }
+
+    pub(crate) fn memory_outcome(&self) -> MemoryOutcome {
+        self.arena.memory_outcome()
+    }
+}
+
+/// Identifies the type of a field, to catch processes built with different definitions of it.
+/// Only uses 56 bits, so that it fits in a segment tag along with the kind of the field.
+pub(crate) fn fingerprint<T>() -> u64 {
+    let type_name = std::any::type_name::<T>();
+    stable_hash(
+        format!(
+            "{}:{}:{}",
+            type_name,
+            std::mem::size_of::<T>(),
+            std::mem::align_of::<T>()
+        )
+        .as_bytes(),
+    ) >> KIND_BITS
+}
+
+/// Describes a segment holding a single field, so that the other process can check it expects
+/// the same content and tools can find out what the segment holds
+pub(crate) fn segment_tag(kind: ArenaFieldKind, fingerprint: u64) -> u64 {
+    fingerprint << KIND_BITS | (kind as u64 + 1)
+}
+
+/// Kind and fingerprint of the field in a segment, `None` for arenas and untagged segments
+pub(crate) fn parse_segment_tag(tag: u64) -> Option<(ArenaFieldKind, u64)> {
+    let kind = ArenaFieldKind::from_u32(((tag & KIND_MASK) as u32).checked_sub(1)?)?;
+    Some((kind, tag >> KIND_BITS))
+}
+
+fn round_up(size: usize, align: usize) -> usize {
+    size.div_ceil(align) * align
+}
+
+fn toc_size(count: usize) -> usize {
+    round_up(
+        std::mem::size_of::<TocHeader>() + count * std::mem::size_of::<TocEntry>(),
+        FIELD_ALIGN,
+    )
+}
+
+fn truncated_name(name: &str) -> String {
+    let mut len = name.len().min(TOC_NAME_LEN);
+    while !name.is_char_boundary(len) {
+        len -= 1;
+    }
+    String::from(&name[..len])
+}
+
+fn write_toc(bytes: &mut [u8], entries: &[ArenaEntry]) {
+    let header = TocHeader {
+        magic: TOC_MAGIC,
+        count: entries.len() as u32,
+    };
+    unsafe { (bytes.as_mut_ptr() as *mut TocHeader).write_unaligned(header) };
+    for (i, entry) in entries.iter().enumerate() {
+        let mut name = [0u8; TOC_NAME_LEN];
+        name[..entry.name.len()].copy_from_slice(entry.name.as_bytes());
+        let toc_entry = TocEntry {
+            name,
+            name_hash: entry.name_hash,
+            kind: entry.kind as u32,
+            _reserved: 0,
+            offset: entry.offset as u64,
+            size: entry.size as u64,
+            fingerprint: entry.fingerprint,
+        };
+        unsafe { (entry_ptr(bytes.as_ptr(), i) as *mut TocEntry).write_unaligned(toc_entry) };
+    }
+}
+
+pub(crate) fn read_toc(bytes: &[u8]) -> Result<Vec<ArenaEntry>> {
+    let invalid = "The shared memory doesn't hold an arena";
+    if bytes.len() < std::mem::size_of::<TocHeader>() {
+        return Err(invalid.into());
+    }
+    let header = unsafe { (bytes.as_ptr() as *const TocHeader).read_unaligned() };
+    if header.magic != TOC_MAGIC || bytes.len() < toc_size(header.count as usize) {
+        return Err(invalid.into());
+    }
+    let entries = (0..header.count as usize)
+        .map(|i| {
+            let entry =
+                unsafe { (entry_ptr(bytes.as_ptr(), i) as *const TocEntry).read_unaligned() };
+            let name_len = entry
+                .name
+                .iter()
+                .position(|b| *b == 0)
+                .unwrap_or(TOC_NAME_LEN);
+            Ok(ArenaEntry {
+                name: String::from_utf8_lossy(&entry.name[..name_len]).into_owned(),
+                kind: ArenaFieldKind::from_u32(entry.kind).ok_or(invalid)?,
+                offset: entry.offset as usize,
+                size: entry.size as usize,
+                fingerprint: entry.fingerprint,
+                name_hash: entry.name_hash,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    // Fields are only accessed through the table of contents, it has to stay within the memory
+    let is_corrupt = entries.iter().any(|entry| {
+        entry
+            .offset
+            .checked_add(entry.size)
+            .is_none_or(|end| end > bytes.len())
+    });
+    if is_corrupt {
+        return Err("The arena has fields past its end".into());
+    }
+    Ok(entries)
+}
+
+fn entry_ptr(toc: *const u8, index: usize) -> *const u8 {
+    unsafe { toc.add(std::mem::size_of::<TocHeader>() + index * std::mem::size_of::<TocEntry>()) }
+}
@@ -0,0 +1,35 @@
+//! Removes the shared memory left behind by hot-reload owners that were killed.
+//!
+//! Usage: hot-reload-gc [--dry-run]
+
+fn main() -> hot_reload::Result<()> {
+    let dry_run = match std::env::args().nth(1).as_deref() {
+        None => false,
+        Some("--dry-run") => true,
+        Some(arg) => return Err(format!("Unexpected argument {}", arg).into()),
+    };
+
+    let segments = if dry_run {
+        hot_reload::gc::find_stale_segments()?
+    } else {
+        hot_reload::gc::collect_stale_segments()?
+    };
+    for segment in &segments {
+        println!(
+            "{} {} {}({} bytes, owner {} is gone)",
+            if dry_run { "Would remove" } else { "Removed" },
+            segment.os_id,
+            segment
+                .name
+                .as_ref()
+                .map(|name| format!("\"{}\" ", name))
+                .unwrap_or_default(),
+            segment.size,
+            segment.owner_pid
+        );
+    }
+    if segments.is_empty() {
+        println!("No stale shared memory found");
+    }
+    Ok(())
+}
@@ -0,0 +1,12 @@
+//! Shows what the shared memory of running hot-reload processes holds.
+//!
+//! Usage: hot-reload-inspect [--hex] [--values] [PREFIX]
+//!
+//! Lists the segments whose identifier starts with PREFIX and the header of each field: the
+//! fingerprint of its type, its size and for channels the state of their lock and event and
+//! how many values are pending. `--hex` dumps the fields, `--values` pretty prints those of
+//! primitive types and dumps the others.
+
+fn main() -> hot_reload::Result<()> {
+    hot_reload::inspect::run(&hot_reload::inspect::TypeRegistry::new())
+}
@@ -0,0 +1,117 @@
+//! Cleanup of shared memory left behind by owners that didn't exit cleanly.
+//!
+//! The owner unlinks its shared memory when it drops it, which never happens if it
+//! gets killed. Every segment records the process id of its owner, so segments whose
+//! owner isn't running anymore can be found and removed. The segments are named so that
+//! the reloadable can open them, `HotReload` doesn't use memfd mappings, which would
+//! leave nothing behind.
+
+use crate::Result;
+use shared_memory::*;
+
+/// Start of the os_id of every shared memory segment created by `HotReload`
+pub(crate) const SEGMENT_PREFIX: &str = "hr_";
+
+/// A shared memory segment whose owner isn't running anymore
+#[derive(Debug)]
+pub struct StaleSegment {
+    pub os_id: String,
+    /// The identifier the segment was created for, e.g. `1A2B3C4D/buffer`
+    pub name: Option<String>,
+    pub owner_pid: u32,
+    pub size: usize,
+}
+
+/// Lists the segments created by `HotReload` whose owner isn't running anymore.
+///
+/// Only Linux exposes the existing segments (in `/dev/shm`), other platforms never report any.
+pub fn find_stale_segments() -> Result<Vec<StaleSegment>> {
+    Ok(stale_segments()?
+        .into_iter()
+        .map(|(segment, _)| segment)
+        .collect())
+}
+
+/// Unlinks the segments created by `HotReload` whose owner isn't running anymore and
+/// returns them. Processes that still have them open keep their mapping.
+pub fn collect_stale_segments() -> Result<Vec<StaleSegment>> {
+    Ok(stale_segments()?
+        .into_iter()
+        .map(|(segment, mut shmem)| {
+            // Dropping the mapping as its owner unlinks it
+            shmem.set_owner(true);
+            segment
+        })
+        .collect())
+}
+
+fn stale_segments() -> Result<Vec<(StaleSegment, Shmem)>> {
+    let mut stale = Vec::new();
+    for (os_id, shmem) in segments()? {
+        let owner_pid = shmem.owner_pid();
+        if is_running(owner_pid) {
+            continue;
+        }
+        stale.push((
+            StaleSegment {
+                os_id,
+                name: shmem.get_name().map(String::from),
+                owner_pid,
+                size: shmem.len(),
+            },
+            shmem,
+        ));
+    }
+    Ok(stale)
+}
+
+/// Opens every segment created by `HotReload`, with its os_id
+#[cfg(target_os = "linux")]
+pub(crate) fn segments() -> Result<Vec<(String, Shmem)>> {
+    let mut segments = Vec::new();
+    for entry in std::fs::read_dir("/dev/shm")? {
+        let name = entry?.file_name();
+        let name = match name.to_str() {
+            Some(name) if name.starts_with(SEGMENT_PREFIX) => name,
+            _ => continue,
+        };
+        let os_id = format!("/{}", name);
+        // Skip whatever we can't open or wasn't created through shared_memory
+        if let Ok(shmem) = ShmemConf::new().os_id(&os_id).open() {
+            segments.push((os_id, shmem));
+        }
+    }
+    Ok(segments)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn segments() -> Result<Vec<(String, Shmem)>> {
+    Ok(Vec::new())
+}
+
+/// Other platforms can't tell, every process counts as running
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn is_running(_pid: u32) -> bool {
+    true
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn is_running(pid: u32) -> bool {
+    // Signal 0 only checks whether the process exists, EPERM means it belongs to another user
+    let res = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    let exists = res == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM);
+    exists && !is_zombie(pid)
+}
+
+/// Killed processes linger as zombies until their parent reaps them
+#[cfg(target_os = "linux")]
+fn is_zombie(pid: u32) -> bool {
+    // The state follows the parenthesized command name, which can itself contain spaces
+    std::fs::read_to_string(format!("/proc/{}/stat", pid))
+        .ok()
+        .and_then(|stat| {
+            let (_, after_name) = stat.rsplit_once(')')?;
+            after_name.trim_start().chars().next()
+        })
+        == Some('Z')
+}
@@ -0,0 +1,512 @@
+//! Looking into the shared memory of running `HotReload` processes.
+//!
+//! Every segment records what it holds: a single value, slice, channel, broadcast, queue, ring or
+//! pool with the fingerprint of its type, or an arena with its table of contents. Values are hex dumped, or
+//! pretty printed when their type is registered in a `TypeRegistry`. The `hot-reload-inspect`
+//! binary only knows the primitive types, an application can build an inspector knowing its own
+//! types with:
+//!
+//! ```ignore
+//! fn main() -> hot_reload::Result<()> {
+//!     let registry = hot_reload::inspect::TypeRegistry::new().register::<Counter>();
+//!     hot_reload::inspect::run(&registry)
+//! }
+//! ```
+
+use crate::arena::{parse_segment_tag, read_toc, ArenaFieldKind, ARENA_TAG};
+use crate::simple_shared_memory::{
+    broadcast_state, channel_state, pool_state, queue_state, QueueSlot,
+};
+use crate::Result;
+use shared_memory::*;
+use std::collections::HashMap;
+
+/// State of the mutex of a channel when it was inspected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockState {
+    Unlocked,
+    Locked,
+    /// The process holding the lock died, trying the lock released it (not on macOS)
+    OwnerDead,
+}
+
+/// What a channel held when it was inspected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelState {
+    pub lock: LockState,
+    /// The event waking up the receiver is set
+    pub signaled: bool,
+    /// Number of values sent but not received yet
+    pub pending: usize,
+    /// Slot of the oldest pending value
+    pub head: usize,
+    /// Number of slots
+    pub capacity: usize,
+    /// Number of values `send()` and `force_send()` dropped before they were received
+    pub dropped: u64,
+}
+
+/// What a broadcast held when it was inspected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BroadcastState {
+    pub lock: LockState,
+    /// Number of values sent since the broadcast was created
+    pub sent: u64,
+    /// Number of slots, holding the latest values sent
+    pub capacity: usize,
+}
+
+/// What a queue or ring held when it was inspected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueState {
+    /// The receiver waits for the senders to set the event
+    pub receiver_waiting: bool,
+    /// Number of values sent but not received yet, including those still being written
+    pub pending: usize,
+    /// Position of the oldest pending value
+    pub head: usize,
+    /// Number of slots
+    pub capacity: usize,
+    /// Number of values `force_send()` dropped because there was no room for them
+    pub dropped: u64,
+}
+
+/// How much of a pool was allocated when it was inspected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolState {
+    /// Number of blocks allocated, including those held by processes that died
+    pub allocated: usize,
+    pub blocks: usize,
+    /// Number of values in a block
+    pub block_len: usize,
+}
+
+/// A shared memory segment created by `HotReload`
+pub struct Segment {
+    pub os_id: String,
+    /// The identifier the segment was created for, e.g. `1A2B3C4D/buffer`
+    pub name: String,
+    pub owner_pid: u32,
+    pub size: usize,
+    memory: Shmem,
+}
+
+/// A value, slice, channel, broadcast, queue, ring or pool found in a segment
+pub struct Field<'a> {
+    pub name: String,
+    pub kind: ArenaFieldKind,
+    /// Hash of the name, size and alignment of the type of the field (of the elements of
+    /// slices and of the values sent through channels)
+    pub fingerprint: u64,
+    /// Offset of the field from the start of the segment
+    pub offset: usize,
+    pub size: usize,
+    pub channel: Option<ChannelState>,
+    pub broadcast: Option<BroadcastState>,
+    pub queue: Option<QueueState>,
+    pub pool: Option<PoolState>,
+    /// The value, the elements of the slice, the slots of the channel, broadcast, queue or ring
+    /// or the blocks of the pool (followed by padding)
+    pub data: &'a [u8],
+}
+
+/// Lists the segments whose identifier starts with `prefix`.
+///
+/// The segments are mapped writable to take the lock of channels for an instant, only Linux
+/// exposes the existing segments (in `/dev/shm`) and segments backed by files aren't listed.
+pub fn find_segments(prefix: &str) -> Result<Vec<Segment>> {
+    let mut segments: Vec<Segment> = crate::gc::segments()?
+        .into_iter()
+        .filter_map(|(os_id, memory)| {
+            let name = String::from(memory.get_name()?);
+            if !name.starts_with(prefix) {
+                return None;
+            }
+            Some(Segment {
+                os_id,
+                name,
+                owner_pid: memory.owner_pid(),
+                size: memory.len(),
+                memory,
+            })
+        })
+        .collect();
+    segments.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(segments)
+}
+
+impl Segment {
+    /// Returns whether the segment holds the arena of a state
+    pub fn is_arena(&self) -> bool {
+        self.memory.tag() == ARENA_TAG
+    }
+
+    /// Returns the fields held by the segment, none if it wasn't created for a field
+    pub fn fields(&self) -> Result<Vec<Field<'_>>> {
+        let tag = self.memory.tag();
+        if tag == ARENA_TAG {
+            return read_toc(unsafe { self.memory.as_slice() })?
+                .into_iter()
+                .map(|entry| {
+                    self.field(
+                        entry.name,
+                        entry.kind,
+                        entry.fingerprint,
+                        entry.offset,
+                        entry.size,
+                    )
+                })
+                .collect();
+        }
+        Ok(match parse_segment_tag(tag) {
+            Some((kind, fingerprint)) => {
+                // Without the prefix of the owner, e.g. `players/0/input`
+                let name = self
+                    .name
+                    .split_once('/')
+                    .map_or(&*self.name, |(_, name)| name);
+                vec![self.field(String::from(name), kind, fingerprint, 0, self.size)?]
+            }
+            None => Vec::new(),
+        })
+    }
+
+    fn field(
+        &self,
+        name: String,
+        kind: ArenaFieldKind,
+        fingerprint: u64,
+        offset: usize,
+        size: usize,
+    ) -> Result<Field<'_>> {
+        if offset.checked_add(size).is_none_or(|end| end > self.size) {
+            return Err(format!("The field {} lies outside of {}", name, self.name).into());
+        }
+        let start = unsafe { self.memory.as_ptr().add(offset) };
+        let (mut channel, mut broadcast, mut queue, mut pool) = (None, None, None, None);
+        let value_ptr = match kind {
+            ArenaFieldKind::Channel => {
+                let (state, value_ptr) = unsafe { channel_state(start)? };
+                channel = Some(state);
+                value_ptr
+            }
+            ArenaFieldKind::Broadcast => {
+                let (state, value_ptr) = unsafe { broadcast_state(start)? };
+                broadcast = Some(state);
+                value_ptr
+            }
+            // A ring has the internals of a queue, without sequence numbers in its slots
+            ArenaFieldKind::Queue | ArenaFieldKind::Ring => {
+                let (state, value_ptr) = unsafe { queue_state(start)? };
+                queue = Some(state);
+                value_ptr
+            }
+            ArenaFieldKind::Pool => {
+                let (state, value_ptr) = unsafe { pool_state(start) };
+                pool = Some(state);
+                value_ptr
+            }
+            _ => start,
+        };
+        let value_offset = value_ptr as usize - start as usize;
+        let data =
+            unsafe { std::slice::from_raw_parts(value_ptr, size.saturating_sub(value_offset)) };
+        Ok(Field {
+            name,
+            kind,
+            fingerprint,
+            offset,
+            size,
+            channel,
+            broadcast,
+            queue,
+            pool,
+            data,
+        })
+    }
+}
+
+/// Types whose values can be pretty printed, found by their fingerprint
+pub struct TypeRegistry {
+    types: HashMap<u64, RegisteredType>,
+}
+
+struct RegisteredType {
+    name: &'static str,
+    format: fn(&Field) -> Result<String>,
+}
+
+#[allow(clippy::new_without_default)]
+impl TypeRegistry {
+    /// A registry knowing the primitive types
+    pub fn new() -> Self {
+        TypeRegistry {
+            types: HashMap::new(),
+        }
+        .register::<bool>()
+        .register::<char>()
+        .register::<u8>()
+        .register::<u16>()
+        .register::<u32>()
+        .register::<u64>()
+        .register::<usize>()
+        .register::<i8>()
+        .register::<i16>()
+        .register::<i32>()
+        .register::<i64>()
+        .register::<isize>()
+        .register::<f32>()
+        .register::<f64>()
+    }
+
+    /// Pretty prints the fields of type `T`, which must be the type the process that created the
+    /// field was built with
+    pub fn register<T: serde::Serialize + Copy>(mut self) -> Self {
+        self.types.insert(
+            crate::arena::fingerprint::<T>(),
+            RegisteredType {
+                name: std::any::type_name::<T>(),
+                format: format_values::<T>,
+            },
+        );
+        self
+    }
+
+    /// Returns the name of the type of `field`, if it is registered
+    pub fn type_name(&self, field: &Field) -> Option<&'static str> {
+        self.types.get(&field.fingerprint).map(|t| t.name)
+    }
+
+    /// Pretty prints the value(s) of `field`, `None` if its type isn't registered
+    pub fn format(&self, field: &Field) -> Option<Result<String>> {
+        let registered = self.types.get(&field.fingerprint)?;
+        Some((registered.format)(field))
+    }
+}
+
+fn format_values<T: serde::Serialize + Copy>(field: &Field) -> Result<String> {
+    let size = std::mem::size_of::<T>();
+    let read = |bytes: &[u8]| unsafe { (bytes.as_ptr() as *const T).read_unaligned() };
+    let config = ron::ser::PrettyConfig::new();
+    Ok(match (field.kind, field.channel, field.broadcast) {
+        (ArenaFieldKind::Slice, ..) => {
+            let values: Vec<T> = if size == 0 {
+                Vec::new()
+            } else {
+                field.data.chunks_exact(size).map(read).collect()
+            };
+            ron::ser::to_string_pretty(&values, config)?
+        }
+        // The pending values, from the oldest one
+        (ArenaFieldKind::Channel, Some(channel), _) => {
+            if channel.capacity == 0 || field.data.len() < size * channel.capacity {
+                return Err("The channel is smaller than its slots".into());
+            }
+            let values: Vec<T> = (0..channel.pending.min(channel.capacity))
+                .map(|i| {
+                    let slot = (channel.head + i) % channel.capacity;
+                    read(&field.data[slot * size..])
+                })
+                .collect();
+            ron::ser::to_string_pretty(&values, config)?
+        }
+        // The values still kept, from the oldest one
+        (ArenaFieldKind::Broadcast, _, Some(broadcast)) => {
+            if broadcast.capacity == 0 || field.data.len() < size * broadcast.capacity {
+                return Err("The broadcast is smaller than its slots".into());
+            }
+            let kept = broadcast.sent.min(broadcast.capacity as u64);
+            let values: Vec<T> = (broadcast.sent - kept..broadcast.sent)
+                .map(|i| read(&field.data[(i % broadcast.capacity as u64) as usize * size..]))
+                .collect();
+            ron::ser::to_string_pretty(&values, config)?
+        }
+        // The pending values, from the oldest one. Each slot holds a sequence number before
+        // the value.
+        (ArenaFieldKind::Queue, ..) => {
+            let queue = field.queue.ok_or("The queue wasn't inspected")?;
+            let slot_size = std::mem::size_of::<QueueSlot<T>>();
+            if queue.capacity == 0 || field.data.len() < slot_size * queue.capacity {
+                return Err("The queue is smaller than its slots".into());
+            }
+            let values: Vec<T> = (0..queue.pending.min(queue.capacity))
+                .map(|i| {
+                    let slot = queue.head.wrapping_add(i) % queue.capacity;
+                    let bytes = &field.data[slot * slot_size..];
+                    unsafe { (bytes.as_ptr() as *const QueueSlot<T>).read_unaligned() }.value
+                })
+                .collect();
+            ron::ser::to_string_pretty(&values, config)?
+        }
+        // The pending values, from the oldest one
+        (ArenaFieldKind::Ring, ..) => {
+            let ring = field.queue.ok_or("The ring wasn't inspected")?;
+            if ring.capacity == 0 || field.data.len() < size * ring.capacity {
+                return Err("The ring is smaller than its slots".into());
+            }
+            let values: Vec<T> = (0..ring.pending.min(ring.capacity))
+                .map(|i| read(&field.data[ring.head.wrapping_add(i) % ring.capacity * size..]))
+                .collect();
+            ron::ser::to_string_pretty(&values, config)?
+        }
+        _ => {
+            if field.data.len() < size {
+                return Err("The field is smaller than its type".into());
+            }
+            ron::ser::to_string_pretty(&read(field.data), config)?
+        }
+    })
+}
+
+/// Runs the `hot-reload-inspect` command line, pretty printing the types of `registry`.
+///
+/// Usage: hot-reload-inspect [--hex] [--values] [PREFIX]
+pub fn run(registry: &TypeRegistry) -> Result<()> {
+    let mut hex = false;
+    let mut values = false;
+    let mut prefix = String::new();
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--hex" => hex = true,
+            "--values" => values = true,
+            _ if arg.starts_with("--") => return Err(format!("Unexpected argument {}", arg).into()),
+            _ => prefix = arg,
+        }
+    }
+
+    let segments = find_segments(&prefix)?;
+    for segment in &segments {
+        println!(
+            "{} \"{}\" ({} bytes, owner {}{})",
+            segment.os_id,
+            segment.name,
+            segment.size,
+            segment.owner_pid,
+            if segment.is_arena() { ", arena" } else { "" }
+        );
+        let fields = match segment.fields() {
+            Ok(fields) => fields,
+            Err(e) => {
+                println!("  {}", e);
+                continue;
+            }
+        };
+        for field in &fields {
+            println!("  {}", describe(field, registry));
+            // The values of a channel, queue or ring with nothing pending were all received already,
+            // the blocks of a pool are too large to print
+            let has_value = field.channel.is_none_or(|channel| channel.pending > 0)
+                && field.queue.is_none_or(|queue| queue.pending > 0)
+                && field.pool.is_none();
+            if values && has_value {
+                match registry.format(field) {
+                    Some(Ok(formatted)) => println!("{}", indent(&formatted, "    ")),
+                    Some(Err(e)) => println!("    {}", e),
+                    None if !hex => println!("{}", indent(&hex_dump(field.data), "    ")),
+                    None => {}
+                }
+            }
+            if hex {
+                println!("{}", indent(&hex_dump(field.data), "    "));
+            }
+        }
+    }
+    if segments.is_empty() {
+        println!("No shared memory found");
+    }
+    Ok(())
+}
+
+fn describe(field: &Field, registry: &TypeRegistry) -> String {
+    let kind = match field.kind {
+        ArenaFieldKind::Value => "value",
+        ArenaFieldKind::Slice => "slice",
+        ArenaFieldKind::Channel => "channel",
+        ArenaFieldKind::Broadcast => "broadcast",
+        ArenaFieldKind::Queue => "queue",
+        ArenaFieldKind::Ring => "ring",
+        ArenaFieldKind::Pool => "pool",
+    };
+    let mut description = format!(
+        "{} : {} of {} (fingerprint {:016X}), {} bytes at {}",
+        field.name,
+        kind,
+        registry.type_name(field).unwrap_or("unregistered type"),
+        field.fingerprint,
+        field.size,
+        field.offset
+    );
+    if let Some(channel) = field.channel {
+        description += &format!(
+            ", {}, event {}, {}/{} pending, {} dropped",
+            describe_lock(channel.lock),
+            if channel.signaled { "set" } else { "clear" },
+            channel.pending,
+            channel.capacity,
+            channel.dropped
+        );
+    }
+    if let Some(broadcast) = field.broadcast {
+        description += &format!(
+            ", {}, {} sent, keeps {}",
+            describe_lock(broadcast.lock),
+            broadcast.sent,
+            broadcast.capacity
+        );
+    }
+    if let Some(queue) = field.queue {
+        description += &format!(
+            ", {}/{} pending, {} dropped{}",
+            queue.pending,
+            queue.capacity,
+            queue.dropped,
+            if queue.receiver_waiting {
+                ", receiver waiting"
+            } else {
+                ""
+            }
+        );
+    }
+    if let Some(pool) = field.pool {
+        description += &format!(
+            ", {}/{} blocks of {} allocated",
+            pool.allocated, pool.blocks, pool.block_len
+        );
+    }
+    description
+}
+
+fn describe_lock(lock: LockState) -> &'static str {
+    match lock {
+        LockState::Unlocked => "unlocked",
+        LockState::Locked => "locked",
+        LockState::OwnerDead => "abandoned by a dead process",
+    }
+}
+
+/// Offset, 16 bytes in hex and the same bytes as ASCII on every line
+fn hex_dump(bytes: &[u8]) -> String {
+    bytes
+        .chunks(16)
+        .enumerate()
+        .map(|(i, line)| {
+            let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+            let ascii: String = line
+                .iter()
+                .map(|b| match *b {
+                    0x20..=0x7e => *b as char,
+                    _ => '.',
+                })
+                .collect();
+            format!("{:08x}  {:<47}  |{}|", i * 16, hex.join(" "), ascii)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn indent(text: &str, prefix: &str) -> String {
+    text.lines()
+        .map(|line| format!("{}{}", prefix, line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -1,9 +1,29 @@
+pub mod access;
+pub mod arena;
+pub mod gc;
+pub mod inspect;
+#[cfg(target_family = "unix")]
+pub mod select;
+pub mod service;
 pub mod simple_shared_memory;
 pub extern crate ron;
 pub extern crate serde;
+pub use access::*;
+pub use arena::{Arena, ArenaEntry, ArenaField, ArenaFieldKind};
+pub use hot_reload_derive::{service, state};
+pub use service::{Service, SharedService};
+pub use simple_shared_memory::Full;
+pub use simple_shared_memory::PoolBlock;
+#[cfg(target_family = "unix")]
+pub use simple_shared_memory::SharedBroadcast;
 pub use simple_shared_memory::SharedChannel;
 pub use simple_shared_memory::SharedMemory;
+pub use simple_shared_memory::SharedMemorySimple;
 pub use simple_shared_memory::SharedMemorySlice;
+pub use simple_shared_memory::SharedPool;
+pub use simple_shared_memory::SharedQueue;
+pub use simple_shared_memory::SharedRing;
+pub use simple_shared_memory::SharedRpc;
 use simple_shared_memory::*;
 
 pub enum Process {
@@ -47,6 +67,10 @@ pub struct HotReload {
     process: Process,
     shared_memory_id_prefix: String,
     reloadable_watch_process: Option<std::process::Child>,
+    arena: Option<Arena>,
+    /// Rung by the channels created by this `HotReload`, opened with the first of them
+    #[cfg(target_family = "unix")]
+    doorbell: std::cell::RefCell<Option<std::sync::Arc<select::Doorbell>>>,
 }
 
 pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
@@ -56,16 +80,44 @@ pub type Shared<T> = Box<dyn SharedMemory<T>>;
 impl HotReload {
     pub fn new(process: Process) -> Self {
         let shared_memory_id_prefix = match process {
-            Process::Owner => format!("/{:X}", rand::random::<u32>()),
+            Process::Owner => {
+                // Clean up after previous owners that were killed before they could
+                #[cfg(feature = "gc-on-start")]
+                let _ = gc::collect_stale_segments();
+                format!("{:X}/", rand::random::<u32>())
+            }
             Process::Reloadable => relodable_process_memory_pref(),
         };
         HotReload {
             process,
             shared_memory_id_prefix,
             reloadable_watch_process: None,
+            arena: None,
+            #[cfg(target_family = "unix")]
+            doorbell: std::cell::RefCell::new(None),
         }
     }
 
+    /// Same as `new()`, but the owner keeps the shared state in files inside `dir` so that it
+    /// survives restarts of the owner. The reloadable process picks the files up on its own.
+    ///
+    /// Only plain data persists: values, slices and the arena. The owner initializes the locks
+    /// and events of channels, rpcs, broadcasts, queues, rings and pools again every time it
+    /// starts, since they belonged to processes that are gone, so anything sent through them
+    /// before the restart is lost.
+    pub fn persistent<P: AsRef<std::path::Path>>(process: Process, dir: P) -> Result<Self> {
+        let mut hot_reload = Self::new(process);
+        if hot_reload.is_owner() {
+            std::fs::create_dir_all(dir.as_ref())?;
+            hot_reload.shared_memory_id_prefix = format!(
+                "{}{}/",
+                simple_shared_memory::FILE_PREFIX,
+                dir.as_ref().display()
+            );
+        }
+        Ok(hot_reload)
+    }
+
     pub fn start<TArgs>(
         &mut self,
         reloadable_process_project_name: &str,
@@ -74,6 +126,9 @@ impl HotReload {
     where
         TArgs: serde::Serialize,
     {
+        // The reloadable process announces its starts through the doorbell
+        #[cfg(target_family = "unix")]
+        self.doorbell()?;
         self.reloadable_watch_process = Some(
             std::process::Command::new("cargo")
                 .args(&["watch", "-x"])
@@ -96,6 +151,8 @@ impl HotReload {
         F: Send + 'static,
         TArgs: serde::Serialize,
     {
+        #[cfg(target_family = "unix")]
+        self.doorbell()?;
         let memory_prefix = String::from(&self.shared_memory_id_prefix);
         let serialized_args = ron::to_string(args).unwrap();
         std::thread::spawn(|| {
@@ -111,28 +168,215 @@ impl HotReload {
     }
 
     pub fn value<T>(&self, name: &str) -> Result<Box<impl SharedMemory<T>>> {
-        Ok(Box::new(shared_memory(
-            self.is_owner(),
-            &self.memory_id(name),
-        )?))
+        self.value_with_access::<T, ReadWrite>(name)
+    }
+
+    /// Same as `value()`, but a process that doesn't own the memory maps it read only when
+    /// `A` is `ReadOnly`
+    pub fn value_with_access<T, A: Access>(
+        &self,
+        name: &str,
+    ) -> Result<Box<SharedMemorySimple<T, A>>> {
+        let memory = self.memory::<T>(
+            name,
+            ArenaFieldKind::Value,
+            std::mem::size_of::<T>(),
+            A::WRITABLE,
+        )?;
+        Ok(Box::new(SharedMemorySimple::new(memory)))
     }
 
     pub fn slice<T>(&self, name: &str, length: usize) -> Result<Box<SharedMemorySlice<T>>> {
-        Ok(Box::new(shared_memory_with_slice(
-            self.is_owner(),
-            &self.memory_id(name),
-            length,
-        )?))
+        self.slice_with_access::<T, ReadWrite>(name, length)
+    }
+
+    /// Same as `slice()`, but a process that doesn't own the memory maps it read only when
+    /// `A` is `ReadOnly`
+    pub fn slice_with_access<T, A: Access>(
+        &self,
+        name: &str,
+        length: usize,
+    ) -> Result<Box<SharedMemorySlice<T, A>>> {
+        let memory = self.memory::<T>(
+            name,
+            ArenaFieldKind::Slice,
+            std::mem::size_of::<T>() * length,
+            A::WRITABLE,
+        )?;
+        Ok(Box::new(SharedMemorySlice::new(memory, length)))
+    }
+
+    /// A channel holding up to `capacity` values. `send()` waits for the receiver to make room
+    /// when the channel is full, `force_send()` drops the oldest value instead.
+    pub fn channel<T>(&self, name: &str, capacity: usize) -> Result<Box<SharedChannel<T>>>
+    where
+        T: Copy,
+    {
+        let memory = self.memory::<T>(
+            name,
+            ArenaFieldKind::Channel,
+            channel_size::<T>(capacity),
+            true,
+        )?;
+        let channel = channel_in(memory, capacity)?;
+        #[cfg(target_family = "unix")]
+        let channel = channel.with_doorbell(self.doorbell()?);
+        Ok(Box::new(channel))
     }
 
-    pub fn channel<T>(&self, name: &str) -> Result<Box<SharedChannel<T>>>
+    /// A queue any number of threads, in both processes, can send up to `capacity` values to
+    /// without taking a lock. Only one process receives from it.
+    pub fn queue<T>(&self, name: &str, capacity: usize) -> Result<Box<SharedQueue<T>>>
     where
         T: Copy,
     {
-        Ok(Box::new(shared_channel(
-            self.is_owner(),
-            &self.memory_id(name),
-        )?))
+        let memory =
+            self.memory::<T>(name, ArenaFieldKind::Queue, queue_size::<T>(capacity), true)?;
+        let queue = queue_in(memory, capacity)?;
+        #[cfg(target_family = "unix")]
+        let queue = queue.with_doorbell(self.doorbell()?);
+        Ok(Box::new(queue))
+    }
+
+    /// A ring buffer of up to `capacity` values from one thread to another, in either process,
+    /// for values sent at a high rate. Neither side takes a lock.
+    pub fn ring<T>(&self, name: &str, capacity: usize) -> Result<Box<SharedRing<T>>>
+    where
+        T: Copy,
+    {
+        let memory =
+            self.memory::<T>(name, ArenaFieldKind::Ring, ring_size::<T>(capacity), true)?;
+        let ring = ring_in(memory, capacity)?;
+        #[cfg(target_family = "unix")]
+        let ring = ring.with_doorbell(self.doorbell()?);
+        Ok(Box::new(ring))
+    }
+
+    /// A pool of `blocks` blocks of `block_len` values, written in place by one process and sent
+    /// to the other as a `PoolBlock` through a channel
+    pub fn pool<T>(&self, name: &str, block_len: usize, blocks: usize) -> Result<Box<SharedPool<T>>>
+    where
+        T: Copy,
+    {
+        let memory = self.memory::<T>(
+            name,
+            ArenaFieldKind::Pool,
+            pool_size::<T>(block_len, blocks),
+            true,
+        )?;
+        Ok(Box::new(pool_in(memory, block_len, blocks)?))
+    }
+
+    /// A channel where every subscriber receives each value, keeping the last `capacity` values
+    #[cfg(target_family = "unix")]
+    pub fn broadcast<T>(&self, name: &str, capacity: usize) -> Result<Box<SharedBroadcast<T>>>
+    where
+        T: Copy,
+    {
+        let memory = self.memory::<T>(
+            name,
+            ArenaFieldKind::Broadcast,
+            broadcast_size::<T>(capacity),
+            true,
+        )?;
+        Ok(Box::new(
+            broadcast_in(memory, capacity)?.with_doorbell(self.doorbell()?),
+        ))
+    }
+
+    /// Calls answered by the other process, made of the channels `{name}/requests` and
+    /// `{name}/responses`
+    pub fn rpc<Req, Resp>(&self, name: &str) -> Result<Box<SharedRpc<Req, Resp>>>
+    where
+        Req: Copy,
+        Resp: Copy,
+    {
+        let requests = self.channel(&format!("{}/requests", name), 1)?;
+        let responses = self.channel(&format!("{}/responses", name), 1)?;
+        Ok(Box::new(SharedRpc::new(*requests, *responses)))
+    }
+
+    /// Calls of the methods of `T`, made through `rpc()`
+    pub fn service<T: ?Sized + Service>(&self, name: &str) -> Result<Box<SharedService<T>>> {
+        Ok(Box::new(SharedService::new(*self.rpc(name)?)))
+    }
+
+    /// Lays out the values, slices and channels created from now on in a single shared
+    /// memory segment instead of one segment each. The owner passes all the fields it will
+    /// create, the other process finds them in the table of contents of the arena.
+    ///
+    /// The whole arena is writable, so fields with `ReadOnly` access are only protected by
+    /// their API and not by the memory mapping.
+    pub fn use_arena(&mut self, fields: &[ArenaField]) -> Result<()> {
+        let identifier = self.memory_id("arena");
+        self.arena = Some(if self.is_owner() {
+            Arena::create(&identifier, fields)?
+        } else {
+            Arena::open(&identifier)?
+        });
+        Ok(())
+    }
+
+    pub fn arena(&self) -> Option<&Arena> {
+        self.arena.as_ref()
+    }
+
+    /// Ready whenever the reloadable process started, from its first start on, so that a
+    /// `Select` of the owner can wait for reloads along with its channels
+    #[cfg(target_family = "unix")]
+    pub fn reloads(&self) -> Result<select::Reloads> {
+        Ok(select::Reloads::new(self.doorbell()?))
+    }
+
+    /// Tells the `reloads()` of the owner that the reloadable process started, done by the
+    /// `reloadable()` constructor of a state
+    pub fn announce_start(&self) -> Result<()> {
+        #[cfg(target_family = "unix")]
+        self.doorbell()?.announce_start();
+        Ok(())
+    }
+
+    // The memory of a field of type `T`, in the arena if there is one
+    fn memory<T>(
+        &self,
+        name: &str,
+        kind: ArenaFieldKind,
+        size: usize,
+        writable: bool,
+    ) -> Result<Memory> {
+        if name.starts_with('.') {
+            return Err(format!(
+                "`{}`: names starting with `.` are reserved for the internals of the state",
+                name
+            )
+            .into());
+        }
+        let fingerprint = arena::fingerprint::<T>();
+        Ok(match self.arena.as_ref() {
+            Some(arena) => Memory::InArena(arena.region(name, kind, size, fingerprint)?),
+            None => Memory::Own(get_shared_memory(
+                self.is_owner(),
+                &self.memory_id(name),
+                size,
+                writable,
+                arena::segment_tag(kind, fingerprint),
+            )?),
+        })
+    }
+
+    // The doorbell of `select`, created by the owner along with its first channel or before
+    // starting the reloadable process. Its name can't be the name of a field.
+    #[cfg(target_family = "unix")]
+    fn doorbell(&self) -> Result<std::sync::Arc<select::Doorbell>> {
+        let mut doorbell = self.doorbell.borrow_mut();
+        if doorbell.is_none() {
+            let identifier = self.memory_id(".doorbell");
+            *doorbell = Some(std::sync::Arc::new(select::Doorbell::open(
+                self.is_owner(),
+                &identifier,
+            )?));
+        }
+        Ok(std::sync::Arc::clone(doorbell.as_ref().unwrap()))
     }
 
     fn is_owner(&self) -> bool {
@@ -152,139 +396,153 @@ impl Drop for HotReload {
     }
 }
 
-/// Creates a new shared state between `owner` and `reloadable` processes.
-///
-/// Any data in the state must not contain pointers. It is the same as the data that
-/// would be allocated on the stack (unfortunately there is no Trait for this
-/// constraint in Rust).
+/// The fields of a state nested in other `#[hot_reload::state]`s, implemented by the states
+/// declared without a reloadable package
+pub trait SharedFields: Sized {
+    /// Arguments of the outermost state
+    type Arguments;
+
+    /// Adds the fields to the layout of an arena, their names starting with `prefix`
+    fn layout(prefix: &str, arguments: &Self::Arguments, fields: &mut Vec<ArenaField>);
+
+    /// Creates or opens the fields, their names starting with `prefix`
+    fn open(hot_reload: &HotReload, prefix: &str, arguments: &Self::Arguments) -> Result<Self>;
+}
+
+/// Calls the closure declaring the length of a slice or the capacity of a channel of a state
+#[doc(hidden)]
+pub fn with_arguments<A, R>(arguments: &A, declarator: impl FnOnce(&A) -> R) -> R {
+    declarator(arguments)
+}
+
+/// Declares the state shared between the owner and the reloadable processes, along with `owner()`,
+/// `persistent_owner()`, `reloadable()` and `args()` functions calling those of the state.
 ///
-/// # Examples
+/// Kept for states written before `#[hot_reload::state]`, which it expands to: fields declared
+/// as `name: slice::<T>(length)`, `name: value::<T>()` and `name: channel::<T>()` become
+/// `#[slice(len = length)] pub name: [T]`, `pub name: T` and `#[channel] pub name: T`. Their
+/// channels hold a single value, which `force_send()` replaces where sending would wait.
 ///
-/// ```
+/// ```ignore
 /// hot_reload!(
-///    // The name of the package that will be reloadable
-///    "example-impl",
-///    // The arguments passed to the reloadable package on initialization
-///    Arguments,
-///    // Template for the shared state struct
-///    struct HotReloaded {
-///        buffer: slice::<u32>(|arguments: &Arguments| {
-///            arguments.window_width * arguments.window_height
-///        }),
-///        channel_to_impl: channel::<()>(),
-///        channel_from_impl: channel::<str>(),
-///    }
+///     "example-impl",
+///     Arguments,
+///     #[arena]
+///     struct HotReloaded {
+///         #[writer(reloadable)]
+///         buffer: slice::<u32>(|arguments: &Arguments| arguments.width * arguments.height),
+///         channel_to_impl: channel::<()>(),
+///     }
 /// );
 /// ```
+#[deprecated(note = "declare the state with the #[hot_reload::state] attribute")]
 #[macro_export]
 macro_rules! hot_reload {
     (
         $project_name:literal,
         $args_type_name:ident,
-        struct $state_struct_name:ident
-            {
-                $($field_name:ident : $field_type:ident::<$field_type_arg:ty>
-                    ( $( $declarator:expr)? )
-                ),+ $(,)?
-            }) => {
+        $(#[$layout:ident])?
+        struct $state_struct_name:ident { $($fields:tt)* }
+    ) => {
+        $crate::hot_reload_fields!(
+            [$project_name, $args_type_name, [$($layout)?], $state_struct_name]
+            []
+            $($fields)*
+        );
+    };
+}
+
+/// Turns the fields of `hot_reload!` into those of a `#[hot_reload::state]` one by one
+#[doc(hidden)]
+#[macro_export]
+macro_rules! hot_reload_fields {
+    (
+        [$project_name:literal, $args_type_name:ident, [$($layout:ident)?], $state_struct_name:ident]
+        [$($fields:tt)*]
+    ) => {
+        #[$crate::state(reloadable = $project_name, args = $args_type_name $(, $layout)?)]
         pub struct $state_struct_name {
-            hot_reload: HotReload,
-            $(pub $field_name: $crate::hot_reload_field_type!($field_type $field_type_arg),)+
+            $($fields)*
         }
 
         pub fn owner<F>(
             arguments: $args_type_name,
-            implementation: F
-        ) -> Result<$state_struct_name>
+            implementation: F,
+        ) -> $crate::Result<$state_struct_name>
         where
             F: FnOnce(),
             F: Send + 'static,
         {
-            let mut state = setup(Process::Owner, &arguments)?;
-            #[cfg(feature = "enabled")]
-            state.hot_reload.start($project_name, &arguments)?;
-            #[cfg(not(feature = "enabled"))]
-            state.hot_reload.skip(implementation, &arguments)?;
-            Ok(state)
-        }
-
-        pub fn reloadable() -> Result<$state_struct_name> {
-            setup(Process::Reloadable, &reloadable_process_args()?)
+            $state_struct_name::owner(arguments, implementation)
         }
 
-        pub fn args() -> Result<$args_type_name>
+        pub fn persistent_owner<F, P>(
+            arguments: $args_type_name,
+            dir: P,
+            implementation: F,
+        ) -> $crate::Result<$state_struct_name>
         where
-            $args_type_name: serde::de::DeserializeOwned,
+            F: FnOnce(),
+            F: Send + 'static,
+            P: AsRef<std::path::Path>,
         {
-            Ok(ron::from_str(&std::env::args().nth(2).ok_or(
-                "Unexpected missing argument to reloadable process",
-            )?)?)
+            $state_struct_name::persistent_owner(arguments, dir, implementation)
         }
 
+        pub fn reloadable() -> $crate::Result<$state_struct_name<$crate::ReloadableSide>> {
+            $state_struct_name::reloadable()
+        }
 
-        fn setup(
-            process: Process,
-            arguments: &$args_type_name,
-        ) -> Result<$state_struct_name> {
-            let hot_reload = HotReload::new(process);
-
-            $(
-                let $field_name = $crate::hot_reload_field_definition!(
-                    $field_type,
-                    hot_reload,
-                    arguments,
-                    $field_name,
-                    $($declarator)?
-                );
-            )+
-
-            // let buffer = hot_reload.slice::<u32>("buffer", window_width * window_height)?;
-            Ok($state_struct_name {
-                hot_reload,
-                $($field_name,)+
-                // buffer
-            })
+        pub fn args() -> $crate::Result<$args_type_name> {
+            $state_struct_name::args()
         }
     };
-}
-
-#[doc(hidden)]
-#[macro_export]
-macro_rules! hot_reload_field_definition {
     (
-        slice,
-        $reload_instance_name:ident,
-        $args_variable_name:ident,
-        $field_name:ident,
-        $declarator:expr) => {{
-        $reload_instance_name.slice(stringify!($field_name), $declarator($args_variable_name))?
-    }};
-    (
-        value,
-        $reload_instance_name:ident,
-        $args_variable_name:ident,
-        $field_name:ident,) => {{
-        $reload_instance_name.value(stringify!($field_name))?
-    }};
-    (
-        channel,
-        $reload_instance_name:ident,
-        $args_variable_name:ident,
-        $field_name:ident, ) => {{
-        $reload_instance_name.channel(stringify!($field_name))?
-    }};
-}
-
-#[doc(hidden)]
-#[macro_export]
-macro_rules! hot_reload_field_type {
-    (slice $type_arg:ty) => {
-        Box<SharedMemorySlice<$type_arg>>
+        $state:tt [$($fields:tt)*]
+        $(#[writer($writer:ident)])?
+        $field_name:ident : slice::<$field_type_arg:ty>($declarator:expr)
+        $(, $($rest:tt)*)?
+    ) => {
+        $crate::hot_reload_fields!(
+            $state
+            [
+                $($fields)*
+                $(#[writer($writer)])?
+                #[slice(len = $declarator)]
+                pub $field_name: [$field_type_arg],
+            ]
+            $($($rest)*)?
+        );
     };
-    (value $type_arg:ty) => {
-        Box<dyn SharedMemory<$type_arg>>
+    (
+        $state:tt [$($fields:tt)*]
+        $(#[writer($writer:ident)])?
+        $field_name:ident : value::<$field_type_arg:ty>()
+        $(, $($rest:tt)*)?
+    ) => {
+        $crate::hot_reload_fields!(
+            $state
+            [
+                $($fields)*
+                $(#[writer($writer)])?
+                pub $field_name: $field_type_arg,
+            ]
+            $($($rest)*)?
+        );
     };
-    (channel $type_arg:ty) => {
-        Box<SharedChannel<$type_arg>>
+    (
+        $state:tt [$($fields:tt)*]
+        $field_name:ident : channel::<$field_type_arg:ty>()
+        $(, $($rest:tt)*)?
+    ) => {
+        $crate::hot_reload_fields!(
+            $state
+            [
+                $($fields)*
+                #[channel]
+                pub $field_name: $field_type_arg,
+            ]
+            $($($rest)*)?
+        );
     };
 }
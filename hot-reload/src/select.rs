@@ -0,0 +1,352 @@
+//! Waiting for the first of several channels to hold a value. Only available on Unix.
+//!
+//! The channels, queues, rings, broadcasts and rpcs created by a `HotReload` share a doorbell, a
+//! mutex and condition variable in a segment of their own, which senders ring while a `Select`
+//! waits on it. A `Select` checks the handles it was given and sleeps until the doorbell rings.
+//! `HotReload::reloads()` rings it too whenever the reloadable process starts:
+//!
+//! ```ignore
+//! use hot_reload::select::Select;
+//!
+//! loop {
+//!     let mut select = Select::new();
+//!     let clicks = select.recv(&*state.clicks)?;
+//!     let renderer = select.recv(&*state.renderer)?;
+//!     match select.ready_timeout(frame) {
+//!         Some(i) if i == clicks => on_click(state.clicks.try_recv().unwrap()),
+//!         Some(_) => {
+//!             state.renderer.try_serve(&mut my_renderer);
+//!         }
+//!         None => on_frame(),
+//!     }
+//! }
+//! ```
+//!
+//! Anything else implementing `Selectable` can be waited on as well, as long as all the handles
+//! of a `Select` share a doorbell. Handles of `shared_channel()` and the other functions of
+//! `simple_shared_memory` ring one once given it with `with_doorbell()`, e.g.
+//! `shared_channel(is_owner, "clicks", 4)?.with_doorbell(doorbell)` with
+//! `let doorbell = Arc::new(Doorbell::open(is_owner, "doorbell")?)`.
+
+use crate::simple_shared_memory::get_shared_memory;
+use crate::Result;
+use raw_sync::condvar::*;
+use raw_sync::locks::*;
+use raw_sync::Timeout;
+use shared_memory::Shmem;
+use std::sync::atomic::{fence, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Most processes whose waiting `Select`s are tracked, those of others aren't forgotten when
+/// they are killed
+const TRACKED_PROCESSES: usize = 16;
+
+/// Something a `Select` can wait for
+pub trait Selectable {
+    /// Whether receiving, or serving a call, wouldn't wait
+    fn is_ready(&self) -> bool;
+
+    /// The doorbell rung when it becomes ready, `None` for handles not created by a `HotReload`
+    /// and not given one
+    fn doorbell(&self) -> Option<&Arc<Doorbell>>;
+}
+
+/// Rung after every value sent through the handles of a `HotReload`, or those given it with
+/// `with_doorbell()`, while a `Select` waits
+pub struct Doorbell {
+    #[allow(dead_code)]
+    memory: Shmem,
+    header: *const DoorbellHeader,
+    mutex: ShmMutex<DoorbellInternal>,
+    condvar: Box<dyn CondvarImpl>,
+}
+
+// The mutex and condition variable are shared between processes
+unsafe impl Send for Doorbell {}
+unsafe impl Sync for Doorbell {}
+
+/// Read by every sender without taking the lock, on a cache line of its own
+#[repr(C, align(64))]
+struct DoorbellHeader {
+    /// Number of `Select`s waiting, only written with the lock held
+    waiting: AtomicUsize,
+    /// Number of times the reloadable process started
+    starts: AtomicU64,
+}
+
+struct DoorbellInternal {
+    /// Number of times the doorbell rang
+    rung: u64,
+    /// The `Select`s waiting in each process, so that those of a killed process can be forgotten
+    waiters: [Waiters; TRACKED_PROCESSES],
+    /// The `Select`s waiting in processes that didn't fit in `waiters`
+    untracked: usize,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Waiters {
+    pid: u32,
+    count: usize,
+}
+
+impl DoorbellInternal {
+    /// Counts a `Select` of the process `pid` as waiting
+    fn add_waiter(&mut self, pid: u32) {
+        let waiters = self
+            .waiters
+            .iter()
+            .position(|waiters| waiters.count > 0 && waiters.pid == pid)
+            .or_else(|| self.waiters.iter().position(|waiters| waiters.count == 0));
+        match waiters {
+            Some(index) => {
+                self.waiters[index].pid = pid;
+                self.waiters[index].count += 1;
+            }
+            None => self.untracked += 1,
+        }
+    }
+
+    /// Stops counting a `Select` of the process `pid`
+    fn remove_waiter(&mut self, pid: u32) {
+        match self
+            .waiters
+            .iter_mut()
+            .find(|waiters| waiters.count > 0 && waiters.pid == pid)
+        {
+            Some(waiters) => waiters.count -= 1,
+            None => self.untracked = self.untracked.saturating_sub(1),
+        }
+    }
+
+    /// Forgets the `Select`s of processes that are gone and returns how many are left
+    fn count_waiting(&mut self) -> usize {
+        let mut waiting = self.untracked;
+        for waiters in self.waiters.iter_mut().filter(|waiters| waiters.count > 0) {
+            if crate::gc::is_running(waiters.pid) {
+                waiting += waiters.count;
+            } else {
+                *waiters = Waiters::default();
+            }
+        }
+        waiting
+    }
+}
+
+impl Doorbell {
+    /// Creates the doorbell at `identifier`, or opens the one the owner created
+    pub fn open(is_owner: bool, identifier: &str) -> Result<Self> {
+        let size = std::mem::size_of::<DoorbellHeader>()
+            + ShmMutex::<DoorbellInternal>::size_of(None)
+            + (std::mem::align_of::<u64>() - 1)
+            + Condvar::size_of(None);
+        let memory = get_shared_memory(is_owner, identifier, size, true, 0)?;
+        let header = memory.as_ptr() as *mut DoorbellHeader;
+        let mutex_ptr = unsafe { header.add(1) } as *mut u8;
+        let (mutex, mutex_size) = if is_owner {
+            unsafe {
+                header.write(DoorbellHeader {
+                    waiting: AtomicUsize::new(0),
+                    starts: AtomicU64::new(0),
+                });
+                let internal = DoorbellInternal {
+                    rung: 0,
+                    waiters: [Waiters::default(); TRACKED_PROCESSES],
+                    untracked: 0,
+                };
+                let options = MutexOptions::default().kind(MutexKind::ErrorCheck);
+                ShmMutex::new_with_options(mutex_ptr, internal, options)?
+            }
+        } else {
+            unsafe { ShmMutex::from_existing(mutex_ptr)? }
+        };
+        let condvar_ptr = unsafe { mutex_ptr.add(mutex_size) };
+        let condvar_ptr =
+            unsafe { condvar_ptr.add(condvar_ptr.align_offset(std::mem::align_of::<u64>())) };
+        let (condvar, _) = if is_owner {
+            unsafe { Condvar::new(condvar_ptr)? }
+        } else {
+            unsafe { Condvar::from_existing(condvar_ptr)? }
+        };
+        Ok(Doorbell {
+            header,
+            memory,
+            mutex,
+            condvar,
+        })
+    }
+
+    /// Wakes the waiting `Select`s, called after every value sent
+    pub(crate) fn ring(&self) {
+        // Pairs with the fence in `wait()`: either the `Select` sees the value, or the sender
+        // sees the `Select` waiting
+        fence(Ordering::SeqCst);
+        if self.header().waiting.load(Ordering::SeqCst) == 0 {
+            return;
+        }
+        // Only bumps the count, `Select`s killed while waiting are forgotten by the next `wait()`
+        let mut internal = self.mutex.lock().unwrap();
+        internal.rung = internal.rung.wrapping_add(1);
+        drop(internal);
+        self.condvar.notify_all().unwrap();
+    }
+
+    /// Counts a start of the reloadable process and wakes the `Select`s waiting for it
+    pub(crate) fn announce_start(&self) {
+        self.header().starts.fetch_add(1, Ordering::SeqCst);
+        self.ring();
+    }
+
+    /// Waits up to `timeout` for the doorbell to ring, unless `is_ready()` already. Forgets the
+    /// `Select`s of processes that are gone, which would otherwise make every sender take the lock.
+    fn wait(&self, is_ready: impl Fn() -> bool, timeout: Timeout) {
+        let pid = std::process::id();
+        let mut internal = self.mutex.lock().unwrap();
+        internal.add_waiter(pid);
+        self.recount_waiting(&mut internal);
+        fence(Ordering::SeqCst);
+        let rung = internal.rung;
+        let mut internal = if is_ready() {
+            internal
+        } else {
+            let (guard, _) = self
+                .condvar
+                .wait_timeout_while(internal.into_inner(), timeout, &mut |internal| unsafe {
+                    (*(internal as *const DoorbellInternal)).rung == rung
+                })
+                .unwrap();
+            unsafe { ShmLockGuard::from_inner(guard) }
+        };
+        internal.remove_waiter(pid);
+        self.recount_waiting(&mut internal);
+    }
+
+    /// Tells the senders how many `Select`s wait, called with the lock held
+    fn recount_waiting(&self, internal: &mut DoorbellInternal) {
+        let waiting = internal.count_waiting();
+        self.header().waiting.store(waiting, Ordering::SeqCst);
+    }
+
+    fn header(&self) -> &DoorbellHeader {
+        unsafe { &*self.header }
+    }
+}
+
+/// Ready when the reloadable process started since the last `try_recv()`, e.g. to send it again
+/// what it missed while restarting. Created by `HotReload::reloads()`.
+pub struct Reloads {
+    doorbell: Arc<Doorbell>,
+    /// Starts already received
+    received: u64,
+}
+
+impl Reloads {
+    pub(crate) fn new(doorbell: Arc<Doorbell>) -> Self {
+        Reloads {
+            doorbell,
+            received: 0,
+        }
+    }
+
+    /// Number of times the reloadable process started since the last call, `None` if it didn't
+    pub fn try_recv(&mut self) -> Option<u64> {
+        let starts = self.doorbell.header().starts.load(Ordering::SeqCst);
+        let new_starts = starts.wrapping_sub(self.received);
+        self.received = starts;
+        if new_starts == 0 {
+            None
+        } else {
+            Some(new_starts)
+        }
+    }
+}
+
+impl Selectable for Reloads {
+    fn is_ready(&self) -> bool {
+        self.doorbell.header().starts.load(Ordering::SeqCst) != self.received
+    }
+
+    fn doorbell(&self) -> Option<&Arc<Doorbell>> {
+        Some(&self.doorbell)
+    }
+}
+
+/// Waits for the first of several handles to be ready
+#[derive(Default)]
+pub struct Select<'a> {
+    selected: Vec<&'a dyn Selectable>,
+    /// The doorbell all the handles share
+    doorbell: Option<&'a Arc<Doorbell>>,
+}
+
+impl<'a> Select<'a> {
+    pub fn new() -> Self {
+        Select {
+            selected: Vec::new(),
+            doorbell: None,
+        }
+    }
+
+    /// Adds a handle to wait for, returns the index `ready()` returns when it is ready. Fails
+    /// unless the handle rings the same doorbell as the handles added before it.
+    pub fn recv(&mut self, handle: &'a dyn Selectable) -> Result<usize> {
+        let doorbell = handle
+            .doorbell()
+            .ok_or("The handle rings no doorbell, give it one with `with_doorbell()`")?;
+        match self.doorbell {
+            Some(shared) if !Arc::ptr_eq(shared, doorbell) => {
+                return Err("The handles of a `Select` must share a doorbell".into())
+            }
+            _ => self.doorbell = Some(doorbell),
+        }
+        self.selected.push(handle);
+        Ok(self.selected.len() - 1)
+    }
+
+    /// Returns the index of a ready handle, without waiting. Starts looking at a random handle
+    /// so that a busy one doesn't hide the others.
+    pub fn try_ready(&self) -> Option<usize> {
+        let len = self.selected.len();
+        let start = if len == 0 {
+            0
+        } else {
+            rand::random::<usize>() % len
+        };
+        (0..len)
+            .map(|i| (start + i) % len)
+            .find(|index| self.selected[*index].is_ready())
+    }
+
+    /// Waits for a handle to be ready and returns its index. Waits forever without handles.
+    pub fn ready(&self) -> usize {
+        self.ready_within(None).unwrap()
+    }
+
+    /// Waits up to `timeout` for a handle to be ready and returns its index
+    pub fn ready_timeout(&self, timeout: Duration) -> Option<usize> {
+        self.ready_within(Some(timeout))
+    }
+
+    fn ready_within(&self, timeout: Option<Duration>) -> Option<usize> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            if let Some(index) = self.try_ready() {
+                return Some(index);
+            }
+            let remaining =
+                deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            if remaining == Some(Duration::from_secs(0)) {
+                return None;
+            }
+            match (self.doorbell, remaining) {
+                (Some(doorbell), _) => doorbell.wait(
+                    || self.try_ready().is_some(),
+                    remaining.map_or(Timeout::Infinite, Timeout::Val),
+                ),
+                // Nothing can become ready without handles
+                (None, Some(remaining)) => std::thread::sleep(remaining),
+                (None, None) => std::thread::park(),
+            }
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! Calls of the methods of a trait from one process, answered by an implementation of the
+//! trait in the other process.
+//!
+//! `#[hot_reload::service]` on a trait generates the requests and responses of its methods,
+//! implements `Service` for `dyn Trait` and adds a `{Trait}Client` trait with the same methods
+//! for `SharedService<dyn Trait>`. A state holds the service as `#[service] name: dyn Trait`:
+//!
+//! ```ignore
+//! #[hot_reload::service]
+//! pub trait Renderer {
+//!     fn render(&mut self, time: f64);
+//!     fn on_click(&mut self, x: u32, y: u32) -> bool;
+//! }
+//!
+//! #[hot_reload::state(reloadable = "my-impl", args = Arguments)]
+//! pub struct State {
+//!     #[service]
+//!     pub renderer: dyn Renderer,
+//! }
+//!
+//! // In the owner, returns `None` if the reloadable process didn't answer in time
+//! use my_state::RendererClient;
+//! let handled = state.renderer.on_click(10, 20);
+//!
+//! // In the reloadable process
+//! loop {
+//!     state.renderer.serve(&mut my_renderer);
+//! }
+//! ```
+
+#[cfg(target_family = "unix")]
+use crate::select::{Doorbell, Selectable};
+use crate::SharedRpc;
+use std::time::Duration;
+
+/// How long calls wait for their response, unless changed with `SharedService::set_timeout()`
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A trait whose methods can be called through shared memory, implemented for `dyn Trait` by
+/// `#[hot_reload::service]`
+pub trait Service {
+    /// The method called and its arguments
+    type Request: Copy;
+    /// The method called and what it returned
+    type Response: Copy;
+
+    /// Calls the method of `request`
+    fn dispatch(&mut self, request: &Self::Request) -> Self::Response;
+}
+
+/// Calls of the methods of `T` from one process, answered by the other
+pub struct SharedService<T: ?Sized + Service> {
+    rpc: SharedRpc<T::Request, T::Response>,
+    timeout: Duration,
+}
+
+impl<T: ?Sized + Service> SharedService<T> {
+    pub(crate) fn new(rpc: SharedRpc<T::Request, T::Response>) -> Self {
+        SharedService {
+            rpc,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Sends `request` to the other process and waits for its response, `None` if it didn't
+    /// come in time. The generated `{Trait}Client` methods call this.
+    pub fn call(&mut self, request: &T::Request) -> Option<T::Response> {
+        self.rpc.call(request, self.timeout)
+    }
+
+    /// Changes how long calls wait for their response
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Waits for a call and answers it with `implementation`
+    pub fn serve(&mut self, implementation: &mut T) {
+        self.rpc.serve(|request| implementation.dispatch(request))
+    }
+
+    /// Answers the pending call with `implementation` if there is one, without waiting.
+    /// Returns whether there was a call.
+    pub fn try_serve(&mut self, implementation: &mut T) -> bool {
+        self.rpc
+            .try_serve(|request| implementation.dispatch(request))
+    }
+}
+
+// Ready when a call waits to be served
+#[cfg(target_family = "unix")]
+impl<T: ?Sized + Service> Selectable for SharedService<T> {
+    fn is_ready(&self) -> bool {
+        self.rpc.is_ready()
+    }
+
+    fn doorbell(&self) -> Option<&std::sync::Arc<Doorbell>> {
+        self.rpc.doorbell()
+    }
+}
@@ -1,89 +1,1783 @@
-use anyhow::*;
+use crate::access::*;
+use crate::arena::{fingerprint, segment_tag, ArenaFieldKind, ArenaRegion};
+use crate::inspect::{BroadcastState, ChannelState, LockState, PoolState, QueueState};
+#[cfg(target_family = "unix")]
+use crate::select::{Doorbell, Selectable};
+#[cfg(target_family = "unix")]
+use raw_sync::condvar::*;
 use raw_sync::events::*;
 use raw_sync::locks::*;
 use raw_sync::Timeout;
 use shared_memory::*;
+use std::cell::Cell;
 use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
 // Provides a cross-process channel with a familiar API, similar to [`std::sync::mpsc::channel`].
+// It holds up to `capacity` values, sending to a full channel waits for the receiver to make room
+// unless sent with `try_send()`, or with `force_send()`, which drops the oldest value.
 pub fn shared_channel<T>(
   is_owner: bool,
   identifier: &str,
+  capacity: usize,
 ) -> Result<SharedChannel<T>, Box<dyn std::error::Error>>
 where
   T: Copy,
 {
+  channel_in(
+    Memory::Own(get_shared_memory(
+      is_owner,
+      identifier,
+      channel_size::<T>(capacity),
+      true,
+      segment_tag(ArenaFieldKind::Channel, fingerprint::<T>()),
+    )?),
+    capacity,
+  )
+}
+
+// Provides calls from one process answered by the other, see `SharedRpc`.
+pub fn shared_rpc<Req, Resp>(
+  is_owner: bool,
+  identifier: &str,
+) -> Result<SharedRpc<Req, Resp>, Box<dyn std::error::Error>>
+where
+  Req: Copy,
+  Resp: Copy,
+{
+  Ok(SharedRpc::new(
+    shared_channel(is_owner, &format!("{}/requests", identifier), 1)?,
+    shared_channel(is_owner, &format!("{}/responses", identifier), 1)?,
+  ))
+}
+
+// Provides a cross-process channel where every subscriber receives each value, see
+// `SharedBroadcast`.
+#[cfg(target_family = "unix")]
+pub fn shared_broadcast<T>(
+  is_owner: bool,
+  identifier: &str,
+  capacity: usize,
+) -> Result<SharedBroadcast<T>, Box<dyn std::error::Error>>
+where
+  T: Copy,
+{
+  broadcast_in(
+    Memory::Own(get_shared_memory(
+      is_owner,
+      identifier,
+      broadcast_size::<T>(capacity),
+      true,
+      segment_tag(ArenaFieldKind::Broadcast, fingerprint::<T>()),
+    )?),
+    capacity,
+  )
+}
+
+// Provides a cross-process queue any number of threads and processes can send to, see
+// `SharedQueue`.
+pub fn shared_queue<T>(
+  is_owner: bool,
+  identifier: &str,
+  capacity: usize,
+) -> Result<SharedQueue<T>, Box<dyn std::error::Error>>
+where
+  T: Copy,
+{
+  queue_in(
+    Memory::Own(get_shared_memory(
+      is_owner,
+      identifier,
+      queue_size::<T>(capacity),
+      true,
+      segment_tag(ArenaFieldKind::Queue, fingerprint::<T>()),
+    )?),
+    capacity,
+  )
+}
+
+// Provides a cross-process ring buffer from one sender to one receiver, see `SharedRing`.
+pub fn shared_ring<T>(
+  is_owner: bool,
+  identifier: &str,
+  capacity: usize,
+) -> Result<SharedRing<T>, Box<dyn std::error::Error>>
+where
+  T: Copy,
+{
+  ring_in(
+    Memory::Own(get_shared_memory(
+      is_owner,
+      identifier,
+      ring_size::<T>(capacity),
+      true,
+      segment_tag(ArenaFieldKind::Ring, fingerprint::<T>()),
+    )?),
+    capacity,
+  )
+}
+
+// Provides a cross-process pool of `blocks` blocks of `block_len` values each, see `SharedPool`.
+pub fn shared_pool<T>(
+  is_owner: bool,
+  identifier: &str,
+  block_len: usize,
+  blocks: usize,
+) -> Result<SharedPool<T>, Box<dyn std::error::Error>>
+where
+  T: Copy,
+{
+  pool_in(
+    Memory::Own(get_shared_memory(
+      is_owner,
+      identifier,
+      pool_size::<T>(block_len, blocks),
+      true,
+      segment_tag(ArenaFieldKind::Pool, fingerprint::<T>()),
+    )?),
+    block_len,
+    blocks,
+  )
+}
+
+pub(crate) fn channel_in<T>(
+  memory: Memory,
+  capacity: usize,
+) -> Result<SharedChannel<T>, Box<dyn std::error::Error>>
+where
+  T: Copy,
+{
+  if capacity == 0 {
+    return Err("A channel needs room for at least one value".into());
+  }
+  let memory = event_and_mutex_in(
+    memory,
+    SharedChannelInternal {
+      len: 0,
+      head: 0,
+      capacity,
+      dropped: 0,
+      senders_waiting: 0,
+    },
+  )?;
+  let mut internal = memory.lock();
+  if internal.capacity != capacity {
+    return Err("The channel has another capacity in the other process".into());
+  }
+  let room_ptr = channel_room_ptr(&mut *internal as *mut SharedChannelInternal as *mut u8);
+  drop(internal);
+  let (room, room_size) = unsafe { event_in(room_ptr, memory.memory.is_owner())? };
+  let values = unsafe { room_ptr.add(room_size) };
   Ok(SharedChannel {
-    memory: shared_memory_with_event_and_mutex::<SharedChannelInternal<T>>(is_owner, identifier)?,
+    values: unsafe { values.add(values.align_offset(std::mem::align_of::<T>())) } as *mut T,
+    memory,
+    room,
+    #[cfg(target_family = "unix")]
+    doorbell: None,
+  })
+}
+
+// Bytes of shared memory used by a channel of `T` holding up to `capacity` values
+pub(crate) fn channel_size<T>(capacity: usize) -> usize {
+  Event::size_of(None)
+    + ShmMutex::<SharedChannelInternal>::size_of(None)
+    + Event::size_of(None)
+    + (std::mem::align_of::<T>() - 1)
+    + std::mem::size_of::<T>() * capacity
+}
+
+pub struct SharedChannel<T>
+where
+  T: Copy,
+{
+  memory: SharedMemoryWithEventAndMutex<SharedChannelInternal>,
+  // Set by the receiver when it takes a value while senders wait for room, follows the internals
+  room: Box<dyn EventImpl>,
+  // Ring buffer of `capacity` values following the room event, only accessed under the lock
+  values: *mut T,
+  // Rung after every value sent, see `crate::select`
+  #[cfg(target_family = "unix")]
+  doorbell: Option<Arc<Doorbell>>,
+}
+
+// Aligned to a cache line so that tools which don't know the type of the values can find the
+// internals after the mutex and the room event after the internals, with the number of pending
+// values first
+#[repr(C, align(64))]
+struct SharedChannelInternal {
+  len: usize,
+  head: usize,
+  capacity: usize,
+  // Number of values `force_send()` dropped before they were received
+  dropped: u64,
+  // Number of senders waiting for the room event
+  senders_waiting: usize,
+}
+
+// Returned with the value that couldn't be sent because the channel, queue or ring was full
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Full<T>(pub T);
+
+impl<T> std::fmt::Display for Full<T> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "No room left for the value sent")
+  }
+}
+
+impl<T: std::fmt::Debug> std::error::Error for Full<T> {}
+
+// Waits on `room` until `try_send` succeeds, giving up once `deadline` passes if there is one.
+// `announce` tells the receiver that a sender waits, or that it doesn't anymore, and must be
+// followed by another attempt so that room made in between isn't missed.
+fn send_waiting<T>(
+  room: &dyn EventImpl,
+  deadline: Option<std::time::Instant>,
+  mut try_send: impl FnMut() -> Result<(), Full<T>>,
+  announce: impl Fn(bool),
+) -> Result<(), Full<T>> {
+  if let Ok(()) = try_send() {
+    return Ok(());
+  }
+  announce(true);
+  let sent = loop {
+    let full = match try_send() {
+      Ok(()) => break Ok(()),
+      Err(full) => full,
+    };
+    match deadline {
+      None => wait_for_event(room),
+      Some(deadline) => {
+        let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+        if remaining.as_nanos() == 0 {
+          break Err(full);
+        }
+        match room.wait(Timeout::Val(remaining)) {
+          Ok(()) | Err(raw_sync::Error::TimedOut) => {}
+          Err(e) => panic!("Waiting for room failed: {}", e),
+        }
+      }
+    }
+  };
+  announce(false);
+  sent
+}
+
+// Where the channel internals start after the mutex at `mutex_ptr`
+fn channel_internal_ptr(mutex_ptr: *mut u8) -> *mut u8 {
+  let ptr = unsafe { mutex_ptr.add(Mutex::size_of(Some(mutex_ptr))) };
+  unsafe { ptr.add(ptr.align_offset(std::mem::align_of::<SharedChannelInternal>())) }
+}
+
+// Where the room event starts after the internals at `internal_ptr`, the values follow it
+fn channel_room_ptr(internal_ptr: *mut u8) -> *mut u8 {
+  unsafe { internal_ptr.add(std::mem::size_of::<SharedChannelInternal>()) }
+}
+
+// Tries the mutex at `mutex_ptr` without waiting and reports what it found, releasing it right
+// away. When its owner died raw_sync already released it, except where mutexes aren't robust.
+unsafe fn lock_state(
+  mutex_ptr: *mut u8,
+  data_ptr: *mut u8,
+) -> Result<LockState, Box<dyn std::error::Error>> {
+  let (lock, _) = Mutex::from_existing(mutex_ptr, data_ptr)?;
+  let state = match lock.try_lock(Timeout::Val(std::time::Duration::from_millis(0))) {
+    Ok(_guard) => LockState::Unlocked,
+    Err(raw_sync::Error::TimedOut) => LockState::Locked,
+    Err(raw_sync::Error::OwnerDead) => LockState::OwnerDead,
+    Err(e) => return Err(e.into()),
+  };
+  Ok(state)
+}
+
+// Peeks at the state of the channel at `base_ptr` without knowing the type of its values, taking
+// the lock for an instant. Also returns where the values start.
+pub(crate) unsafe fn channel_state(
+  base_ptr: *mut u8,
+) -> Result<(ChannelState, *const u8), Box<dyn std::error::Error>> {
+  let (event, event_size) = Event::from_existing(base_ptr)?;
+  let mutex_ptr = base_ptr.add(event_size);
+  let internal_ptr = channel_internal_ptr(mutex_ptr);
+  let lock_state = lock_state(mutex_ptr, internal_ptr)?;
+  let internal = &*(internal_ptr as *const SharedChannelInternal);
+  let state = ChannelState {
+    lock: lock_state,
+    signaled: event.is_signaled()?,
+    pending: internal.len,
+    head: internal.head,
+    capacity: internal.capacity,
+    dropped: internal.dropped,
+  };
+  let room_ptr = channel_room_ptr(internal_ptr);
+  let (_, room_size) = Event::from_existing(room_ptr)?;
+  Ok((state, room_ptr.add(room_size)))
+}
+
+impl<T> SharedChannel<T>
+where
+  T: Copy,
+{
+  pub fn try_recv(&mut self) -> Option<T> {
+    let mut internal = self.memory.lock();
+    if internal.len == 0 {
+      return None;
+    }
+    let value = unsafe { self.values.add(internal.head).read() };
+    internal.head = (internal.head + 1) % internal.capacity;
+    internal.len -= 1;
+    // `recv()` consumed the signal, keep it set while values are left
+    let state = if internal.len == 0 {
+      EventState::Clear
+    } else {
+      EventState::Signaled
+    };
+    self.memory.event.set(state).unwrap();
+    if internal.senders_waiting > 0 {
+      self.room.set(EventState::Signaled).unwrap();
+    }
+    Some(value)
+  }
+
+  pub fn recv(&mut self) -> T {
+    wait_for_event(&*self.memory.event);
+    self.try_recv().unwrap()
+  }
+
+  // Waits up to `timeout` for a value
+  pub fn recv_timeout(&mut self, timeout: std::time::Duration) -> Option<T> {
+    let deadline = std::time::Instant::now() + timeout;
+    loop {
+      if let Some(value) = self.try_recv() {
+        return Some(value);
+      }
+      let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+      if remaining.as_nanos() == 0 {
+        return None;
+      }
+      match self.memory.event.wait(Timeout::Val(remaining)) {
+        Ok(()) | Err(raw_sync::Error::TimedOut) => {}
+        Err(e) => panic!("Waiting for the channel failed: {}", e),
+      }
+    }
+  }
+
+  // Queues a copy of `data`, returns it in `Full` if the channel is full
+  pub fn try_send(&mut self, data: &T) -> Result<(), Full<T>> {
+    self.try_push(data)
+  }
+
+  // Queues a copy of `data`, waiting for the receiver to make room if the channel is full
+  pub fn send(&mut self, data: &T) {
+    let _ = self.send_before(data, None);
+  }
+
+  // Queues a copy of `data`, waiting up to `timeout` for the receiver to make room if the channel
+  // is full
+  pub fn send_timeout(&mut self, data: &T, timeout: std::time::Duration) -> Result<(), Full<T>> {
+    self.send_before(data, Some(std::time::Instant::now() + timeout))
+  }
+
+  // Queues a copy of `data` without waiting. If the channel is full its oldest value is dropped
+  // instead, counted in `dropped()` and returned.
+  pub fn force_send(&mut self, data: &T) -> Option<T> {
+    let mut internal = self.memory.lock();
+    let dropped = if internal.len == internal.capacity {
+      let value = unsafe { self.values.add(internal.head).read() };
+      internal.head = (internal.head + 1) % internal.capacity;
+      internal.len -= 1;
+      internal.dropped += 1;
+      Some(value)
+    } else {
+      None
+    };
+    self.push(internal, data);
+    dropped
+  }
+
+  fn send_before(&self, data: &T, deadline: Option<std::time::Instant>) -> Result<(), Full<T>> {
+    send_waiting(&*self.room, deadline, || self.try_push(data), |waiting| {
+      let mut internal = self.memory.lock();
+      if waiting {
+        internal.senders_waiting += 1;
+      } else {
+        internal.senders_waiting -= 1;
+      }
+    })
+  }
+
+  fn try_push(&self, data: &T) -> Result<(), Full<T>> {
+    let internal = self.memory.lock();
+    if internal.len == internal.capacity {
+      return Err(Full(*data));
+    }
+    self.push(internal, data);
+    Ok(())
+  }
+
+  // Writes `data` after the pending values, there must be room for it
+  fn push(&self, mut internal: ShmLockGuard<'_, SharedChannelInternal>, data: &T) {
+    let tail = (internal.head + internal.len) % internal.capacity;
+    unsafe { self.values.add(tail).write(*data) };
+    internal.len += 1;
+    drop(internal);
+    self.memory.event.set(EventState::Signaled).unwrap();
+    #[cfg(target_family = "unix")]
+    ring(&self.doorbell);
+  }
+
+  // Number of values the channel holds before sending has to wait
+  pub fn capacity(&self) -> usize {
+    self.memory.lock().capacity
+  }
+
+  // Number of values `force_send()` dropped before they were received, by either process
+  pub fn dropped(&self) -> u64 {
+    self.memory.lock().dropped
+  }
+
+  // Rings `doorbell` after every value sent, so that a `Select` can wait for the handle
+  #[cfg(target_family = "unix")]
+  pub fn with_doorbell(mut self, doorbell: Arc<Doorbell>) -> Self {
+    self.doorbell = Some(doorbell);
+    self
+  }
+}
+
+#[cfg(target_family = "unix")]
+impl<T> Selectable for SharedChannel<T>
+where
+  T: Copy,
+{
+  fn is_ready(&self) -> bool {
+    self.memory.lock().len > 0
+  }
+
+  fn doorbell(&self) -> Option<&Arc<Doorbell>> {
+    self.doorbell.as_ref()
+  }
+}
+
+// A queue of up to `capacity` values which any number of threads, in any number of processes,
+// send to without taking a lock, and a single receiver takes them out of. Every slot carries a
+// sequence number telling whether it is free for the sender claiming the position or holds the
+// value for the receiver (a bounded queue as described by Dmitry Vyukov). Senders only set the
+// event when the receiver announced that it waits for it, and the receiver only sets the room
+// event when senders announced that they wait for room.
+//
+// A sender killed between claiming a slot and filling it, e.g. a reloadable process restarted in
+// the middle of `send()`, would keep the receiver from ever getting past that slot. Slots record
+// the process of their sender, and the receiver skips the slot once that process is gone. Only a
+// sender killed in the few instructions between claiming the slot and recording its process
+// still blocks the queue, and processes are only checked on Linux.
+pub struct SharedQueue<T>
+where
+  T: Copy,
+{
+  #[allow(dead_code)]
+  memory: Memory,
+  event: Box<dyn EventImpl>,
+  room: Box<dyn EventImpl>,
+  internal: *mut SharedQueueInternal,
+  slots: *mut QueueSlot<T>,
+  // Rung after every value sent, see `crate::select`
+  #[cfg(target_family = "unix")]
+  doorbell: Option<Arc<Doorbell>>,
+}
+
+// Senders on several threads share a handle, the receiving methods take `&mut self`
+unsafe impl<T> Sync for SharedQueue<T> where T: Copy + Send {}
+
+// Aligned to a cache line like `SharedChannelInternal`, with the positions written by the
+// receiver and by the senders on lines of their own
+#[repr(C, align(64))]
+pub(crate) struct SharedQueueInternal {
+  capacity: usize,
+  receiver_waiting: AtomicBool,
+  // Number of senders waiting for the room event
+  senders_waiting: AtomicUsize,
+  // Number of values `force_send()` dropped because there was no room for them
+  dropped: AtomicU64,
+  // Position of the next value to receive
+  head: CacheLine<AtomicUsize>,
+  // Position the next sender claims
+  tail: CacheLine<AtomicUsize>,
+}
+
+#[repr(C, align(64))]
+struct CacheLine<T>(T);
+
+#[repr(C)]
+pub(crate) struct QueueSlot<T> {
+  // `free_sequence()` of the position of the sender that may fill the slot, plus one once the
+  // value is in it
+  sequence: AtomicUsize,
+  // The process of the sender filling the slot, 0 until it recorded itself
+  sender: AtomicU32,
+  pub(crate) value: T,
+}
+
+// Sequence number of the slot at `position` while it is free for the sender claiming the
+// position. Positions count twice, so that a slot holding the value sent at a position is never
+// taken for the free slot of the next position a lap later, as it would in a queue of one value.
+fn free_sequence(position: usize) -> usize {
+  position.wrapping_mul(2)
+}
+
+// Sequence number of the slot at `position` once the value sent at the position is in it
+fn filled_sequence(position: usize) -> usize {
+  free_sequence(position).wrapping_add(1)
+}
+
+// Bytes of shared memory used by a queue of up to `capacity` values of `T`
+pub(crate) fn queue_size<T>(capacity: usize) -> usize {
+  Event::size_of(None) * 2
+    + (std::mem::align_of::<SharedQueueInternal>() - 1)
+    + std::mem::size_of::<SharedQueueInternal>()
+    + (std::mem::align_of::<QueueSlot<T>>() - 1)
+    + std::mem::size_of::<QueueSlot<T>>() * capacity
+}
+
+// Where the queue internals start after the events taking `events_size` bytes at `base_ptr`
+fn queue_internal_ptr(base_ptr: *mut u8, events_size: usize) -> *mut SharedQueueInternal {
+  let ptr = unsafe { base_ptr.add(events_size) };
+  (unsafe { ptr.add(ptr.align_offset(std::mem::align_of::<SharedQueueInternal>())) })
+    as *mut SharedQueueInternal
+}
+
+// Initial internals of a queue or ring
+fn queue_internal(capacity: usize) -> SharedQueueInternal {
+  SharedQueueInternal {
+    capacity,
+    receiver_waiting: AtomicBool::new(false),
+    senders_waiting: AtomicUsize::new(0),
+    dropped: AtomicU64::new(0),
+    head: CacheLine(AtomicUsize::new(0)),
+    tail: CacheLine(AtomicUsize::new(0)),
+  }
+}
+
+pub(crate) fn queue_in<T>(
+  memory: Memory,
+  capacity: usize,
+) -> Result<SharedQueue<T>, Box<dyn std::error::Error>>
+where
+  T: Copy,
+{
+  if capacity == 0 {
+    return Err("A queue needs room for at least one value".into());
+  }
+  let base_ptr = memory.as_ptr();
+  let (event, event_size) = unsafe { event_in(base_ptr, memory.is_owner())? };
+  let (room, room_size) = unsafe { event_in(base_ptr.add(event_size), memory.is_owner())? };
+  let internal = queue_internal_ptr(base_ptr, event_size + room_size);
+  let slots = unsafe { internal.add(1) } as *mut u8;
+  let slots = unsafe { slots.add(slots.align_offset(std::mem::align_of::<QueueSlot<T>>())) };
+  let slots = slots as *mut QueueSlot<T>;
+  if memory.is_owner() {
+    unsafe {
+      internal.write(queue_internal(capacity));
+      for position in 0..capacity {
+        let sequence = AtomicUsize::new(free_sequence(position));
+        std::ptr::addr_of_mut!((*slots.add(position)).sequence).write(sequence);
+        std::ptr::addr_of_mut!((*slots.add(position)).sender).write(AtomicU32::new(0));
+      }
+    }
+  } else if unsafe { (*internal).capacity } != capacity {
+    return Err("The queue has another capacity in the other process".into());
+  }
+  Ok(SharedQueue {
+    memory,
+    event,
+    room,
+    internal,
+    slots,
+    #[cfg(target_family = "unix")]
+    doorbell: None,
+  })
+}
+
+// Peeks at the state of the queue or ring at `base_ptr` without knowing the type of its values.
+// Also returns where the slots start.
+pub(crate) unsafe fn queue_state(
+  base_ptr: *mut u8,
+) -> Result<(QueueState, *const u8), Box<dyn std::error::Error>> {
+  let (_, event_size) = Event::from_existing(base_ptr)?;
+  let (_, room_size) = Event::from_existing(base_ptr.add(event_size))?;
+  let internal = queue_internal_ptr(base_ptr, event_size + room_size);
+  let head = (*internal).head.0.load(Ordering::Acquire);
+  let state = QueueState {
+    receiver_waiting: (*internal).receiver_waiting.load(Ordering::Acquire),
+    pending: (*internal).tail.0.load(Ordering::Acquire).wrapping_sub(head),
+    head,
+    capacity: (*internal).capacity,
+    dropped: (*internal).dropped.load(Ordering::Relaxed),
+  };
+  Ok((state, internal.add(1) as *const u8))
+}
+
+impl<T> SharedQueue<T>
+where
+  T: Copy,
+{
+  // Queues a copy of `data`, returns it in `Full` if the queue is full
+  pub fn try_send(&self, data: &T) -> Result<(), Full<T>> {
+    let internal = self.internal();
+    let mut position = internal.tail.0.load(Ordering::Relaxed);
+    loop {
+      let slot = self.slot(position);
+      let sequence = unsafe { (*slot).sequence.load(Ordering::Acquire) };
+      match sequence.wrapping_sub(free_sequence(position)) as isize {
+        // The slot is free, claim it
+        0 => match internal.tail.0.compare_exchange_weak(
+          position,
+          position.wrapping_add(1),
+          Ordering::Relaxed,
+          Ordering::Relaxed,
+        ) {
+          Ok(_) => break,
+          Err(current) => position = current,
+        },
+        // The receiver didn't take the value sent a lap ago yet
+        lag if lag < 0 => return Err(Full(*data)),
+        // Another sender claimed the position
+        _ => position = internal.tail.0.load(Ordering::Relaxed),
+      }
+    }
+    let slot = self.slot(position);
+    unsafe {
+      (*slot).sender.store(std::process::id(), Ordering::Release);
+      std::ptr::addr_of_mut!((*slot).value).write(*data);
+      (*slot).sequence.store(filled_sequence(position), Ordering::Release);
+    }
+    std::sync::atomic::fence(Ordering::SeqCst);
+    if internal.receiver_waiting.swap(false, Ordering::SeqCst) {
+      self.event.set(EventState::Signaled).unwrap();
+    }
+    #[cfg(target_family = "unix")]
+    ring(&self.doorbell);
+    Ok(())
+  }
+
+  // Queues a copy of `data`, waiting for the receiver to make room if the queue is full
+  pub fn send(&self, data: &T) {
+    let _ = self.send_before(data, None);
+  }
+
+  // Queues a copy of `data`, waiting up to `timeout` for the receiver to make room if the queue is
+  // full
+  pub fn send_timeout(&self, data: &T, timeout: std::time::Duration) -> Result<(), Full<T>> {
+    self.send_before(data, Some(std::time::Instant::now() + timeout))
+  }
+
+  // Queues a copy of `data` without waiting. If the queue is full the value is dropped instead,
+  // counted in `dropped()` and returned: only the receiver takes values out of the queue, so
+  // unlike a channel the queue can't make room by dropping the oldest one.
+  pub fn force_send(&self, data: &T) -> Option<T> {
+    let Full(value) = self.try_send(data).err()?;
+    self.internal().dropped.fetch_add(1, Ordering::Relaxed);
+    Some(value)
+  }
+
+  pub fn try_recv(&mut self) -> Option<T> {
+    loop {
+      let position = self.internal().head.0.load(Ordering::Relaxed);
+      let slot = self.slot(position);
+      if unsafe { (*slot).sequence.load(Ordering::Acquire) } == filled_sequence(position) {
+        let value = unsafe { std::ptr::addr_of!((*slot).value).read() };
+        self.free(position);
+        return Some(value);
+      }
+      if !self.is_abandoned(position) {
+        return None;
+      }
+      self.free(position);
+    }
+  }
+
+  pub fn recv(&mut self) -> T {
+    loop {
+      if let Some(value) = self.try_recv_or_announce_wait() {
+        return value;
+      }
+      wait_for_event(&*self.event);
+    }
+  }
+
+  // Waits up to `timeout` for a value
+  pub fn recv_timeout(&mut self, timeout: std::time::Duration) -> Option<T> {
+    let deadline = std::time::Instant::now() + timeout;
+    loop {
+      if let Some(value) = self.try_recv_or_announce_wait() {
+        return Some(value);
+      }
+      let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+      if remaining.as_nanos() == 0 {
+        return None;
+      }
+      match self.event.wait(Timeout::Val(remaining)) {
+        Ok(()) | Err(raw_sync::Error::TimedOut) => {}
+        Err(e) => panic!("Waiting for the queue failed: {}", e),
+      }
+    }
+  }
+
+  // Number of values the queue holds before sending has to wait
+  pub fn capacity(&self) -> usize {
+    self.internal().capacity
+  }
+
+  // Number of values `force_send()` dropped, by any sender
+  pub fn dropped(&self) -> u64 {
+    self.internal().dropped.load(Ordering::Relaxed)
+  }
+
+  // Rings `doorbell` after every value sent, so that a `Select` can wait for the handle
+  #[cfg(target_family = "unix")]
+  pub fn with_doorbell(mut self, doorbell: Arc<Doorbell>) -> Self {
+    self.doorbell = Some(doorbell);
+    self
+  }
+
+  fn send_before(&self, data: &T, deadline: Option<std::time::Instant>) -> Result<(), Full<T>> {
+    send_waiting(&*self.room, deadline, || self.try_send(data), |waiting| {
+      announce_waiting_for_room(self.internal(), waiting)
+    })
+  }
+
+  // Receives a value, or tells the senders to set the event and checks once more, so that a
+  // value sent in between isn't missed
+  fn try_recv_or_announce_wait(&mut self) -> Option<T> {
+    if let Some(value) = self.try_recv() {
+      return Some(value);
+    }
+    self.internal().receiver_waiting.store(true, Ordering::SeqCst);
+    std::sync::atomic::fence(Ordering::SeqCst);
+    self.try_recv()
+  }
+
+  // Whether a sender claimed the slot at `position` and died before filling it
+  fn is_abandoned(&self, position: usize) -> bool {
+    let slot = self.slot(position);
+    let claimed = unsafe { (*slot).sequence.load(Ordering::Acquire) } == free_sequence(position)
+      && self.internal().tail.0.load(Ordering::Acquire) != position;
+    let sender = unsafe { (*slot).sender.load(Ordering::Acquire) };
+    claimed && sender != 0 && !crate::gc::is_running(sender)
+  }
+
+  // Frees the slot at the head for the sender a lap later and moves on to the next one
+  fn free(&self, position: usize) {
+    let internal = self.internal();
+    let slot = self.slot(position);
+    unsafe {
+      (*slot).sender.store(0, Ordering::Relaxed);
+      let next_lap = position.wrapping_add(internal.capacity);
+      (*slot).sequence.store(free_sequence(next_lap), Ordering::Release);
+    }
+    internal.head.0.store(position.wrapping_add(1), Ordering::Relaxed);
+    wake_sender_waiting_for_room(internal, &*self.room);
+  }
+
+  fn internal(&self) -> &SharedQueueInternal {
+    unsafe { &*self.internal }
+  }
+
+  fn slot(&self, position: usize) -> *mut QueueSlot<T> {
+    unsafe { self.slots.add(position % self.internal().capacity) }
+  }
+}
+
+#[cfg(target_family = "unix")]
+impl<T> Selectable for SharedQueue<T>
+where
+  T: Copy,
+{
+  // Also when the slot at the head was abandoned, which receiving skips without waiting
+  fn is_ready(&self) -> bool {
+    let position = self.internal().head.0.load(Ordering::Relaxed);
+    let sequence = unsafe { (*self.slot(position)).sequence.load(Ordering::Acquire) };
+    sequence == filled_sequence(position) || self.is_abandoned(position)
+  }
+
+  fn doorbell(&self) -> Option<&Arc<Doorbell>> {
+    self.doorbell.as_ref()
+  }
+}
+
+// Counts a sender of a queue or ring in or out of those waiting for room. Like the receiver
+// waiting for values, a sender announcing that it waits tries once more before waiting.
+fn announce_waiting_for_room(internal: &SharedQueueInternal, waiting: bool) {
+  if waiting {
+    internal.senders_waiting.fetch_add(1, Ordering::SeqCst);
+    std::sync::atomic::fence(Ordering::SeqCst);
+  } else {
+    internal.senders_waiting.fetch_sub(1, Ordering::SeqCst);
+  }
+}
+
+// Called by the receiver of a queue or ring after it made room
+fn wake_sender_waiting_for_room(internal: &SharedQueueInternal, room: &dyn EventImpl) {
+  std::sync::atomic::fence(Ordering::SeqCst);
+  if internal.senders_waiting.load(Ordering::Relaxed) > 0 {
+    room.set(EventState::Signaled).unwrap();
+  }
+}
+
+// A ring buffer of up to `capacity` values from a single sender to a single receiver, for
+// values sent at a high rate like mouse positions or audio samples. Neither side takes a lock or
+// waits for the other: each only writes its own position and reads the other's, and remembers
+// the last position of the other so that it rarely touches its cache line. The sender only sets
+// the event when the receiver announced that it waits for it and the receiver only sets the room
+// event when the sender announced that it waits for room, like in `SharedQueue` whose internals
+// it shares. Sending from two handles at the same time, or receiving from two, breaks the ring.
+pub struct SharedRing<T>
+where
+  T: Copy,
+{
+  #[allow(dead_code)]
+  memory: Memory,
+  event: Box<dyn EventImpl>,
+  room: Box<dyn EventImpl>,
+  internal: *mut SharedQueueInternal,
+  values: *mut T,
+  // The positions of the other side when this handle last looked at them
+  known_head: Cell<usize>,
+  known_tail: usize,
+  // Rung after every value sent, see `crate::select`
+  #[cfg(target_family = "unix")]
+  doorbell: Option<Arc<Doorbell>>,
+}
+
+// Bytes of shared memory used by a ring of up to `capacity` values of `T`
+pub(crate) fn ring_size<T>(capacity: usize) -> usize {
+  Event::size_of(None) * 2
+    + (std::mem::align_of::<SharedQueueInternal>() - 1)
+    + std::mem::size_of::<SharedQueueInternal>()
+    + (std::mem::align_of::<T>() - 1)
+    + std::mem::size_of::<T>() * capacity
+}
+
+pub(crate) fn ring_in<T>(
+  memory: Memory,
+  capacity: usize,
+) -> Result<SharedRing<T>, Box<dyn std::error::Error>>
+where
+  T: Copy,
+{
+  if capacity == 0 {
+    return Err("A ring needs room for at least one value".into());
+  }
+  let base_ptr = memory.as_ptr();
+  let (event, event_size) = unsafe { event_in(base_ptr, memory.is_owner())? };
+  let (room, room_size) = unsafe { event_in(base_ptr.add(event_size), memory.is_owner())? };
+  let internal = queue_internal_ptr(base_ptr, event_size + room_size);
+  let values = unsafe { internal.add(1) } as *mut u8;
+  let values = unsafe { values.add(values.align_offset(std::mem::align_of::<T>())) } as *mut T;
+  if memory.is_owner() {
+    unsafe { internal.write(queue_internal(capacity)) };
+  } else if unsafe { (*internal).capacity } != capacity {
+    return Err("The ring has another capacity in the other process".into());
+  }
+  let (known_head, known_tail) = unsafe {
+    (
+      (*internal).head.0.load(Ordering::Acquire),
+      (*internal).tail.0.load(Ordering::Acquire),
+    )
+  };
+  Ok(SharedRing {
+    memory,
+    event,
+    room,
+    internal,
+    values,
+    known_head: Cell::new(known_head),
+    known_tail,
+    #[cfg(target_family = "unix")]
+    doorbell: None,
   })
 }
 
-pub struct SharedChannel<T>
+impl<T> SharedRing<T>
+where
+  T: Copy,
+{
+  // Sends a copy of `data`, returns it in `Full` if the ring is full
+  pub fn try_send(&mut self, data: &T) -> Result<(), Full<T>> {
+    self.try_push(data)
+  }
+
+  // Sends a copy of `data`, waiting for the receiver to make room if the ring is full
+  pub fn send(&mut self, data: &T) {
+    let _ = self.send_before(data, None);
+  }
+
+  // Sends a copy of `data`, waiting up to `timeout` for the receiver to make room if the ring is
+  // full
+  pub fn send_timeout(&mut self, data: &T, timeout: std::time::Duration) -> Result<(), Full<T>> {
+    self.send_before(data, Some(std::time::Instant::now() + timeout))
+  }
+
+  // Sends a copy of `data` without waiting. If the ring is full the value is dropped instead,
+  // counted in `dropped()` and returned, like `SharedQueue::force_send()`.
+  pub fn force_send(&mut self, data: &T) -> Option<T> {
+    let Full(value) = self.try_push(data).err()?;
+    unsafe { &*self.internal }.dropped.fetch_add(1, Ordering::Relaxed);
+    Some(value)
+  }
+
+  fn try_push(&self, data: &T) -> Result<(), Full<T>> {
+    let internal = unsafe { &*self.internal };
+    let tail = internal.tail.0.load(Ordering::Relaxed);
+    if tail.wrapping_sub(self.known_head.get()) >= internal.capacity {
+      self.known_head.set(internal.head.0.load(Ordering::Acquire));
+      if tail.wrapping_sub(self.known_head.get()) >= internal.capacity {
+        return Err(Full(*data));
+      }
+    }
+    unsafe { self.values.add(tail % internal.capacity).write(*data) };
+    internal.tail.0.store(tail.wrapping_add(1), Ordering::Release);
+    std::sync::atomic::fence(Ordering::SeqCst);
+    if internal.receiver_waiting.load(Ordering::Relaxed)
+      && internal.receiver_waiting.swap(false, Ordering::SeqCst)
+    {
+      self.event.set(EventState::Signaled).unwrap();
+    }
+    #[cfg(target_family = "unix")]
+    ring(&self.doorbell);
+    Ok(())
+  }
+
+  pub fn try_recv(&mut self) -> Option<T> {
+    let internal = unsafe { &*self.internal };
+    let head = internal.head.0.load(Ordering::Relaxed);
+    if head == self.known_tail {
+      self.known_tail = internal.tail.0.load(Ordering::Acquire);
+      if head == self.known_tail {
+        return None;
+      }
+    }
+    let value = unsafe { self.values.add(head % internal.capacity).read() };
+    internal.head.0.store(head.wrapping_add(1), Ordering::Release);
+    wake_sender_waiting_for_room(internal, &*self.room);
+    Some(value)
+  }
+
+  pub fn recv(&mut self) -> T {
+    loop {
+      if let Some(value) = self.try_recv_or_announce_wait() {
+        return value;
+      }
+      wait_for_event(&*self.event);
+    }
+  }
+
+  // Waits up to `timeout` for a value
+  pub fn recv_timeout(&mut self, timeout: std::time::Duration) -> Option<T> {
+    let deadline = std::time::Instant::now() + timeout;
+    loop {
+      if let Some(value) = self.try_recv_or_announce_wait() {
+        return Some(value);
+      }
+      let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+      if remaining.as_nanos() == 0 {
+        return None;
+      }
+      match self.event.wait(Timeout::Val(remaining)) {
+        Ok(()) | Err(raw_sync::Error::TimedOut) => {}
+        Err(e) => panic!("Waiting for the ring failed: {}", e),
+      }
+    }
+  }
+
+  // Number of values the ring holds before sending has to wait
+  pub fn capacity(&self) -> usize {
+    unsafe { (*self.internal).capacity }
+  }
+
+  // Number of values `force_send()` dropped
+  pub fn dropped(&self) -> u64 {
+    unsafe { (*self.internal).dropped.load(Ordering::Relaxed) }
+  }
+
+  // Rings `doorbell` after every value sent, so that a `Select` can wait for the handle
+  #[cfg(target_family = "unix")]
+  pub fn with_doorbell(mut self, doorbell: Arc<Doorbell>) -> Self {
+    self.doorbell = Some(doorbell);
+    self
+  }
+
+  fn send_before(&self, data: &T, deadline: Option<std::time::Instant>) -> Result<(), Full<T>> {
+    send_waiting(&*self.room, deadline, || self.try_push(data), |waiting| {
+      announce_waiting_for_room(unsafe { &*self.internal }, waiting)
+    })
+  }
+
+  // Like `SharedQueue::try_recv_or_announce_wait()`
+  fn try_recv_or_announce_wait(&mut self) -> Option<T> {
+    if let Some(value) = self.try_recv() {
+      return Some(value);
+    }
+    unsafe { &*self.internal }
+      .receiver_waiting
+      .store(true, Ordering::SeqCst);
+    std::sync::atomic::fence(Ordering::SeqCst);
+    self.try_recv()
+  }
+}
+
+#[cfg(target_family = "unix")]
+impl<T> Selectable for SharedRing<T>
+where
+  T: Copy,
+{
+  fn is_ready(&self) -> bool {
+    let internal = unsafe { &*self.internal };
+    internal.head.0.load(Ordering::Relaxed) != internal.tail.0.load(Ordering::Acquire)
+  }
+
+  fn doorbell(&self) -> Option<&Arc<Doorbell>> {
+    self.doorbell.as_ref()
+  }
+}
+
+// Blocks of `block_len` values for sending large payloads, e.g. images or meshes, without
+// copying them through a channel. The sender allocates a block, writes into it in place and sends
+// the `PoolBlock` referring to it through a channel, the receiver gets the block from its own
+// handle of the pool, which frees it when the receiver is done with it.
+//
+// Every block has a state word telling what it is used for, by which process, and how often it
+// was freed. Allocating, sending, getting and freeing a block move it from one state to the next
+// with a compare-and-swap, so that neither takes a lock and a `PoolBlock` received twice, or kept
+// after its block was freed, can't give access to the block again. Blocks held or sent by a
+// process that died, e.g. a reloadable process that was restarted, are reclaimed when the
+// reloadable process opens the pool again and when no block is free. Blocks sent to a process that
+// died after receiving the `PoolBlock` but before getting the block stay allocated until the
+// sender gives them back with `release()`.
+pub struct SharedPool<T>
+where
+  T: Copy,
+{
+  #[allow(dead_code)]
+  memory: Memory,
+  internal: *mut SharedPoolInternal,
+  // The state of every block, see `block_state()`
+  states: *const AtomicU64,
+  values: *mut T,
+}
+
+// Threads of a process allocate from a shared handle, the blocks they get are theirs alone
+unsafe impl<T> Sync for SharedPool<T> where T: Copy + Send {}
+
+// Aligned to a cache line like `SharedChannelInternal`, followed by the states and the blocks
+#[repr(C, align(64))]
+struct SharedPoolInternal {
+  block_len: usize,
+  blocks: usize,
+  // Where allocating starts looking for a free block, after the last allocated one
+  next_block: AtomicUsize,
+}
+
+// What a block is used for, stored in its state
+const BLOCK_FREE: u64 = 0;
+// Allocated and written by the process in the state
+const BLOCK_WRITING: u64 = 1;
+// Sent by the process in the state, not received yet
+const BLOCK_SHARED: u64 = 2;
+// Received and read by the process in the state
+const BLOCK_RECEIVED: u64 = 3;
+
+// Bits of the generation of a block, counting how often it was freed
+const GENERATION_BITS: u32 = 30;
+
+// The state of a block: its generation, what it is used for and the process using it
+fn block_state(generation: u32, usage: u64, pid: u32) -> u64 {
+  ((generation as u64) << 34) | (usage << 32) | pid as u64
+}
+
+fn block_generation(state: u64) -> u32 {
+  (state >> 34) as u32
+}
+
+fn block_usage(state: u64) -> u64 {
+  (state >> 32) & 3
+}
+
+fn block_holder(state: u64) -> u32 {
+  state as u32
+}
+
+// The state of the block once freed, in its next generation
+fn freed(state: u64) -> u64 {
+  let generation = (block_generation(state) + 1) & ((1 << GENERATION_BITS) - 1);
+  block_state(generation, BLOCK_FREE, 0)
+}
+
+// Refers to a block of a `SharedPool` holding `len` values, sent to the other process through a
+// channel. Only the first `SharedPool::get()` of it gets the block.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PoolBlock {
+  index: u32,
+  len: u32,
+  generation: u32,
+}
+
+impl PoolBlock {
+  pub fn len(&self) -> usize {
+    self.len as usize
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+}
+
+// Bytes of shared memory used by a pool of `blocks` blocks of `block_len` values of `T`
+pub(crate) fn pool_size<T>(block_len: usize, blocks: usize) -> usize {
+  std::mem::size_of::<SharedPoolInternal>()
+    + std::mem::size_of::<AtomicU64>() * blocks
+    + (std::mem::align_of::<T>() - 1)
+    + std::mem::size_of::<T>() * block_len * blocks
+}
+
+pub(crate) fn pool_in<T>(
+  memory: Memory,
+  block_len: usize,
+  blocks: usize,
+) -> Result<SharedPool<T>, Box<dyn std::error::Error>>
+where
+  T: Copy,
+{
+  if block_len == 0 || blocks == 0 {
+    return Err("A pool needs at least one block of at least one value".into());
+  }
+  if block_len > u32::MAX as usize || blocks > u32::MAX as usize {
+    return Err("A pool has at most 2^32 - 1 blocks of 2^32 - 1 values".into());
+  }
+  let internal = memory.as_ptr() as *mut SharedPoolInternal;
+  let states = unsafe { internal.add(1) } as *mut AtomicU64;
+  let values = unsafe { states.add(blocks) } as *mut u8;
+  let values = unsafe { values.add(values.align_offset(std::mem::align_of::<T>())) } as *mut T;
+  if memory.is_owner() {
+    unsafe {
+      internal.write(SharedPoolInternal {
+        block_len,
+        blocks,
+        next_block: AtomicUsize::new(0),
+      });
+      for index in 0..blocks {
+        states.add(index).write(AtomicU64::new(block_state(0, BLOCK_FREE, 0)));
+      }
+    }
+  } else if unsafe { ((*internal).block_len, (*internal).blocks) } != (block_len, blocks) {
+    return Err("The pool has other blocks in the other process".into());
+  }
+  let pool = SharedPool {
+    memory,
+    internal,
+    states,
+    values,
+  };
+  if !pool.memory.is_owner() {
+    // The blocks of the reloadable process this one replaces
+    pool.reclaim();
+  }
+  Ok(pool)
+}
+
+// Peeks at the state of the pool at `base_ptr` without knowing the type of its values. Also
+// returns where the blocks start.
+pub(crate) unsafe fn pool_state(base_ptr: *mut u8) -> (PoolState, *const u8) {
+  let internal = &*(base_ptr as *const SharedPoolInternal);
+  let states = (internal as *const SharedPoolInternal).add(1) as *const AtomicU64;
+  let allocated = (0..internal.blocks)
+    .filter(|index| block_usage((*states.add(*index)).load(Ordering::Acquire)) != BLOCK_FREE)
+    .count();
+  let state = PoolState {
+    allocated,
+    blocks: internal.blocks,
+    block_len: internal.block_len,
+  };
+  (state, states.add(internal.blocks) as *const u8)
+}
+
+impl<T> SharedPool<T>
+where
+  T: Copy,
+{
+  // Allocates a free block, `None` if all of them are allocated by running processes
+  pub fn try_alloc(&self) -> Option<PoolBlockMut<'_, T>> {
+    self
+      .claim_free_block()
+      .or_else(|| (self.reclaim() > 0).then(|| self.claim_free_block()).flatten())
+  }
+
+  // Allocates a free block, waiting up to `timeout` for the other process to free one if all of
+  // them are allocated. Yields to the other process at first, then sleeps between attempts so
+  // that waiting doesn't keep a core busy.
+  pub fn alloc_timeout(&self, timeout: std::time::Duration) -> Option<PoolBlockMut<'_, T>> {
+    const YIELDS: u32 = 100;
+    const SLEEP: std::time::Duration = std::time::Duration::from_micros(100);
+    let deadline = std::time::Instant::now() + timeout;
+    let mut attempts = 0;
+    loop {
+      if let Some(block) = self.try_alloc() {
+        return Some(block);
+      }
+      let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+      if remaining.as_nanos() == 0 {
+        return None;
+      }
+      if attempts < YIELDS {
+        std::thread::yield_now();
+      } else {
+        std::thread::sleep(remaining.min(SLEEP));
+      }
+      attempts += 1;
+    }
+  }
+
+  // The block the other process sent, freed when the returned view is dropped. `None` if the
+  // block was already received or released, or `block` doesn't belong to this pool.
+  pub fn get(&self, block: PoolBlock) -> Option<PoolBlockRef<'_, T>> {
+    let index = block.index as usize;
+    if index >= self.internal().blocks || block.len() > self.internal().block_len {
+      return None;
+    }
+    let state = self.state(index);
+    let current = state.load(Ordering::Acquire);
+    if block_usage(current) != BLOCK_SHARED || block_generation(current) != block.generation {
+      return None;
+    }
+    let received = block_state(block.generation, BLOCK_RECEIVED, std::process::id());
+    state
+      .compare_exchange(current, received, Ordering::Acquire, Ordering::Relaxed)
+      .ok()?;
+    Some(PoolBlockRef {
+      pool: self,
+      index,
+      len: block.len(),
+    })
+  }
+
+  // Frees a block that was sent but never received, e.g. because sending its `PoolBlock` failed
+  // or the receiver is gone. Returns whether it did, `false` once the block was received.
+  pub fn release(&self, block: PoolBlock) -> bool {
+    let index = block.index as usize;
+    if index >= self.internal().blocks {
+      return false;
+    }
+    let state = self.state(index);
+    let current = state.load(Ordering::Acquire);
+    block_usage(current) == BLOCK_SHARED
+      && block_generation(current) == block.generation
+      && state
+        .compare_exchange(current, freed(current), Ordering::AcqRel, Ordering::Relaxed)
+        .is_ok()
+  }
+
+  // Frees the blocks allocated, sent or received by processes that are gone, e.g. a reloadable
+  // process killed while it held them, and returns how many. A block sent by a process that is
+  // gone can't be received anymore. Processes are only checked on Linux.
+  pub fn reclaim(&self) -> usize {
+    (0..self.internal().blocks)
+      .filter(|index| {
+        let state = self.state(*index);
+        let current = state.load(Ordering::Acquire);
+        block_usage(current) != BLOCK_FREE
+          && !crate::gc::is_running(block_holder(current))
+          && state
+            .compare_exchange(current, freed(current), Ordering::AcqRel, Ordering::Relaxed)
+            .is_ok()
+      })
+      .count()
+  }
+
+  // Number of values in a block
+  pub fn block_len(&self) -> usize {
+    self.internal().block_len
+  }
+
+  // Number of blocks, allocated or not
+  pub fn blocks(&self) -> usize {
+    self.internal().blocks
+  }
+
+  // Moves a free block to this process, starting after the last allocated one
+  fn claim_free_block(&self) -> Option<PoolBlockMut<'_, T>> {
+    let blocks = self.internal().blocks;
+    let start = self.internal().next_block.load(Ordering::Relaxed) % blocks;
+    let pid = std::process::id();
+    (start..blocks).chain(0..start).find_map(|index| {
+      let state = self.state(index);
+      let current = state.load(Ordering::Relaxed);
+      let writing = block_state(block_generation(current), BLOCK_WRITING, pid);
+      if block_usage(current) != BLOCK_FREE
+        || state
+          .compare_exchange(current, writing, Ordering::Acquire, Ordering::Relaxed)
+          .is_err()
+      {
+        return None;
+      }
+      self.internal().next_block.store(index + 1, Ordering::Relaxed);
+      Some(PoolBlockMut {
+        pool: self,
+        index,
+        generation: block_generation(current),
+      })
+    })
+  }
+
+  fn internal(&self) -> &SharedPoolInternal {
+    unsafe { &*self.internal }
+  }
+
+  fn state(&self, index: usize) -> &AtomicU64 {
+    unsafe { &*self.states.add(index) }
+  }
+
+  fn block_ptr(&self, index: usize) -> *mut T {
+    unsafe { self.values.add(index * self.internal().block_len) }
+  }
+
+  // Frees a block this process writes or received. No other process changes its state
+  // meanwhile, reclaiming only takes the blocks of processes that are gone.
+  fn free(&self, index: usize) {
+    let state = self.state(index);
+    let current = state.load(Ordering::Relaxed);
+    debug_assert_eq!(block_holder(current), std::process::id());
+    state.store(freed(current), Ordering::Release);
+  }
+}
+
+// A block allocated by this process, freed when dropped unless it was sent with `share()`
+pub struct PoolBlockMut<'a, T>
+where
+  T: Copy,
+{
+  pool: &'a SharedPool<T>,
+  index: usize,
+  generation: u32,
+}
+
+impl<'a, T> PoolBlockMut<'a, T>
 where
   T: Copy,
 {
-  memory: SharedMemoryWithEventAndMutex<SharedChannelInternal<T>>,
+  // Hands the block over to the process receiving the returned `PoolBlock`, which sees the
+  // first `len` values
+  pub fn share(self, len: usize) -> PoolBlock {
+    assert!(len <= self.pool.block_len(), "The block is shorter than that");
+    let shared = block_state(self.generation, BLOCK_SHARED, std::process::id());
+    self.pool.state(self.index).store(shared, Ordering::Release);
+    let block = PoolBlock {
+      index: self.index as u32,
+      len: len as u32,
+      generation: self.generation,
+    };
+    std::mem::forget(self);
+    block
+  }
 }
 
-struct SharedChannelInternal<T> {
-  value: Option<T>,
+impl<'a, T> std::ops::Deref for PoolBlockMut<'a, T>
+where
+  T: Copy,
+{
+  type Target = [T];
+
+  fn deref(&self) -> &[T] {
+    unsafe { std::slice::from_raw_parts(self.pool.block_ptr(self.index), self.pool.block_len()) }
+  }
 }
 
-impl<T> SharedChannel<T>
+impl<'a, T> std::ops::DerefMut for PoolBlockMut<'a, T>
 where
   T: Copy,
 {
-  pub fn try_recv(&mut self) -> Option<T> {
-    let mut internal = self.memory.get();
-    if let Some(value) = internal.value {
-      internal.value = None;
-      self.memory.event.set(EventState::Clear).unwrap();
-      return Some(value);
+  fn deref_mut(&mut self) -> &mut [T] {
+    unsafe {
+      std::slice::from_raw_parts_mut(self.pool.block_ptr(self.index), self.pool.block_len())
     }
-    None
   }
+}
 
-  pub fn recv(&mut self) -> T {
-    #[cfg(target_family = "unix")]
-    {
-      let is_being_killed = Arc::new(AtomicBool::new(false));
-      let is_being_killed_for_handler = Arc::clone(&is_being_killed);
-      let hook_id = unsafe {
-        signal_hook::low_level::register(signal_hook::consts::signal::SIGTERM, move || {
-          is_being_killed_for_handler.store(true, Ordering::Relaxed);
-        })
-        .unwrap()
-      };
-      while matches!(
-        self
-          .memory
-          .event
-          .wait_allow_spurious_wake_up(Timeout::Infinite)
-          .unwrap(),
-        EventState::Clear
-      ) {
-        if is_being_killed.load(Ordering::Relaxed) {
-          signal_hook::low_level::unregister(hook_id);
-          std::process::abort();
-        }
+impl<'a, T> Drop for PoolBlockMut<'a, T>
+where
+  T: Copy,
+{
+  fn drop(&mut self) {
+    self.pool.free(self.index);
+  }
+}
+
+// A block received from the other process, freed when dropped
+pub struct PoolBlockRef<'a, T>
+where
+  T: Copy,
+{
+  pool: &'a SharedPool<T>,
+  index: usize,
+  len: usize,
+}
+
+impl<'a, T> std::ops::Deref for PoolBlockRef<'a, T>
+where
+  T: Copy,
+{
+  type Target = [T];
+
+  fn deref(&self) -> &[T] {
+    unsafe { std::slice::from_raw_parts(self.pool.block_ptr(self.index), self.len) }
+  }
+}
+
+impl<'a, T> Drop for PoolBlockRef<'a, T>
+where
+  T: Copy,
+{
+  fn drop(&mut self) {
+    self.pool.free(self.index);
+  }
+}
+
+// Wakes the `Select`s waiting for the handle of `doorbell`, if it has one
+#[cfg(target_family = "unix")]
+fn ring(doorbell: &Option<Arc<Doorbell>>) {
+  if let Some(doorbell) = doorbell {
+    doorbell.ring();
+  }
+}
+
+// Blocks until `event` is signaled, aborting if the process is asked to terminate meanwhile
+fn wait_for_event(event: &dyn EventImpl) {
+  #[cfg(target_family = "unix")]
+  {
+    let is_being_killed = Arc::new(AtomicBool::new(false));
+    let is_being_killed_for_handler = Arc::clone(&is_being_killed);
+    let hook_id = unsafe {
+      signal_hook::low_level::register(signal_hook::consts::signal::SIGTERM, move || {
+        is_being_killed_for_handler.store(true, Ordering::Relaxed);
+      })
+      .unwrap()
+    };
+    while matches!(
+      event
+        .wait_allow_spurious_wake_up(Timeout::Infinite)
+        .unwrap(),
+      EventState::Clear
+    ) {
+      if is_being_killed.load(Ordering::Relaxed) {
+        signal_hook::low_level::unregister(hook_id);
+        std::process::abort();
       }
     }
-    #[cfg(not(target_family = "unix"))]
-    {
-      self.memory.event.wait(Timeout::Infinite).unwrap();
+    signal_hook::low_level::unregister(hook_id);
+  }
+  #[cfg(not(target_family = "unix"))]
+  {
+    event.wait(Timeout::Infinite).unwrap();
+  }
+}
+
+// Calls from one process answered by the other, made of a channel of requests and a channel of
+// responses holding one message each. Every request carries an id which the response repeats,
+// so the caller discards responses to calls that timed out, e.g. from a reloadable process that
+// was killed while answering.
+pub struct SharedRpc<Req, Resp>
+where
+  Req: Copy,
+  Resp: Copy,
+{
+  requests: SharedChannel<RpcMessage<Req>>,
+  responses: SharedChannel<RpcMessage<Resp>>,
+  next_id: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(crate) struct RpcMessage<T> {
+  id: u64,
+  value: T,
+}
+
+impl<Req, Resp> SharedRpc<Req, Resp>
+where
+  Req: Copy,
+  Resp: Copy,
+{
+  pub(crate) fn new(
+    requests: SharedChannel<RpcMessage<Req>>,
+    responses: SharedChannel<RpcMessage<Resp>>,
+  ) -> Self {
+    SharedRpc {
+      requests,
+      responses,
+      // Random, so that a restarted caller doesn't reuse the ids of the calls it made before
+      next_id: rand::random(),
+    }
+  }
+
+  // Sends `request` to the other process and waits up to `timeout` for its response. Returns
+  // `None` if it didn't come in time.
+  pub fn call(&mut self, request: &Req, timeout: std::time::Duration) -> Option<Resp> {
+    let id = self.next_id;
+    self.next_id = self.next_id.wrapping_add(1);
+    // Replaces the request of an earlier call that timed out before it was served
+    self.requests.force_send(&RpcMessage {
+      id,
+      value: *request,
+    });
+    let deadline = std::time::Instant::now() + timeout;
+    loop {
+      let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+      let response = self.responses.recv_timeout(remaining)?;
+      // Otherwise it is the late response to an earlier call
+      if response.id == id {
+        return Some(response.value);
+      }
     }
-    self.try_recv().unwrap()
   }
 
-  pub fn send(&mut self, data: &T)
+  // Waits for a request and answers it with `handler`
+  pub fn serve<F>(&mut self, handler: F)
   where
-    T: Copy,
+    F: FnOnce(&Req) -> Resp,
   {
-    let internal = self.memory.get();
-    internal.value = Some(*data);
-    self.memory.event.set(EventState::Signaled).unwrap();
+    let request = self.requests.recv();
+    self.respond(request, handler);
+  }
+
+  // Answers the pending request with `handler` if there is one, without waiting. Returns whether
+  // there was a request.
+  pub fn try_serve<F>(&mut self, handler: F) -> bool
+  where
+    F: FnOnce(&Req) -> Resp,
+  {
+    match self.requests.try_recv() {
+      Some(request) => {
+        self.respond(request, handler);
+        true
+      }
+      None => false,
+    }
+  }
+
+  fn respond<F>(&mut self, request: RpcMessage<Req>, handler: F)
+  where
+    F: FnOnce(&Req) -> Resp,
+  {
+    let value = handler(&request.value);
+    // Replaces the response to an earlier call the caller gave up on
+    self.responses.force_send(&RpcMessage {
+      id: request.id,
+      value,
+    });
+  }
+}
+
+// Ready when a request waits to be served
+#[cfg(target_family = "unix")]
+impl<Req, Resp> Selectable for SharedRpc<Req, Resp>
+where
+  Req: Copy,
+  Resp: Copy,
+{
+  fn is_ready(&self) -> bool {
+    self.requests.is_ready()
+  }
+
+  fn doorbell(&self) -> Option<&Arc<Doorbell>> {
+    self.requests.doorbell()
+  }
+}
+
+// A channel where every subscriber receives each value, unlike `SharedChannel` which only one
+// receiver can wait on. Values are kept in a ring buffer of `capacity` slots numbered by the
+// count of values sent, and every handle keeps the number of the next value it receives. A
+// subscriber starts with the values sent after it subscribed, one falling behind by more than
+// `capacity` values misses the oldest ones.
+#[cfg(target_family = "unix")]
+pub struct SharedBroadcast<T>
+where
+  T: Copy,
+{
+  #[allow(dead_code)]
+  memory: Memory,
+  mutex: ShmMutex<SharedBroadcastInternal>,
+  // Notified of every value sent, placed after the values
+  condvar: Box<dyn CondvarImpl>,
+  values: *mut T,
+  next: u64,
+  missed: u64,
+  // Rung after every value sent, see `crate::select`
+  doorbell: Option<Arc<Doorbell>>,
+}
+
+// The mutex and internals come first, so that tools which don't know the type of the values
+// (nor the size of the condition variable) can find them, like for `SharedChannelInternal`
+#[repr(C, align(64))]
+struct SharedBroadcastInternal {
+  sent: u64,
+  capacity: usize,
+}
+
+// Bytes of shared memory used by a broadcast of `T` keeping `capacity` values
+#[cfg(target_family = "unix")]
+pub(crate) fn broadcast_size<T>(capacity: usize) -> usize {
+  ShmMutex::<SharedBroadcastInternal>::size_of(None)
+    + (std::mem::align_of::<T>() - 1)
+    + std::mem::size_of::<T>() * capacity
+    + (std::mem::align_of::<u64>() - 1)
+    + Condvar::size_of(None)
+}
+
+#[cfg(target_family = "unix")]
+pub(crate) fn broadcast_in<T>(
+  memory: Memory,
+  capacity: usize,
+) -> Result<SharedBroadcast<T>, Box<dyn std::error::Error>>
+where
+  T: Copy,
+{
+  if capacity == 0 {
+    return Err("A broadcast needs room for at least one value".into());
+  }
+  let base_ptr = memory.as_ptr();
+  let (mutex, mutex_size) = if memory.is_owner() {
+    let internal = SharedBroadcastInternal { sent: 0, capacity };
+    unsafe { ShmMutex::new_with_options(base_ptr, internal, checked_mutex())? }
+  } else {
+    unsafe { ShmMutex::from_existing(base_ptr)? }
+  };
+  let internal = mutex.lock()?;
+  if internal.capacity != capacity {
+    return Err("The broadcast has another capacity in the other process".into());
+  }
+  let sent = internal.sent;
+  drop(internal);
+  let values = unsafe { base_ptr.add(mutex_size) };
+  let values = unsafe { values.add(values.align_offset(std::mem::align_of::<T>())) } as *mut T;
+  let condvar_ptr = unsafe { values.add(capacity) } as *mut u8;
+  let condvar_ptr =
+    unsafe { condvar_ptr.add(condvar_ptr.align_offset(std::mem::align_of::<u64>())) };
+  let (condvar, _) = if memory.is_owner() {
+    unsafe { Condvar::new(condvar_ptr)? }
+  } else {
+    unsafe { Condvar::from_existing(condvar_ptr)? }
+  };
+  Ok(SharedBroadcast {
+    memory,
+    mutex,
+    condvar,
+    values,
+    next: sent,
+    missed: 0,
+    doorbell: None,
+  })
+}
+
+// Peeks at the state of the broadcast at `base_ptr` without knowing the type of its values,
+// taking the lock for an instant. Also returns where the values start.
+pub(crate) unsafe fn broadcast_state(
+  base_ptr: *mut u8,
+) -> Result<(BroadcastState, *const u8), Box<dyn std::error::Error>> {
+  let internal_ptr = channel_internal_ptr(base_ptr);
+  let lock_state = lock_state(base_ptr, internal_ptr)?;
+  let internal = &*(internal_ptr as *const SharedBroadcastInternal);
+  let state = BroadcastState {
+    lock: lock_state,
+    sent: internal.sent,
+    capacity: internal.capacity,
+  };
+  Ok((
+    state,
+    internal_ptr.add(std::mem::size_of::<SharedBroadcastInternal>()),
+  ))
+}
+
+#[cfg(target_family = "unix")]
+impl<T> SharedBroadcast<T>
+where
+  T: Copy,
+{
+  // Sends a copy of `data` to every subscriber, overwriting the oldest value
+  pub fn send(&mut self, data: &T) {
+    let mut internal = self.mutex.lock().unwrap();
+    let slot = (internal.sent % internal.capacity as u64) as usize;
+    unsafe { self.values.add(slot).write(*data) };
+    internal.sent += 1;
+    drop(internal);
+    self.condvar.notify_all().unwrap();
+    ring(&self.doorbell);
+  }
+
+  pub fn try_recv(&mut self) -> Option<T> {
+    self.recv_within(Timeout::Val(std::time::Duration::from_millis(0)))
+  }
+
+  pub fn recv(&mut self) -> T {
+    self.recv_within(Timeout::Infinite).unwrap()
+  }
+
+  // Waits up to `timeout` for a value
+  pub fn recv_timeout(&mut self, timeout: std::time::Duration) -> Option<T> {
+    self.recv_within(Timeout::Val(timeout))
+  }
+
+  // Number of values this subscriber missed because it fell behind by more than the capacity
+  pub fn missed(&self) -> u64 {
+    self.missed
+  }
+
+  // Number of values kept for subscribers that fall behind
+  pub fn capacity(&self) -> usize {
+    self.mutex.lock().unwrap().capacity
+  }
+
+  // Rings `doorbell` after every value sent, so that a `Select` can wait for the handle
+  pub fn with_doorbell(mut self, doorbell: Arc<Doorbell>) -> Self {
+    self.doorbell = Some(doorbell);
+    self
+  }
+
+  fn recv_within(&mut self, timeout: Timeout) -> Option<T> {
+    let next = self.next;
+    let guard = self.mutex.lock().unwrap().into_inner();
+    let (guard, _) = self
+      .condvar
+      .wait_timeout_while(guard, timeout, &mut |internal| unsafe {
+        (*(internal as *const SharedBroadcastInternal)).sent == next
+      })
+      .unwrap();
+    let internal = unsafe { ShmLockGuard::<SharedBroadcastInternal>::from_inner(guard) };
+    if internal.sent == self.next {
+      return None;
+    }
+    let oldest = internal.sent.saturating_sub(internal.capacity as u64);
+    if self.next < oldest {
+      self.missed += oldest - self.next;
+      self.next = oldest;
+    }
+    let slot = (self.next % internal.capacity as u64) as usize;
+    self.next += 1;
+    Some(unsafe { self.values.add(slot).read() })
+  }
+}
+
+#[cfg(target_family = "unix")]
+impl<T> Selectable for SharedBroadcast<T>
+where
+  T: Copy,
+{
+  fn is_ready(&self) -> bool {
+    self.mutex.lock().unwrap().sent != self.next
+  }
+
+  fn doorbell(&self) -> Option<&Arc<Doorbell>> {
+    self.doorbell.as_ref()
   }
 }
 
@@ -91,22 +1785,101 @@ pub trait SharedMemory<T: ?Sized> {
   fn get(&mut self) -> &mut T;
 }
 
-// This is a type-safe (but not synchronized) API for writing to shared memory.
-pub struct SharedMemorySimple<T> {
-  memory: Shmem,
+// The shared memory behind a value, slice or channel: either a mapping of its own or a part of
+// the arena holding all the fields of a state.
+pub(crate) enum Memory {
+  Own(Shmem),
+  InArena(ArenaRegion),
+}
+
+impl Memory {
+  fn as_ptr(&self) -> *mut u8 {
+    match self {
+      Memory::Own(memory) => memory.as_ptr(),
+      Memory::InArena(region) => region.as_ptr(),
+    }
+  }
+
+  fn len(&self) -> usize {
+    match self {
+      Memory::Own(memory) => memory.len(),
+      Memory::InArena(region) => region.len(),
+    }
+  }
+
+  fn is_owner(&self) -> bool {
+    match self {
+      Memory::Own(memory) => memory.is_owner(),
+      Memory::InArena(region) => region.is_owner(),
+    }
+  }
+
+  // The arena can't change size, so only memory of its own can be resized
+  fn remap_if_resized(&mut self) -> Result<bool, Box<dyn std::error::Error>> {
+    match self {
+      Memory::Own(memory) => Ok(memory.remap_if_resized()?),
+      Memory::InArena(_) => Ok(false),
+    }
+  }
+
+  fn resize(&mut self, size: usize) -> Result<(), Box<dyn std::error::Error>> {
+    match self {
+      Memory::Own(memory) => Ok(memory.resize(size)?),
+      Memory::InArena(_) => Err("Fields laid out in an arena can't be resized".into()),
+    }
+  }
+
+  // Fields in an arena share the memory options of the whole arena
+  fn set_memory_options(&mut self, options: MemoryOptions) -> MemoryOutcome {
+    match self {
+      Memory::Own(memory) => memory.set_memory_options(options),
+      Memory::InArena(region) => region.memory_outcome(),
+    }
+  }
+}
+
+// This is a type-safe (but not synchronized) API for writing to shared memory. With `ReadOnly`
+// access it only allows reading.
+pub struct SharedMemorySimple<T, A: Access = ReadWrite> {
+  memory: Memory,
   memory_type: std::marker::PhantomData<T>,
+  access: std::marker::PhantomData<A>,
 }
 
 impl<T> SharedMemory<T> for SharedMemorySimple<T> {
   fn get(&mut self) -> &mut T {
+    self.get()
+  }
+}
+
+impl<T> SharedMemorySimple<T> {
+  pub fn get(&mut self) -> &mut T {
     unsafe { &mut *(self.memory.as_ptr() as *mut T) }
   }
 }
 
-// This is a type-safe (but not synchronized) API for writing to shared memory.
-pub struct SharedMemorySlice<T> {
-  memory: Shmem,
+impl<T> SharedMemorySimple<T, ReadOnly> {
+  pub fn get(&self) -> &T {
+    unsafe { &*(self.memory.as_ptr() as *const T) }
+  }
+}
+
+impl<T, A: Access> SharedMemorySimple<T, A> {
+  pub(crate) fn new(memory: Memory) -> Self {
+    SharedMemorySimple {
+      memory,
+      memory_type: std::marker::PhantomData,
+      access: std::marker::PhantomData,
+    }
+  }
+}
+
+// This is a type-safe (but not synchronized) API for writing to shared memory. With `ReadOnly`
+// access it only allows reading.
+pub struct SharedMemorySlice<T, A: Access = ReadWrite> {
+  memory: Memory,
   memory_type: std::marker::PhantomData<T>,
+  access: std::marker::PhantomData<A>,
   pub length: usize,
 }
 
@@ -118,137 +1891,251 @@ impl<T> SharedMemory<[T]> for SharedMemorySlice<T> {
 
 impl<T> SharedMemorySlice<T> {
   pub fn get(&mut self) -> &mut [T] {
-    unsafe { std::slice::from_raw_parts_mut(&mut *(self.memory.as_ptr() as *mut T), self.length) }
+    self.remap_if_resized().unwrap();
+    unsafe { std::slice::from_raw_parts_mut(self.memory.as_ptr() as *mut T, self.length) }
+  }
+
+  // Changes the number of elements in the slice, only the owner can resize it. Added elements are
+  // zeroed, the other process sees the new length on its next `get()`.
+  pub fn resize(&mut self, length: usize) -> Result<(), Box<dyn std::error::Error>> {
+    self.memory.resize(std::mem::size_of::<T>() * length)?;
+    self.length = length;
+    Ok(())
+  }
+}
+
+impl<T> SharedMemorySlice<T, ReadOnly> {
+  pub fn get(&mut self) -> &[T] {
+    self.remap_if_resized().unwrap();
+    unsafe { std::slice::from_raw_parts(self.memory.as_ptr() as *const T, self.length) }
+  }
+}
+
+impl<T, A: Access> SharedMemorySlice<T, A> {
+  pub(crate) fn new(memory: Memory, length: usize) -> Self {
+    SharedMemorySlice {
+      memory,
+      memory_type: std::marker::PhantomData,
+      access: std::marker::PhantomData,
+      length,
+    }
+  }
+
+  // Asks for huge pages, pre-faulting or locking of the slice in this process, e.g. for large
+  // frame buffers. Returns what the system allowed.
+  pub fn set_memory_options(&mut self, options: MemoryOptions) -> MemoryOutcome {
+    self.memory.set_memory_options(options)
+  }
+
+  // Picks up the new length if the owner resized the slice, returns whether it did. `get()` calls
+  // it too and panics if remapping fails, call it first to handle the error instead.
+  pub fn remap_if_resized(&mut self) -> Result<bool, Box<dyn std::error::Error>> {
+    let resized = self.memory.remap_if_resized()?;
+    if resized {
+      self.length = self.memory.len() / std::mem::size_of::<T>();
+    }
+    Ok(resized)
   }
 }
 
 // This is a type-safe and synchronized API for writing to shared memory.
 pub struct SharedMemoryWithMutex<T> {
   #[allow(dead_code)]
-  memory: Shmem,
-  memory_type: std::marker::PhantomData<T>,
-  mutex: Box<dyn LockImpl>,
+  memory: Memory,
+  mutex: ShmMutex<T>,
 }
 
-impl<T> SharedMemory<T> for SharedMemoryWithMutex<T> {
-  fn get(&mut self) -> &mut T {
-    let guard = self.mutex.lock().unwrap();
-    unsafe { &mut *(*guard as *mut T) }
+impl<T> SharedMemoryWithMutex<T> {
+  pub fn lock(&self) -> ShmLockGuard<'_, T> {
+    self.mutex.lock().unwrap()
   }
 }
+
 pub struct SharedMemoryWithEventAndMutex<T> {
   #[allow(dead_code)]
-  memory: Shmem,
-  memory_type: std::marker::PhantomData<T>,
+  memory: Memory,
   event: Box<dyn EventImpl>,
-  mutex: Box<dyn LockImpl>,
+  mutex: ShmMutex<T>,
 }
 
-impl<T> SharedMemory<T> for SharedMemoryWithEventAndMutex<T> {
-  fn get(&mut self) -> &mut T {
-    let guard = self.mutex.lock().unwrap();
-    unsafe { &mut *(*guard as *mut T) }
+impl<T> SharedMemoryWithEventAndMutex<T> {
+  pub fn lock(&self) -> ShmLockGuard<'_, T> {
+    self.mutex.lock().unwrap()
   }
 }
 
 // Provides a shared memory between two processes, without synchronization, with a size known
 // at compile time.
-pub fn shared_memory<T>(
+pub fn shared_memory<T, A: Access>(
   is_owner: bool,
   identifier: &str,
-) -> Result<SharedMemorySimple<T>, Box<dyn std::error::Error>> {
-  Ok(SharedMemorySimple {
-    memory: get_shared_memory(is_owner, identifier, std::mem::size_of::<T>())?,
-    memory_type: std::marker::PhantomData,
-  })
+) -> Result<SharedMemorySimple<T, A>, Box<dyn std::error::Error>> {
+  Ok(SharedMemorySimple::new(Memory::Own(get_shared_memory(
+    is_owner,
+    identifier,
+    std::mem::size_of::<T>(),
+    A::WRITABLE,
+    segment_tag(ArenaFieldKind::Value, fingerprint::<T>()),
+  )?)))
 }
 
 // Provides a shared memory between two processes, without synchronization, with dynamic size.
-pub fn shared_memory_with_slice<T>(
+pub fn shared_memory_with_slice<T, A: Access>(
   is_owner: bool,
   identifier: &str,
   length: usize,
-) -> Result<SharedMemorySlice<T>, Box<dyn std::error::Error>> {
-  Ok(SharedMemorySlice {
-    memory: get_shared_memory(is_owner, identifier, std::mem::size_of::<T>() * length)?,
-    memory_type: std::marker::PhantomData,
+) -> Result<SharedMemorySlice<T, A>, Box<dyn std::error::Error>> {
+  Ok(SharedMemorySlice::new(
+    Memory::Own(get_shared_memory(
+      is_owner,
+      identifier,
+      std::mem::size_of::<T>() * length,
+      A::WRITABLE,
+      segment_tag(ArenaFieldKind::Slice, fingerprint::<T>()),
+    )?),
     length,
-  })
+  ))
 }
 
-// Provides a shared memory between two processes, with synchronization.
+// Provides a shared memory between two processes, with synchronization. The value starts out
+// zeroed.
 pub fn shared_memory_with_mutex<T>(
   is_owner: bool,
   identifier: &str,
 ) -> Result<SharedMemoryWithMutex<T>, Box<dyn std::error::Error>> {
-  let mutex_size = Mutex::size_of(None);
-  let memory = get_shared_memory(is_owner, identifier, mutex_size + std::mem::size_of::<T>())?;
-  let is_owner = memory.is_owner();
+  let memory = Memory::Own(get_shared_memory(
+    is_owner,
+    identifier,
+    ShmMutex::<T>::size_of(None),
+    true,
+    0,
+  )?);
   let base_ptr = memory.as_ptr();
-  let ptr = unsafe { base_ptr.add(Mutex::size_of(Some(base_ptr))) };
-  let (mutex, _) = if is_owner {
-    unsafe { Mutex::new(base_ptr, ptr)? }
+  let (mutex, _) = if memory.is_owner() {
+    unsafe { ShmMutex::new_in_place(base_ptr)? }
   } else {
-    unsafe { Mutex::from_existing(base_ptr, ptr)? }
+    unsafe { ShmMutex::from_existing(base_ptr)? }
   };
-  Ok(SharedMemoryWithMutex {
-    memory,
-    memory_type: std::marker::PhantomData,
-    mutex,
-  })
+  Ok(SharedMemoryWithMutex { memory, mutex })
 }
 
 pub fn shared_memory_with_event_and_mutex<T>(
   is_owner: bool,
   identifier: &str,
+  value: T,
 ) -> Result<SharedMemoryWithEventAndMutex<T>, Box<dyn std::error::Error>> {
-  let mutex_size = Mutex::size_of(None);
-  let event_size = Event::size_of(None);
-  let memory = get_shared_memory(
+  let memory = Memory::Own(get_shared_memory(
     is_owner,
     identifier,
-    event_size + mutex_size + std::mem::size_of::<T>(),
-  )?;
+    Event::size_of(None) + ShmMutex::<T>::size_of(None),
+    true,
+    0,
+  )?);
+  event_and_mutex_in(memory, value)
+}
+
+fn event_and_mutex_in<T>(
+  memory: Memory,
+  value: T,
+) -> Result<SharedMemoryWithEventAndMutex<T>, Box<dyn std::error::Error>> {
   let is_owner = memory.is_owner();
   let base_ptr = memory.as_ptr();
 
   let (event, event_size) = if is_owner {
-    // `true` because we don't support multiple concurrent receivers
+    // `true` because we don't support multiple concurrent receivers, `SharedBroadcast` does
     unsafe { Event::new(base_ptr, true) }
   } else {
     unsafe { Event::from_existing(base_ptr) }
   }?;
   let mutex_base_ptr = unsafe { base_ptr.add(event_size) };
-  let ptr = unsafe { mutex_base_ptr.add(Mutex::size_of(Some(mutex_base_ptr))) };
   let (mutex, _) = if is_owner {
-    unsafe { Mutex::new(base_ptr, ptr)? }
+    unsafe { ShmMutex::new_with_options(mutex_base_ptr, value, checked_mutex())? }
   } else {
-    unsafe { Mutex::from_existing(base_ptr, ptr)? }
+    unsafe { ShmMutex::from_existing(mutex_base_ptr)? }
   };
   Ok(SharedMemoryWithEventAndMutex {
     memory,
-    memory_type: std::marker::PhantomData,
     event,
     mutex,
   })
 }
 
-fn get_shared_memory(is_owner: bool, identifier: &str, size: usize) -> anyhow::Result<Shmem> {
-  if identifier.len() >= 32 {
-    return Err(anyhow!(
-      "Tried to create shared memory with identifier {}, \
-      which is too long (macOS limits to 32 characters)",
-      identifier
-    ));
+// Creates the event at `ptr` in the owner or opens it in the other process, returns it with the
+// bytes it takes
+unsafe fn event_in(ptr: *mut u8, is_owner: bool) -> raw_sync::Result<(Box<dyn EventImpl>, usize)> {
+  if is_owner {
+    Event::new(ptr, true)
+  } else {
+    Event::from_existing(ptr)
+  }
+}
+
+// Error checking, so that locking twice from the same thread panics instead of deadlocking or, with
+// the recursive mutexes of Windows, handing out a second `&mut`.
+fn checked_mutex() -> MutexOptions {
+  MutexOptions::default().kind(MutexKind::ErrorCheck)
+}
+
+// Identifiers starting with this prefix are paths of files backing the shared memory
+pub(crate) const FILE_PREFIX: &str = "file:";
+
+// The owner always creates the memory writable, `writable` only restricts the other process.
+// The other process checks that the memory was created with the same `tag` (0 when untagged).
+pub(crate) fn get_shared_memory(
+  is_owner: bool,
+  identifier: &str,
+  size: usize,
+  writable: bool,
+  tag: u64,
+) -> Result<Shmem, Box<dyn std::error::Error>> {
+  let memory = open_or_create_shared_memory(is_owner, identifier, size, writable, tag)?;
+  if memory.tag() != tag {
+    return Err(format!("{} holds another type in the other process", identifier).into());
+  }
+  Ok(memory)
+}
+
+fn open_or_create_shared_memory(
+  is_owner: bool,
+  identifier: &str,
+  size: usize,
+  writable: bool,
+  tag: u64,
+) -> Result<Shmem, Box<dyn std::error::Error>> {
+  let open_conf = if writable {
+    ShmemConf::new()
+  } else {
+    ShmemConf::new().read_only()
+  };
+  if let Some(path) = identifier.strip_prefix(FILE_PREFIX) {
+    return Ok(if is_owner {
+      // Fields of nested states are kept in subdirectories
+      if let Some(dir) = std::path::Path::new(path).parent() {
+        std::fs::create_dir_all(dir)?;
+      }
+      ShmemConf::new()
+        .size(size)
+        .name(identifier)
+        .tag(tag)
+        .file_backed(path)
+        .create()
+    } else {
+      open_conf.name(identifier).file_backed(path).open()
+    }?);
   }
+  // Identifiers can be longer than what macOS allows for an os_id, the os_id is a hash of the
+  // identifier, which is kept in the memory to detect collisions and for debugging
+  let os_id = os_id_for_name(crate::gc::SEGMENT_PREFIX, identifier);
   Ok(if is_owner {
     ShmemConf::new()
       .size(size)
-      .os_id(identifier)
+      .name(identifier)
+      .tag(tag)
+      .os_id(os_id)
       .force_create_flink()
       .create()
   } else {
-    ShmemConf::new().os_id(identifier).open()
+    open_conf.name(identifier).os_id(os_id).open()
   }?)
   // // The following code doesn't need to know who is first, but if owner quits without
   // // deleting the flink it will panic!
@@ -0,0 +1,39 @@
+use hot_reload::{Arena, ArenaField, ArenaFieldKind};
+
+#[test]
+fn reopening_an_arena_reads_back_its_table_of_contents() {
+  let fields = [
+    ArenaField::value::<u64>("counter"),
+    ArenaField::slice::<u16>("samples", 7),
+    ArenaField::queue::<u32>("events", 4),
+  ];
+  let arena = Arena::create("test-arena-reopen", &fields).unwrap();
+  let entries = arena.entries().to_vec();
+  let kinds: Vec<_> = entries.iter().map(|entry| entry.kind).collect();
+  assert_eq!(kinds, [ArenaFieldKind::Value, ArenaFieldKind::Slice, ArenaFieldKind::Queue]);
+  assert_eq!(entries[1].name, "samples");
+  assert_eq!(entries[1].size, 14);
+
+  let opened = std::thread::spawn(|| Arena::open("test-arena-reopen").unwrap().entries().to_vec());
+  assert_eq!(opened.join().unwrap(), entries);
+}
+
+#[test]
+fn restoring_a_snapshot_copies_the_values_back() {
+  let fields = [ArenaField::value::<u64>("counter"), ArenaField::queue::<u32>("events", 4)];
+  let snapshot = {
+    let arena = Arena::create("test-arena-restore", &fields).unwrap();
+    arena.snapshot()
+  };
+  let arena = Arena::create("test-arena-restore", &fields).unwrap();
+  // Queues are never restored
+  assert_eq!(arena.restore(&snapshot).unwrap(), 1);
+}
+
+#[test]
+fn a_snapshot_with_fields_past_its_end_is_refused() {
+  let fields = [ArenaField::value::<u64>("counter"), ArenaField::slice::<u8>("bytes", 64)];
+  let arena = Arena::create("test-arena-truncated", &fields).unwrap();
+  let snapshot = arena.snapshot();
+  assert!(arena.restore(&snapshot[..snapshot.len() - 1]).is_err());
+}
@@ -0,0 +1,47 @@
+use hot_reload::simple_shared_memory::*;
+use std::time::Duration;
+
+#[test]
+fn forcing_a_value_into_a_full_channel_drops_the_oldest_value() {
+  let mut channel = shared_channel::<u32>(true, "test-channel-drop", 2).unwrap();
+  assert_eq!(channel.force_send(&1), None);
+  assert_eq!(channel.force_send(&2), None);
+  assert_eq!(channel.force_send(&3), Some(1));
+  assert_eq!(channel.force_send(&4), Some(2));
+  assert_eq!(channel.try_send(&5), Err(Full(5)));
+  assert_eq!(channel.dropped(), 2);
+  assert_eq!(channel.try_recv(), Some(3));
+  assert_eq!(channel.try_recv(), Some(4));
+  assert_eq!(channel.try_recv(), None);
+}
+
+#[test]
+fn sending_with_a_timeout_waits_for_the_receiver_to_make_room() {
+  let mut channel = shared_channel::<u32>(true, "test-channel-room", 1).unwrap();
+  channel.send(&1);
+  assert_eq!(channel.send_timeout(&2, Duration::from_millis(10)), Err(Full(2)));
+
+  let receiver = std::thread::spawn(|| {
+    let mut channel = shared_channel::<u32>(false, "test-channel-room", 1).unwrap();
+    std::thread::sleep(Duration::from_millis(50));
+    (channel.recv(), channel.recv())
+  });
+  channel.send_timeout(&2, Duration::from_secs(10)).unwrap();
+  assert_eq!(receiver.join().unwrap(), (1, 2));
+  assert_eq!(channel.dropped(), 0);
+}
+
+#[test]
+fn sending_to_a_full_channel_waits_for_the_receiver() {
+  let mut channel = shared_channel::<u32>(true, "test-channel-wait", 1).unwrap();
+  channel.send(&1);
+
+  let receiver = std::thread::spawn(|| {
+    let mut channel = shared_channel::<u32>(false, "test-channel-wait", 1).unwrap();
+    std::thread::sleep(Duration::from_millis(50));
+    (channel.recv_timeout(Duration::from_secs(5)), channel.recv_timeout(Duration::from_secs(5)))
+  });
+  channel.send(&2);
+  assert_eq!(receiver.join().unwrap(), (Some(1), Some(2)));
+  assert_eq!(channel.dropped(), 0);
+}
@@ -0,0 +1,47 @@
+//! The fields of a state may be named like the locals of the code generated for it
+#![allow(dead_code)]
+
+use hot_reload::serde::*;
+
+#[derive(Serialize, Deserialize)]
+pub struct Arguments {
+    pub len: usize,
+}
+
+#[hot_reload::state(args = Arguments)]
+pub struct Nested {
+    pub prefix: u32,
+    pub arguments: u32,
+    pub name: u32,
+    pub fields: u32,
+    pub elements: u32,
+    pub index: u32,
+    #[slice(len = |arguments: &Arguments| arguments.len)]
+    pub tiles: [u8],
+    #[slice(len = 2)]
+    pub tiles_size: [u8],
+    #[channel(capacity = |arguments: &Arguments| arguments.len)]
+    #[array]
+    pub channels: [u8; 2],
+}
+
+#[hot_reload::state(reloadable = "field-names", args = Arguments, arena)]
+pub struct State {
+    pub prefix: u32,
+    pub arguments: u32,
+    pub name: u32,
+    pub fields: u32,
+    #[slice(len = |arguments: &Arguments| arguments.len)]
+    pub tiles: [u8],
+    #[ring(capacity = 4)]
+    pub tiles_size: u8,
+    #[nested]
+    pub nested: Nested,
+}
+
+#[test]
+fn fields_named_like_generated_locals() {
+    let mut fields = Vec::new();
+    <Nested as hot_reload::SharedFields>::layout("nested/", &Arguments { len: 3 }, &mut fields);
+    assert_eq!(fields.len(), 10);
+}
@@ -0,0 +1,62 @@
+use hot_reload::simple_shared_memory::*;
+use std::time::Duration;
+
+#[test]
+fn a_block_is_only_received_once() {
+  let pool = shared_pool::<u8>(true, "test-pool-once", 4, 2).unwrap();
+  let mut block = pool.try_alloc().unwrap();
+  block[0] = 7;
+  let sent = block.share(1);
+
+  let received = pool.get(sent).unwrap();
+  assert_eq!(&*received, &[7]);
+  assert!(pool.get(sent).is_none());
+  drop(received);
+  // The block was freed, an old `PoolBlock` doesn't get it back once it is sent again
+  let again = pool.try_alloc().unwrap().share(1);
+  let _ = pool.try_alloc().unwrap().share(1);
+  assert!(pool.get(sent).is_none());
+  assert!(pool.get(again).is_some());
+}
+
+#[test]
+fn allocating_from_a_full_pool_gives_up() {
+  let pool = shared_pool::<u8>(true, "test-pool-full", 4, 2).unwrap();
+  let first = pool.try_alloc().unwrap();
+  let _second = pool.try_alloc().unwrap();
+  assert!(pool.try_alloc().is_none());
+  assert!(pool.alloc_timeout(Duration::from_millis(10)).is_none());
+  drop(first);
+  assert!(pool.alloc_timeout(Duration::from_millis(10)).is_some());
+}
+
+#[test]
+fn releasing_a_block_nobody_received_frees_it() {
+  let pool = shared_pool::<u8>(true, "test-pool-release", 4, 1).unwrap();
+  let sent = pool.try_alloc().unwrap().share(1);
+  assert!(pool.try_alloc().is_none());
+  assert!(pool.release(sent));
+  assert!(!pool.release(sent));
+  assert!(pool.get(sent).is_none());
+  let again = pool.try_alloc().unwrap().share(1);
+  let received = pool.get(again).unwrap();
+  assert!(!pool.release(again));
+  drop(received);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn blocks_sent_by_a_process_that_is_gone_are_reclaimed() {
+  let pool = shared_pool::<u8>(true, "test-pool-reclaim", 4, 1).unwrap();
+  unsafe {
+    let child = libc::fork();
+    if child == 0 {
+      // Only touches the inherited mapping, the child shares the block and exits without sending
+      let _ = pool.try_alloc().map(|block| block.share(1));
+      libc::_exit(0);
+    }
+    libc::waitpid(child, std::ptr::null_mut(), 0);
+  }
+  assert_eq!(pool.reclaim(), 1);
+  assert!(pool.try_alloc().is_some());
+}
@@ -0,0 +1,81 @@
+use hot_reload::simple_shared_memory::*;
+
+#[test]
+fn values_are_received_in_the_order_they_were_sent() {
+  let mut queue = shared_queue::<u32>(true, "test-queue-order", 4).unwrap();
+  for lap in 0..10 {
+    for i in 0..3 {
+      queue.try_send(&(lap * 3 + i)).unwrap();
+    }
+    for i in 0..3 {
+      assert_eq!(queue.try_recv(), Some(lap * 3 + i));
+    }
+  }
+  assert_eq!(queue.try_recv(), None);
+}
+
+#[test]
+fn sending_to_a_full_queue_fails_until_a_value_is_received() {
+  let mut queue = shared_queue::<u32>(true, "test-queue-full", 2).unwrap();
+  assert_eq!(queue.try_recv(), None);
+  queue.try_send(&1).unwrap();
+  queue.try_send(&2).unwrap();
+  assert_eq!(queue.try_send(&3), Err(Full(3)));
+  assert_eq!(queue.try_recv(), Some(1));
+  queue.try_send(&3).unwrap();
+  assert_eq!(queue.try_recv(), Some(2));
+  assert_eq!(queue.try_recv(), Some(3));
+  assert_eq!(queue.try_recv(), None);
+}
+
+#[test]
+fn a_queue_of_one_value_holds_one_value() {
+  let mut queue = shared_queue::<u32>(true, "test-queue-one", 1).unwrap();
+  for value in 0..3 {
+    queue.try_send(&value).unwrap();
+    assert_eq!(queue.try_send(&(value + 10)), Err(Full(value + 10)));
+    assert_eq!(queue.try_recv(), Some(value));
+    assert_eq!(queue.try_recv(), None);
+  }
+}
+
+#[test]
+fn forcing_a_value_into_a_full_queue_drops_it() {
+  let mut queue = shared_queue::<u32>(true, "test-queue-force", 1).unwrap();
+  assert_eq!(queue.force_send(&1), None);
+  assert_eq!(queue.force_send(&2), Some(2));
+  assert_eq!(queue.dropped(), 1);
+  assert_eq!(queue.try_recv(), Some(1));
+  assert_eq!(queue.try_recv(), None);
+}
+
+#[test]
+fn every_value_of_concurrent_senders_is_received_once_and_in_order() {
+  const SENDERS: usize = 4;
+  const VALUES: u32 = 1000;
+  let queue = shared_queue::<(usize, u32)>(true, "test-queue-senders", 8).unwrap();
+
+  let receiver = std::thread::spawn(|| {
+    let mut queue = shared_queue::<(usize, u32)>(false, "test-queue-senders", 8).unwrap();
+    let mut next = [0; SENDERS];
+    for _ in 0..SENDERS as u32 * VALUES {
+      let (sender, value) = queue.recv();
+      assert_eq!(value, next[sender]);
+      next[sender] += 1;
+    }
+    assert_eq!(queue.try_recv(), None);
+    next
+  });
+
+  std::thread::scope(|scope| {
+    for sender in 0..SENDERS {
+      let queue = &queue;
+      scope.spawn(move || {
+        for value in 0..VALUES {
+          queue.send(&(sender, value));
+        }
+      });
+    }
+  });
+  assert_eq!(receiver.join().unwrap(), [VALUES; SENDERS]);
+}
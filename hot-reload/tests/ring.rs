@@ -0,0 +1,74 @@
+use hot_reload::simple_shared_memory::*;
+use std::time::{Duration, Instant};
+
+#[test]
+fn values_are_received_in_order_as_the_ring_wraps_around() {
+  let mut ring = shared_ring::<u32>(true, "test-ring-wrap", 3).unwrap();
+  for lap in 0..10 {
+    for i in 0..2 {
+      ring.try_send(&(lap * 2 + i)).unwrap();
+    }
+    for i in 0..2 {
+      assert_eq!(ring.try_recv(), Some(lap * 2 + i));
+    }
+  }
+  assert_eq!(ring.try_recv(), None);
+}
+
+#[test]
+fn sending_to_a_full_ring_fails_until_a_value_is_received() {
+  let mut ring = shared_ring::<u32>(true, "test-ring-full", 2).unwrap();
+  ring.try_send(&1).unwrap();
+  ring.try_send(&2).unwrap();
+  assert_eq!(ring.try_send(&3), Err(Full(3)));
+  assert_eq!(ring.send_timeout(&3, Duration::from_millis(20)), Err(Full(3)));
+  assert_eq!(ring.try_recv(), Some(1));
+  ring.try_send(&3).unwrap();
+  assert_eq!(ring.try_recv(), Some(2));
+  assert_eq!(ring.try_recv(), Some(3));
+  assert_eq!(ring.try_recv(), None);
+}
+
+#[test]
+fn forcing_a_value_into_a_full_ring_drops_it() {
+  let mut ring = shared_ring::<u32>(true, "test-ring-force", 1).unwrap();
+  assert_eq!(ring.force_send(&1), None);
+  assert_eq!(ring.force_send(&2), Some(2));
+  assert_eq!(ring.dropped(), 1);
+  assert_eq!(ring.try_recv(), Some(1));
+  assert_eq!(ring.try_recv(), None);
+}
+
+#[test]
+fn sending_with_a_timeout_waits_for_the_receiver_to_make_room() {
+  let mut ring = shared_ring::<u32>(true, "test-ring-timeout", 1).unwrap();
+  ring.try_send(&1).unwrap();
+
+  let receiver = std::thread::spawn(|| {
+    let mut ring = shared_ring::<u32>(false, "test-ring-timeout", 1).unwrap();
+    std::thread::sleep(Duration::from_millis(50));
+    [ring.recv(), ring.recv()]
+  });
+
+  let start = Instant::now();
+  assert_eq!(ring.send_timeout(&2, Duration::from_secs(5)), Ok(()));
+  assert!(start.elapsed() < Duration::from_secs(5));
+  assert_eq!(receiver.join().unwrap(), [1, 2]);
+}
+
+#[test]
+fn every_value_sent_through_a_small_ring_is_received_once_and_in_order() {
+  const VALUES: u32 = 1000;
+  let mut ring = shared_ring::<u32>(true, "test-ring-threads", 4).unwrap();
+
+  let receiver = std::thread::spawn(|| {
+    let mut ring = shared_ring::<u32>(false, "test-ring-threads", 4).unwrap();
+    (0..VALUES).map(|_| ring.recv()).collect::<Vec<_>>()
+  });
+
+  for value in 0..VALUES {
+    ring.send(&value);
+  }
+  assert_eq!(receiver.join().unwrap(), (0..VALUES).collect::<Vec<_>>());
+  assert_eq!(ring.dropped(), 0);
+}
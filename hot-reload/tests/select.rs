@@ -0,0 +1,47 @@
+#![cfg(target_family = "unix")]
+
+use hot_reload::select::*;
+use hot_reload::simple_shared_memory::*;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[test]
+fn waiting_for_a_ready_handle_wakes_up_on_a_send() {
+  let doorbell = Arc::new(Doorbell::open(true, "test-select-wake-doorbell").unwrap());
+  let mut queue = shared_queue::<u32>(true, "test-select-wake", 4).unwrap().with_doorbell(doorbell);
+
+  let sender = std::thread::spawn(|| {
+    let doorbell = Arc::new(Doorbell::open(false, "test-select-wake-doorbell").unwrap());
+    let queue = shared_queue::<u32>(false, "test-select-wake", 4).unwrap().with_doorbell(doorbell);
+    std::thread::sleep(Duration::from_millis(50));
+    queue.send(&7);
+  });
+
+  let start = Instant::now();
+  let mut select = Select::new();
+  let index = select.recv(&queue).unwrap();
+  assert_eq!(select.ready_timeout(Duration::from_secs(5)), Some(index));
+  assert!(start.elapsed() < Duration::from_secs(5));
+  drop(select);
+  assert_eq!(queue.try_recv(), Some(7));
+  sender.join().unwrap();
+}
+
+#[test]
+fn waiting_without_a_send_times_out() {
+  let doorbell = Arc::new(Doorbell::open(true, "test-select-timeout-doorbell").unwrap());
+  let channel = shared_channel::<u32>(true, "test-select-timeout", 1).unwrap();
+  let channel = channel.with_doorbell(doorbell);
+
+  let start = Instant::now();
+  let mut select = Select::new();
+  select.recv(&channel).unwrap();
+  assert_eq!(select.ready_timeout(Duration::from_millis(50)), None);
+  assert!(start.elapsed() >= Duration::from_millis(50));
+}
+
+#[test]
+fn handles_without_a_doorbell_are_refused() {
+  let queue = shared_queue::<u32>(true, "test-select-no-doorbell", 1).unwrap();
+  assert!(Select::new().recv(&queue).is_err());
+}
@@ -1,6 +1,5 @@
 use arraystring::CacheString;
 use hot_reload::serde::*;
-use hot_reload::*;
 
 // These are the arguments the reloadable process will need to get the shared
 // state.
@@ -10,17 +9,23 @@ pub struct Arguments {
     pub window_height: usize,
 }
 
-hot_reload!(
+#[hot_reload::state(
     // The name of the package that will be reloadable
-    "example-impl",
+    reloadable = "example-impl",
     // The arguments passed to the reloadable package on initialization
-    Arguments,
-    // Template for the shared state struct
-    struct HotReloaded {
-        buffer: slice::<u32>(|arguments: &Arguments| {
-            arguments.window_width * arguments.window_height
-        }),
-        channel_to_impl: channel::<()>(),
-        channel_from_impl: channel::<CacheString>(),
-    }
-);
+    args = Arguments,
+    // Lay out all the fields in a single shared memory segment
+    arena
+)]
+pub struct HotReloaded {
+    /// The pixels of the window, rendered by the reloadable package
+    #[slice(len = |arguments: &Arguments| arguments.window_width * arguments.window_height)]
+    #[writer(reloadable)]
+    pub buffer: [u32],
+    /// Clicks in the window
+    #[channel]
+    pub channel_to_impl: (),
+    /// Number of renders since the reloadable package started
+    #[channel]
+    pub channel_from_impl: CacheString,
+}
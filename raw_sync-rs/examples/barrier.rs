@@ -0,0 +1,36 @@
+use std::thread;
+use std::time;
+
+use env_logger::Env;
+use log::*;
+use raw_sync::barrier::*;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+fn main() -> Result<()> {
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+    let mut mem = [0u8; 128];
+    let mem_ptr = mem.as_mut_ptr() as usize;
+
+    let (barrier, _) = unsafe { Barrier::new(mem.as_mut_ptr(), 2)? };
+
+    let child = thread::spawn(move || {
+        let (barrier, _) = unsafe { Barrier::from_existing(mem_ptr as _).unwrap() };
+        for frame in 0..3 {
+            info!("\t[{}] Rendering", frame);
+            thread::sleep(time::Duration::from_millis(500));
+            barrier.wait().unwrap();
+        }
+    });
+
+    for frame in 0..3 {
+        info!("[{}] Waiting for the frame to be rendered", frame);
+        if barrier.wait()?.is_leader() {
+            info!("[{}] Released the barrier", frame);
+        }
+    }
+
+    let _ = child.join();
+    info!("Done");
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use std::thread;
+use std::time;
+
+use env_logger::Env;
+use log::*;
+use raw_sync::{condvar::*, locks::*, Timeout};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+fn main() -> Result<()> {
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+    let mut mem = [0u8; 256];
+    let mut ready: bool = false;
+
+    let mem_ptr = mem.as_mut_ptr() as usize;
+    let data_ptr = &mut ready as *mut _ as usize;
+
+    let (lock, lock_size) = unsafe { Mutex::new(mem.as_mut_ptr(), data_ptr as _)? };
+    let (condvar, _) = unsafe { Condvar::new(mem.as_mut_ptr().add(lock_size))? };
+
+    let child = thread::spawn(move || {
+        let mem = mem_ptr as *mut u8;
+        let (lock, lock_size) = unsafe { Mutex::from_existing(mem, data_ptr as _).unwrap() };
+        let (condvar, _) = unsafe { Condvar::from_existing(mem.add(lock_size)).unwrap() };
+
+        info!("\tWaiting for 1.5 seconds without being notified");
+        let (guard, result) = condvar
+            .wait_timeout_while(
+                lock.lock().unwrap(),
+                Timeout::Val(time::Duration::from_millis(1500)),
+                &mut |ready| unsafe { !*(ready as *const bool) },
+            )
+            .unwrap();
+        if !result.timed_out() {
+            panic!("This should have timed out !");
+        }
+        drop(guard);
+        info!("\ttimed out !");
+
+        info!("\tWaiting until ready");
+        let _guard = condvar
+            .wait_while(lock.lock().unwrap(), &mut |ready| unsafe {
+                !*(ready as *const bool)
+            })
+            .unwrap();
+        info!("\tReady !");
+    });
+
+    thread::sleep(time::Duration::from_secs(2));
+    info!("Setting ready");
+    {
+        let guard = lock.lock()?;
+        unsafe { *(*guard as *mut bool) = true };
+    }
+    condvar.notify_all()?;
+
+    let _ = child.join();
+    info!("Done");
+    Ok(())
+}
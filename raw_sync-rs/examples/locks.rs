@@ -3,18 +3,19 @@ use std::time;
 
 use env_logger::Env;
 use log::*;
-use raw_sync::{locks::*, Timeout};
+use raw_sync::{locks::*, Error, Timeout};
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
 fn test_timeout(id: u8, lock: &dyn LockImpl) {
     info!("[{}] Waiting for lock for 1 second", id);
-    let guard = lock.try_lock(Timeout::Val(time::Duration::from_secs(1)));
-    if guard.is_err() {
-        info!("[{}] Timed out !", id);
-    } else {
-        info!("[{}] Holding lock for 2s", id);
-        thread::sleep(time::Duration::from_secs(2))
+    match lock.try_lock(Timeout::Val(time::Duration::from_secs(1))) {
+        Err(Error::TimedOut) => info!("[{}] Timed out !", id),
+        Err(e) => panic!("[{}] Failed to acquire lock : {}", id, e),
+        Ok(_guard) => {
+            info!("[{}] Holding lock for 2s", id);
+            thread::sleep(time::Duration::from_secs(2))
+        }
     }
 }
 
@@ -56,6 +57,8 @@ fn main() -> Result<()> {
     #[cfg(not(windows))]
     test_rwlock(mem.as_mut_ptr())?;
 
+    test_mutex_kinds(mem.as_mut_ptr())?;
+
     Ok(())
 }
 
@@ -106,3 +109,29 @@ fn test_rwlock(mem: *mut u8) -> Result<()> {
     let _ = child.join();
     Ok(())
 }
+
+fn test_mutex_kinds(mem: *mut u8) -> Result<()> {
+    info!("-----------");
+    info!("Mutex kinds");
+    info!("-----------");
+    let mut some_data: usize = 0;
+    let data_ptr = &mut some_data as *mut usize as *mut u8;
+
+    let options = MutexOptions::default().kind(MutexKind::ErrorCheck);
+    let (lock, _) = unsafe { Mutex::new_with_options(mem, data_ptr, options)? };
+    let _guard = lock.lock()?;
+    match lock.lock() {
+        Err(Error::Deadlock) => info!("Relocking an error checking mutex fails"),
+        Err(e) => panic!("Unexpected error : {}", e),
+        Ok(_) => panic!("Relocked an error checking mutex !"),
+    }
+    drop(_guard);
+
+    let options = MutexOptions::default().kind(MutexKind::Recursive);
+    let (lock, _) = unsafe { Mutex::new_with_options(mem, data_ptr, options)? };
+    let _outer = lock.lock()?;
+    let _inner = lock.lock()?;
+    info!("Relocked a recursive mutex");
+
+    Ok(())
+}
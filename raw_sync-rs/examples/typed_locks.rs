@@ -0,0 +1,50 @@
+use std::thread;
+use std::time;
+
+use env_logger::Env;
+use log::*;
+use raw_sync::locks::*;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+#[derive(Debug)]
+struct Counters {
+    hits: u64,
+    misses: u64,
+}
+
+fn main() -> Result<()> {
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+    let mut mem = [0u8; 256];
+    assert!(ShmMutex::<Counters>::size_of(Some(mem.as_mut_ptr())) <= mem.len());
+
+    let mem_ptr = mem.as_mut_ptr() as usize;
+    let (mutex, _) = unsafe { ShmMutex::new(mem.as_mut_ptr(), Counters { hits: 0, misses: 0 })? };
+
+    let child = thread::spawn(move || {
+        let (mutex, _) = unsafe { ShmMutex::<Counters>::from_existing(mem_ptr as _).unwrap() };
+        for _ in 0..5 {
+            {
+                let mut counters = mutex.lock().unwrap();
+                counters.hits += 1;
+                info!("\tchild {:?}", *counters);
+            }
+            thread::sleep(time::Duration::from_millis(100));
+        }
+    });
+
+    for _ in 0..5 {
+        {
+            let mut counters = mutex.lock()?;
+            counters.misses += 1;
+            info!("main {:?}", *counters);
+        }
+        thread::sleep(time::Duration::from_millis(100));
+    }
+
+    let _ = child.join();
+    let counters = mutex.lock()?;
+    info!("Done {:?}", *counters);
+    assert_eq!((counters.hits, counters.misses), (5, 5));
+    Ok(())
+}
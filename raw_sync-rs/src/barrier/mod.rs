@@ -0,0 +1,40 @@
+cfg_if::cfg_if! {
+    if #[cfg(any(target_os = "linux", target_os = "freebsd"))] {
+        mod unix;
+        use unix as os;
+        pub use os::*;
+    }
+}
+use crate::Result;
+
+/// Returned by `BarrierImpl::wait()`
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct BarrierWaitResult(pub(crate) bool);
+impl BarrierWaitResult {
+    /// Exactly one of the threads released by the barrier is the leader
+    pub fn is_leader(&self) -> bool {
+        self.0
+    }
+}
+
+pub trait BarrierInit {
+    /// Size required for the barrier's internal representation
+    fn size_of(addr: Option<*mut u8>) -> usize;
+
+    /// Initializes a new barrier for `count` participants in the provided buffer and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    #[allow(clippy::new_ret_no_self)]
+    unsafe fn new(mem: *mut u8, count: u32) -> Result<(Box<dyn BarrierImpl>, usize)>;
+
+    /// Re-uses a barrier from an already initialized location and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    #[allow(clippy::new_ret_no_self)]
+    unsafe fn from_existing(mem: *mut u8) -> Result<(Box<dyn BarrierImpl>, usize)>;
+}
+
+pub trait BarrierImpl {
+    /// Blocks until all participants have called `wait()`
+    fn wait(&self) -> Result<BarrierWaitResult>;
+}
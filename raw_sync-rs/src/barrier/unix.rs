@@ -0,0 +1,97 @@
+use std::mem::{size_of, MaybeUninit};
+
+use libc::{c_int, c_uint, PTHREAD_PROCESS_SHARED};
+
+use super::{BarrierImpl, BarrierInit, BarrierWaitResult};
+use crate::{init_result, Error, Result};
+
+// libc doesn't expose pthread barriers yet. The storage is deliberately larger than
+// any platform's pthread_barrier_t so the layout doesn't have to be known here.
+#[allow(non_camel_case_types)]
+#[repr(C, align(8))]
+struct pthread_barrier_t {
+    _opaque: [u8; 64],
+}
+#[allow(non_camel_case_types)]
+#[repr(C, align(8))]
+struct pthread_barrierattr_t {
+    _opaque: [u8; 16],
+}
+
+const PTHREAD_BARRIER_SERIAL_THREAD: c_int = -1;
+
+extern "C" {
+    fn pthread_barrierattr_init(attr: *mut pthread_barrierattr_t) -> c_int;
+    fn pthread_barrierattr_setpshared(attr: *mut pthread_barrierattr_t, pshared: c_int) -> c_int;
+    fn pthread_barrierattr_destroy(attr: *mut pthread_barrierattr_t) -> c_int;
+    fn pthread_barrier_init(
+        barrier: *mut pthread_barrier_t,
+        attr: *const pthread_barrierattr_t,
+        count: c_uint,
+    ) -> c_int;
+    fn pthread_barrier_wait(barrier: *mut pthread_barrier_t) -> c_int;
+}
+
+/// Process-shared `pthread_barrier_t`, not available on macOS which lacks pthread barriers
+pub struct Barrier {
+    ptr: *mut pthread_barrier_t,
+}
+
+impl BarrierInit for Barrier {
+    fn size_of(addr: Option<*mut u8>) -> usize {
+        let padding = match addr {
+            Some(mem) => mem.align_offset(size_of::<*mut u8>() as _),
+            None => 0,
+        };
+        padding + size_of::<pthread_barrier_t>()
+    }
+
+    #[allow(clippy::new_ret_no_self)]
+    unsafe fn new(mem: *mut u8, count: u32) -> Result<(Box<dyn BarrierImpl>, usize)> {
+        if count == 0 {
+            return Err(Error::InvalidArgument(
+                "A barrier needs at least one participant",
+            ));
+        }
+        let padding = mem.align_offset(size_of::<*mut u8>() as _);
+        let mut attr = MaybeUninit::<pthread_barrierattr_t>::uninit();
+        init_result(
+            "pthread_barrierattr_init",
+            pthread_barrierattr_init(attr.as_mut_ptr()),
+        )?;
+        let mut attr = attr.assume_init();
+        let ptr = mem.add(padding) as *mut pthread_barrier_t;
+        let res = (|| {
+            init_result(
+                "pthread_barrierattr_setpshared",
+                pthread_barrierattr_setpshared(&mut attr, PTHREAD_PROCESS_SHARED),
+            )?;
+            init_result(
+                "pthread_barrier_init",
+                pthread_barrier_init(ptr, &attr, count),
+            )
+        })();
+        // The barrier doesn't need its attributes once initialized
+        pthread_barrierattr_destroy(&mut attr);
+        res?;
+
+        Ok((Box::new(Self { ptr }), Self::size_of(Some(mem))))
+    }
+
+    unsafe fn from_existing(mem: *mut u8) -> Result<(Box<dyn BarrierImpl>, usize)> {
+        let padding = mem.align_offset(size_of::<*mut u8>() as _);
+        let ptr = mem.add(padding) as *mut pthread_barrier_t;
+
+        Ok((Box::new(Self { ptr }), Self::size_of(Some(mem))))
+    }
+}
+
+impl BarrierImpl for Barrier {
+    fn wait(&self) -> Result<BarrierWaitResult> {
+        match unsafe { pthread_barrier_wait(self.ptr) } {
+            0 => Ok(BarrierWaitResult(false)),
+            PTHREAD_BARRIER_SERIAL_THREAD => Ok(BarrierWaitResult(true)),
+            res => Err(Error::from_code("pthread_barrier_wait", res)),
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use std::time::{Duration, Instant};
+
+cfg_if::cfg_if! {
+    if #[cfg(target_family = "unix")] {
+        mod unix;
+        use unix as os;
+        pub use os::*;
+    }
+}
+use crate::locks::LockGuard;
+use crate::{Result, Timeout};
+
+/// Whether a timed wait on a condition variable returned because its timeout elapsed
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct WaitTimeoutResult(pub(crate) bool);
+impl WaitTimeoutResult {
+    /// Returns true if the wait ended because the timeout elapsed
+    pub fn timed_out(&self) -> bool {
+        self.0
+    }
+}
+
+pub trait CondvarInit {
+    /// Size required for the condition variable's internal representation
+    fn size_of(addr: Option<*mut u8>) -> usize;
+
+    /// Initializes a new instance of the condition variable in the provided buffer and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    #[allow(clippy::new_ret_no_self)]
+    unsafe fn new(mem: *mut u8) -> Result<(Box<dyn CondvarImpl>, usize)>;
+
+    /// Re-uses a condition variable from an already initialized location and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    #[allow(clippy::new_ret_no_self)]
+    unsafe fn from_existing(mem: *mut u8) -> Result<(Box<dyn CondvarImpl>, usize)>;
+}
+
+pub trait CondvarImpl {
+    /// Releases the lock held by `guard`, blocks until notified and re-acquires the lock.
+    /// Like `std::sync::Condvar`, the wait can return without a matching notification.
+    fn wait<'t>(&self, guard: LockGuard<'t>) -> Result<LockGuard<'t>>;
+
+    /// Same as `wait()` but gives up once `timeout` has elapsed
+    fn wait_timeout<'t>(
+        &self,
+        guard: LockGuard<'t>,
+        timeout: Timeout,
+    ) -> Result<(LockGuard<'t>, WaitTimeoutResult)>;
+
+    /// Wakes up one thread blocked on this condition variable
+    fn notify_one(&self) -> Result<()>;
+
+    /// Wakes up all threads blocked on this condition variable
+    fn notify_all(&self) -> Result<()>;
+
+    /// Blocks for as long as `condition` returns true. `condition` receives the data
+    /// pointer protected by the lock and is always called with the lock held.
+    fn wait_while<'t>(
+        &self,
+        mut guard: LockGuard<'t>,
+        condition: &mut dyn FnMut(*mut u8) -> bool,
+    ) -> Result<LockGuard<'t>> {
+        while condition(*guard) {
+            guard = self.wait(guard)?;
+        }
+        Ok(guard)
+    }
+
+    /// Same as `wait_while()` but gives up once `timeout` has elapsed
+    fn wait_timeout_while<'t>(
+        &self,
+        mut guard: LockGuard<'t>,
+        timeout: Timeout,
+        condition: &mut dyn FnMut(*mut u8) -> bool,
+    ) -> Result<(LockGuard<'t>, WaitTimeoutResult)> {
+        let deadline = match timeout {
+            Timeout::Infinite => {
+                return Ok((self.wait_while(guard, condition)?, WaitTimeoutResult(false)))
+            }
+            Timeout::Val(d) => Instant::now() + d,
+        };
+        while condition(*guard) {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::from_secs(0) {
+                return Ok((guard, WaitTimeoutResult(true)));
+            }
+            guard = self.wait_timeout(guard, Timeout::Val(remaining))?.0;
+        }
+        Ok((guard, WaitTimeoutResult(false)))
+    }
+}
@@ -0,0 +1,177 @@
+use std::mem::{size_of, MaybeUninit};
+use std::ptr::null_mut;
+
+use libc::{
+    pthread_cond_broadcast, pthread_cond_init, pthread_cond_signal, pthread_cond_t,
+    pthread_cond_timedwait, pthread_cond_wait, pthread_condattr_destroy, pthread_condattr_init,
+    pthread_condattr_setpshared, pthread_condattr_t, ETIMEDOUT, PTHREAD_PROCESS_SHARED,
+};
+
+use super::{CondvarImpl, CondvarInit, WaitTimeoutResult};
+use crate::locks::*;
+use crate::{init_result, os_result, Error, Result, Timeout};
+
+struct InnerCondvar {
+    cond: pthread_cond_t,
+    /// Bumped on every notification so waiters can tell real wake ups from spurious ones
+    generation: u32,
+}
+
+/// Process-shared condition variable usable with any `LockImpl`.
+///
+/// A `pthread_cond_t` can only be paired with a `pthread_mutex_t`, so the condition
+/// variable carries its own mutex. Waiters grab it before releasing the caller's lock,
+/// which guarantees notifications sent after that point are not missed.
+pub struct Condvar {
+    mutex: Box<dyn LockImpl>,
+    inner: *mut InnerCondvar,
+}
+impl CondvarInit for Condvar {
+    fn size_of(addr: Option<*mut u8>) -> usize {
+        let mutex_size = Mutex::size_of(addr);
+        let padding = match addr {
+            Some(mem) => unsafe { mem.add(mutex_size).align_offset(size_of::<*mut u8>() as _) },
+            None => 0,
+        };
+        mutex_size + padding + size_of::<InnerCondvar>()
+    }
+
+    #[allow(clippy::new_ret_no_self)]
+    unsafe fn new(mem: *mut u8) -> Result<(Box<dyn CondvarImpl>, usize)> {
+        let (mutex, used_bytes) = Mutex::new(mem, null_mut())?;
+        let ptr = mem.add(used_bytes);
+        let ptr = ptr.add(ptr.align_offset(size_of::<*mut u8>() as _)) as *mut InnerCondvar;
+        let inner = &mut *ptr;
+
+        let mut attrs = MaybeUninit::<pthread_condattr_t>::uninit();
+        init_result(
+            "pthread_condattr_init",
+            pthread_condattr_init(attrs.as_mut_ptr()),
+        )?;
+        let mut attrs = attrs.assume_init();
+        let res = (|| {
+            init_result(
+                "pthread_condattr_setpshared",
+                pthread_condattr_setpshared(&mut attrs, PTHREAD_PROCESS_SHARED),
+            )?;
+            set_wait_clock(&mut attrs)?;
+            init_result(
+                "pthread_cond_init",
+                pthread_cond_init(&mut inner.cond, &attrs),
+            )
+        })();
+        // The condition variable doesn't need its attributes once initialized
+        pthread_condattr_destroy(&mut attrs);
+        res?;
+        inner.generation = 0;
+
+        let obj = Box::new(Self { mutex, inner });
+
+        Ok((
+            obj,
+            (ptr as usize - mem as usize) + size_of::<InnerCondvar>(),
+        ))
+    }
+
+    unsafe fn from_existing(mem: *mut u8) -> Result<(Box<dyn CondvarImpl>, usize)> {
+        let (mutex, used_bytes) = Mutex::from_existing(mem, null_mut())?;
+        let ptr = mem.add(used_bytes);
+        let ptr = ptr.add(ptr.align_offset(size_of::<*mut u8>() as _)) as *mut InnerCondvar;
+
+        let obj = Box::new(Self { mutex, inner: ptr });
+
+        Ok((
+            obj,
+            (ptr as usize - mem as usize) + size_of::<InnerCondvar>(),
+        ))
+    }
+}
+
+impl CondvarImpl for Condvar {
+    fn wait<'t>(&self, guard: LockGuard<'t>) -> Result<LockGuard<'t>> {
+        Ok(self.wait_maybe_timeout(guard, Timeout::Infinite)?.0)
+    }
+
+    fn wait_timeout<'t>(
+        &self,
+        guard: LockGuard<'t>,
+        timeout: Timeout,
+    ) -> Result<(LockGuard<'t>, WaitTimeoutResult)> {
+        self.wait_maybe_timeout(guard, timeout)
+    }
+
+    fn notify_one(&self) -> Result<()> {
+        self.notify(false)
+    }
+
+    fn notify_all(&self) -> Result<()> {
+        self.notify(true)
+    }
+}
+
+impl Condvar {
+    fn notify(&self, all: bool) -> Result<()> {
+        let guard = self.mutex.lock()?;
+        let inner = unsafe { &mut *self.inner };
+        inner.generation = inner.generation.wrapping_add(1);
+        let (call, res) = unsafe {
+            if all {
+                (
+                    "pthread_cond_broadcast",
+                    pthread_cond_broadcast(&mut inner.cond),
+                )
+            } else {
+                ("pthread_cond_signal", pthread_cond_signal(&mut inner.cond))
+            }
+        };
+        drop(guard);
+
+        os_result(call, res)
+    }
+
+    fn wait_maybe_timeout<'t>(
+        &self,
+        guard: LockGuard<'t>,
+        timeout: Timeout,
+    ) -> Result<(LockGuard<'t>, WaitTimeoutResult)> {
+        let timespec = match timeout {
+            Timeout::Infinite => None,
+            Timeout::Val(d) => Some(abs_timespec_from_duration(WAIT_CLOCK, d)),
+        };
+        let user_lock = guard.lock_impl();
+
+        // Take our own mutex before letting go of the caller's lock so that a
+        // notification can't slip in between the two
+        let internal_guard = self.mutex.lock()?;
+        let inner = unsafe { &mut *self.inner };
+        let generation = inner.generation;
+        drop(guard);
+
+        let mut res = 0;
+        while unsafe { std::ptr::read_volatile(&inner.generation) } == generation {
+            res = unsafe {
+                match timespec {
+                    Some(ref ts) => {
+                        pthread_cond_timedwait(&mut inner.cond, self.mutex.as_raw() as _, ts)
+                    }
+                    None => pthread_cond_wait(&mut inner.cond, self.mutex.as_raw() as _),
+                }
+            };
+            if res != 0 {
+                break;
+            }
+        }
+        if res == libc::EOWNERDEAD {
+            // Woke up holding the mutex of a process that died with it
+            unsafe { mark_consistent(self.mutex.as_raw() as _)? };
+        }
+        drop(internal_guard);
+
+        let guard = user_lock.lock()?;
+        match res {
+            0 => Ok((guard, WaitTimeoutResult(false))),
+            ETIMEDOUT => Ok((guard, WaitTimeoutResult(true))),
+            _ => Err(Error::from_code("pthread_cond_wait", res)),
+        }
+    }
+}
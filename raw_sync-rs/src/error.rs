@@ -0,0 +1,90 @@
+use std::fmt;
+
+/// Errors reported by the locks, events, condition variables and barriers of this crate
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// A timed wait gave up before the primitive became available.
+    /// Timeouts are measured against a monotonic clock where the platform supports it.
+    TimedOut,
+    /// The previous owner of the mutex died while holding it (`EOWNERDEAD`, `WAIT_ABANDONED`).
+    /// The mutex was released again, the data it protects may be half updated.
+    /// Only mutexes can report it, and only on Windows, Linux and FreeBSD.
+    OwnerDead,
+    /// Acquiring the lock would deadlock, usually because the calling thread already holds it (`EDEADLK`)
+    Deadlock,
+    /// The calling thread tried to release a lock it doesn't hold (`EPERM`)
+    NotOwner,
+    /// A primitive opened with `from_existing()` doesn't look like it was ever initialized
+    Corrupted(&'static str),
+    /// The arguments passed to a constructor can't be used to build the primitive
+    InvalidArgument(&'static str),
+    /// Setting up a new primitive failed in `call`
+    InitFailed { call: &'static str, errno: i32 },
+    /// Any other failure reported by the OS for `call`
+    Os { call: &'static str, errno: i32 },
+}
+
+impl Error {
+    /// Maps the error code returned by `call` to the matching variant
+    pub(crate) fn from_code(call: &'static str, errno: i32) -> Self {
+        #[cfg(target_family = "unix")]
+        match errno {
+            libc::ETIMEDOUT => return Error::TimedOut,
+            libc::EOWNERDEAD => return Error::OwnerDead,
+            libc::EDEADLK => return Error::Deadlock,
+            _ => {}
+        }
+        Error::Os { call, errno }
+    }
+
+    /// The raw OS error code, if the error came from the OS
+    pub fn errno(&self) -> Option<i32> {
+        match *self {
+            Error::InitFailed { errno, .. } | Error::Os { errno, .. } => Some(errno),
+            _ => None,
+        }
+    }
+}
+
+/// Turns the return code of an initialization call into a `Result`
+pub(crate) fn init_result(call: &'static str, res: i32) -> Result<(), Error> {
+    if res != 0 {
+        return Err(Error::InitFailed { call, errno: res });
+    }
+    Ok(())
+}
+
+/// Turns the return code of any other call into a `Result`
+pub(crate) fn os_result(call: &'static str, res: i32) -> Result<(), Error> {
+    if res != 0 {
+        return Err(Error::from_code(call, res));
+    }
+    Ok(())
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::TimedOut => write!(f, "Timed out"),
+            Error::OwnerDead => write!(f, "The owner of the lock died while holding it"),
+            Error::Deadlock => write!(f, "Acquiring the lock would deadlock"),
+            Error::NotOwner => write!(f, "The lock is not held by the calling thread"),
+            Error::Corrupted(what) => write!(f, "Existing {} is corrupted", what),
+            Error::InvalidArgument(why) => write!(f, "Invalid argument : {}", why),
+            Error::InitFailed { call, errno } => write!(
+                f,
+                "Initialization failed in {} : {}",
+                call,
+                std::io::Error::from_raw_os_error(*errno)
+            ),
+            Error::Os { call, errno } => write!(
+                f,
+                "{} failed : {}",
+                call,
+                std::io::Error::from_raw_os_error(*errno)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
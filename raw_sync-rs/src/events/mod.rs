@@ -9,7 +9,7 @@ cfg_if::cfg_if! {
         unimplemented!("This crate does not support your OS yet !");
     }
 }
-use crate::{Result, Timeout};
+use crate::{Error, Result, Timeout};
 pub use os::*;
 
 pub enum EventState {
@@ -23,13 +23,15 @@ pub trait EventInit {
     /// Size required for the event's internal representation
     fn size_of(addr: Option<*mut u8>) -> usize;
 
-    /// Initializes a new instance of the event in the provided buffer and returns the number of used bytes
+    /// Initializes a new instance of the event in the provided buffer and returns the number of used bytes,
+    /// never more than `size_of(Some(mem))`
     /// # Safety
     /// This function is unsafe because it cannot guarantee that the provided memory is valid.
     #[allow(clippy::new_ret_no_self)]
     unsafe fn new(mem: *mut u8, auto_reset: bool) -> Result<(Box<dyn EventImpl>, usize)>;
 
-    /// Re-uses an event from an already initialized location and returns the number of used bytes
+    /// Re-uses an event from an already initialized location and returns the number of used bytes,
+    /// never more than `size_of(Some(mem))`
     /// # Safety
     /// This function is unsafe because it cannot guarantee that the provided memory is valid.
     #[allow(clippy::new_ret_no_self)]
@@ -44,6 +46,8 @@ pub trait EventImpl {
     /// Wait for the event to be signaled, but also return if the thread is woken
     /// up by the OS - this is useful for handing cancellations on Unix.
     fn wait_allow_spurious_wake_up(&self, timeout: Timeout) -> Result<EventState>;
+    /// Returns whether the event is signaled, without waking up or unblocking anyone
+    fn is_signaled(&self) -> Result<bool>;
 }
 
 use std::mem::size_of;
@@ -79,7 +83,7 @@ impl EventInit for BusyEvent {
         let inner = &mut *obj.inner;
 
         if inner.auto_reset > 1 || inner.signal.load(Ordering::Relaxed) > 1 {
-            return Err(From::from("Existing BusyEvent is corrupted"));
+            return Err(Error::Corrupted("BusyEvent"));
         }
 
         Ok((Box::new(obj), Self::size_of(None)))
@@ -108,7 +112,7 @@ fn busy_wait_auto(signal: &mut AtomicU8, timeout: Timeout) -> Result<()> {
     if prev_val == 1 {
         Ok(())
     } else {
-        Err(From::from("Waiting for BusyEvent timed out !".to_string()))
+        Err(Error::TimedOut)
     }
 }
 fn busy_wait_manual(signal: &mut AtomicU8, timeout: Timeout) -> Result<()> {
@@ -135,7 +139,7 @@ fn busy_wait_manual(signal: &mut AtomicU8, timeout: Timeout) -> Result<()> {
     if prev_val == 1 {
         Ok(())
     } else {
-        Err(From::from("Waiting for BusyEvent timed out !".to_string()))
+        Err(Error::TimedOut)
     }
 }
 impl EventImpl for BusyEvent {
@@ -154,6 +158,11 @@ impl EventImpl for BusyEvent {
         Ok(EventState::Signaled)
     }
 
+    fn is_signaled(&self) -> Result<bool> {
+        let inner = unsafe { &*self.inner };
+        Ok(inner.signal.load(Ordering::Relaxed) == 1)
+    }
+
     fn set(&self, state: EventState) -> Result<()> {
         let inner = unsafe { &mut *self.inner };
         match state {
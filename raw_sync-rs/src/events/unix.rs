@@ -19,7 +19,7 @@ use libc::{
 
 use crate::events::*;
 use crate::locks::*;
-use crate::{Result, Timeout};
+use crate::{init_result, os_result, Error, Result, Timeout};
 
 struct InnerEvent {
     cond: pthread_cond_t,
@@ -50,30 +50,27 @@ impl EventInit for Event {
         #[allow(clippy::uninit_assumed_init)]
         let mut attrs: pthread_condattr_t = MaybeUninit::uninit().assume_init();
         //trace!("pthread_condattr_init()");
-        if pthread_condattr_init(&mut attrs) != 0 {
-            return Err(From::from(
-                "Failed to initialize pthread_condattr_init".to_string(),
-            ));
-        }
+        init_result("pthread_condattr_init", pthread_condattr_init(&mut attrs))?;
         //trace!("pthread_condattr_setpshared()");
-        if pthread_condattr_setpshared(&mut attrs, PTHREAD_PROCESS_SHARED) != 0 {
-            return Err(From::from(
-                "Failed to set pthread_condattr_setpshared(PTHREAD_PROCESS_SHARED)".to_string(),
-            ));
-        }
+        init_result(
+            "pthread_condattr_setpshared",
+            pthread_condattr_setpshared(&mut attrs, PTHREAD_PROCESS_SHARED),
+        )?;
+        set_wait_clock(&mut attrs)?;
 
         //trace!("pthread_cond_init({:p})", ptr);
-        if pthread_cond_init(&mut inner.cond, &attrs) != 0 {
-            return Err(From::from(
-                "Failed to initialize pthread_cond_init".to_string(),
-            ));
-        }
+        init_result(
+            "pthread_cond_init",
+            pthread_cond_init(&mut inner.cond, &attrs),
+        )?;
         inner.auto_reset = if auto_reset { 1 } else { 0 };
         inner.signal = 0;
 
         let obj = Box::new(Self { mutex, inner });
 
-        Ok((obj, (ptr as usize - mem as usize) + Self::size_of(None)))
+        let used_bytes = (ptr as usize - mem as usize) + size_of::<InnerEvent>();
+        debug_assert!(used_bytes <= Self::size_of(Some(mem)));
+        Ok((obj, used_bytes))
     }
 
     unsafe fn from_existing(mem: *mut u8) -> Result<(Box<dyn EventImpl>, usize)> {
@@ -84,12 +81,14 @@ impl EventInit for Event {
         let inner = &mut *ptr;
 
         if inner.auto_reset > 1 || inner.signal > 1 {
-            return Err(From::from("Existing Event is corrupted"));
+            return Err(Error::Corrupted("Event"));
         }
 
         let obj = Box::new(Self { mutex, inner });
 
-        Ok((obj, (ptr as usize - mem as usize) + Self::size_of(None)))
+        let used_bytes = (ptr as usize - mem as usize) + size_of::<InnerEvent>();
+        debug_assert!(used_bytes <= Self::size_of(Some(mem)));
+        Ok((obj, used_bytes))
     }
 }
 
@@ -103,38 +102,39 @@ impl EventImpl for Event {
         self.wait_maybe_allow_spurious_wake_up(timeout, true)
     }
 
+    fn is_signaled(&self) -> Result<bool> {
+        let _guard = self.mutex.lock()?;
+        Ok(unsafe { (*self.inner).signal } == 1)
+    }
+
     fn set(&self, state: EventState) -> Result<()> {
         let guard = self.mutex.lock()?;
         let inner = unsafe { &mut *self.inner };
-        let res = match state {
+        let (call, res) = match state {
             EventState::Clear => {
                 //trace!("reset pthread_cond({:p})", &inner.cond);
                 inner.signal = 0;
-                0
+                return Ok(());
             }
             EventState::Signaled => {
                 inner.signal = 1;
                 unsafe {
                     if inner.auto_reset == 1 {
                         //trace!("pthread_cond_signal({:p})", &inner.cond);
-                        pthread_cond_signal(&mut inner.cond)
+                        ("pthread_cond_signal", pthread_cond_signal(&mut inner.cond))
                     } else {
                         //trace!("pthread_cond_broadcast({:p})", &inner.cond);
-                        pthread_cond_broadcast(&mut inner.cond)
+                        (
+                            "pthread_cond_broadcast",
+                            pthread_cond_broadcast(&mut inner.cond),
+                        )
                     }
                 }
             }
         };
         drop(guard);
 
-        if res != 0 {
-            Err(From::from(format!(
-                "Failed to set event state : 0x{:X}",
-                res
-            )))
-        } else {
-            Ok(())
-        }
+        os_result(call, res)
     }
 }
 
@@ -147,7 +147,7 @@ impl Event {
         let (guard, timespec) = match timeout {
             Timeout::Infinite => (self.mutex.lock()?, None),
             Timeout::Val(d) => {
-                let timespec = abs_timespec_from_duration(d);
+                let timespec = abs_timespec_from_duration(WAIT_CLOCK, d);
                 (self.mutex.try_lock(timeout)?, Some(timespec))
             }
         };
@@ -174,17 +174,17 @@ impl Event {
                 }
             }
         }
+        if res == libc::EOWNERDEAD {
+            // Woke up holding the mutex of a process that died with it
+            unsafe { mark_consistent(self.mutex.as_raw() as _)? };
+        }
         // Success
-        let ret = if res == 0 {
+        let ret = os_result("pthread_cond_wait", res).map(|_| {
             if inner.auto_reset == 1 {
                 inner.signal = 0;
             }
-            Ok(EventState::Signaled)
-        } else {
-            Err(From::from(
-                format!("Failed waiting for signal, error code: {}", res).to_string(),
-            ))
-        };
+            EventState::Signaled
+        });
         drop(guard);
         ret
     }
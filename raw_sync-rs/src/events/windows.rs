@@ -3,8 +3,12 @@ use std::mem::size_of;
 use std::ptr::null_mut;
 
 use winapi::{
-    shared::ntdef::{FALSE, NULL, TRUE},
+    shared::{
+        ntdef::{FALSE, NULL, TRUE},
+        winerror::WAIT_TIMEOUT,
+    },
     um::{
+        errhandlingapi::GetLastError,
         handleapi::CloseHandle,
         synchapi::{CreateEventA, OpenEventA, ResetEvent, SetEvent, WaitForSingleObject},
         winbase::{INFINITE, WAIT_OBJECT_0},
@@ -13,7 +17,30 @@ use winapi::{
 };
 
 use super::{EventImpl, EventInit, EventState};
-use crate::{Result, Timeout};
+use crate::{Error, Result, Timeout};
+
+/// Access right to read the state of an event, which winapi doesn't declare
+const EVENT_QUERY_STATE: u32 = 0x0001;
+/// `EventBasicInformation` of `EVENT_INFORMATION_CLASS`
+const EVENT_BASIC_INFORMATION: u32 = 0;
+
+/// `EVENT_BASIC_INFORMATION` returned by `NtQueryEvent()`
+#[repr(C)]
+struct EventBasicInformation {
+    event_type: i32,
+    event_state: i32,
+}
+
+#[link(name = "ntdll")]
+extern "system" {
+    fn NtQueryEvent(
+        event_handle: HANDLE,
+        event_information_class: u32,
+        event_information: *mut EventBasicInformation,
+        event_information_length: u32,
+        return_length: *mut u32,
+    ) -> i32;
+}
 
 pub struct Event {
     handle: HANDLE,
@@ -55,16 +82,16 @@ impl EventInit for Event {
         let path = CString::new(format!("event_{}", id)).unwrap();
         //trace!("OpenEventA('{}')", path.to_string_lossy());
         let handle = OpenEventA(
-            EVENT_MODIFY_STATE | SYNCHRONIZE, // request full access
-            FALSE as _,                       // handle not inheritable
+            EVENT_MODIFY_STATE | EVENT_QUERY_STATE | SYNCHRONIZE, // request full access
+            FALSE as _,                                           // handle not inheritable
             path.as_ptr() as *mut _,
         );
 
         if handle == NULL {
-            return Err(From::from(format!(
-                "Failed to open event {}",
-                path.to_string_lossy()
-            )));
+            return Err(Error::InitFailed {
+                call: "OpenEventA",
+                errno: GetLastError() as _,
+            });
         }
 
         Ok((Box::new(Event { handle }), Self::size_of(None)))
@@ -85,11 +112,13 @@ impl EventImpl for Event {
 
         if wait_res == WAIT_OBJECT_0 {
             Ok(())
+        } else if wait_res == WAIT_TIMEOUT {
+            Err(Error::TimedOut)
         } else {
-            Err(From::from(format!(
-                "Failed waiting for event : 0x{:X}",
-                wait_res
-            )))
+            Err(Error::Os {
+                call: "WaitForSingleObject",
+                errno: unsafe { GetLastError() } as _,
+            })
         }
     }
 
@@ -98,6 +127,31 @@ impl EventImpl for Event {
         Ok(EventState::Signaled)
     }
 
+    fn is_signaled(&self) -> Result<bool> {
+        // Waiting would reset an auto reset event, ntdll reads the state without changing it
+        let mut info = EventBasicInformation {
+            event_type: 0,
+            event_state: 0,
+        };
+        let status = unsafe {
+            NtQueryEvent(
+                self.handle,
+                EVENT_BASIC_INFORMATION,
+                &mut info,
+                size_of::<EventBasicInformation>() as _,
+                null_mut(),
+            )
+        };
+        // Negative NTSTATUS values are errors
+        if status < 0 {
+            return Err(Error::Os {
+                call: "NtQueryEvent",
+                errno: status,
+            });
+        }
+        Ok(info.event_state != 0)
+    }
+
     fn set(&self, state: EventState) -> Result<()> {
         let res = match state {
             EventState::Clear => {
@@ -113,10 +167,10 @@ impl EventImpl for Event {
         if res != 0 {
             Ok(())
         } else {
-            Err(From::from(format!(
-                "Failed setting event state : 0x{:X}",
-                res
-            )))
+            Err(Error::Os {
+                call: "SetEvent",
+                errno: unsafe { GetLastError() } as _,
+            })
         }
     }
 }
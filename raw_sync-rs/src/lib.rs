@@ -1,4 +1,10 @@
-pub(crate) type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+mod error;
+pub use error::*;
+pub type Result<T> = std::result::Result<T, Error>;
+/// Barrier implementations
+pub mod barrier;
+/// Condition variable implementations
+pub mod condvar;
 /// Event implementations
 pub mod events;
 /// Lock implementations
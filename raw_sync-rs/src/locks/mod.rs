@@ -14,6 +14,40 @@ cfg_if::cfg_if! {
 use crate::{Result, Timeout};
 pub use os::*;
 
+mod shm;
+pub use shm::*;
+
+/// What happens when a thread locks a `Mutex` it already holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MutexKind {
+    /// No checks, relocking deadlocks and unlocking a mutex owned by another thread is undefined
+    #[default]
+    Normal,
+    /// Relocking fails with `Error::Deadlock` and unlocking a mutex owned by another thread
+    /// fails with `Error::NotOwner`
+    ErrorCheck,
+    /// The owner can lock the mutex again, it has to be released as many times as it was locked
+    Recursive,
+}
+
+/// Attributes of a `Mutex` created through `Mutex::new_with_options()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MutexOptions {
+    pub kind: MutexKind,
+    /// Boosts the priority of the owner to the one of the highest priority waiter (`PTHREAD_PRIO_INHERIT`)
+    pub priority_inheritance: bool,
+}
+impl MutexOptions {
+    pub fn kind(mut self, kind: MutexKind) -> Self {
+        self.kind = kind;
+        self
+    }
+    pub fn priority_inheritance(mut self, enabled: bool) -> Self {
+        self.priority_inheritance = enabled;
+        self
+    }
+}
+
 pub trait LockInit {
     /// Size required for the lock's internal representation
     fn size_of(addr: Option<*mut u8>) -> usize;
@@ -71,6 +105,10 @@ impl<'t> LockGuard<'t> {
     fn new(lock_impl: &'t dyn LockImpl) -> Self {
         Self { lock: lock_impl }
     }
+    /// Returns the lock this guard was acquired from
+    pub(crate) fn lock_impl(&self) -> &'t dyn LockImpl {
+        self.lock
+    }
     pub fn into_read_guard(self) -> ReadLockGuard<'t> {
         let inner_lock = self.lock;
         std::mem::forget(self);
@@ -0,0 +1,246 @@
+use std::marker::PhantomData;
+use std::mem::{align_of, size_of};
+use std::ops::{Deref, DerefMut};
+
+use super::{LockGuard, LockImpl, LockInit, Mutex, MutexKind, MutexOptions, ReadLockGuard};
+use crate::{Error, Result, Timeout};
+
+/// Bytes needed for a lock of type `L` followed by a `T`. Without an address the
+/// worst case padding is assumed.
+fn size_of_lock_and<L: LockInit, T>(addr: Option<*mut u8>) -> usize {
+    match addr {
+        Some(mem) => unsafe {
+            let data = data_ptr::<T>(mem, L::size_of(Some(mem)));
+            (data as usize - mem as usize) + size_of::<T>()
+        },
+        None => {
+            (size_of::<*mut u8>() - 1) + L::size_of(None) + (align_of::<T>() - 1) + size_of::<T>()
+        }
+    }
+}
+
+/// Location of the `T` stored after a lock that uses `lock_size` bytes
+unsafe fn data_ptr<T>(mem: *mut u8, lock_size: usize) -> *mut T {
+    let ptr = mem.add(lock_size);
+    ptr.add(ptr.align_offset(align_of::<T>())) as *mut T
+}
+
+/// Initializes a lock of type `L` at `mem` through `init`, optionally writing `value` right after it
+unsafe fn new_lock_and<L: LockInit, T>(
+    mem: *mut u8,
+    value: Option<T>,
+    init: impl FnOnce(*mut u8, *mut u8) -> Result<(Box<dyn LockImpl>, usize)>,
+) -> Result<(Box<dyn LockImpl>, *mut T, usize)> {
+    let data = data_ptr::<T>(mem, L::size_of(Some(mem)));
+    if let Some(value) = value {
+        data.write(value);
+    }
+    let (lock, _) = init(mem, data as *mut u8)?;
+    Ok((lock, data, (data as usize - mem as usize) + size_of::<T>()))
+}
+
+unsafe fn existing_lock_and<L: LockInit, T>(
+    mem: *mut u8,
+) -> Result<(Box<dyn LockImpl>, *mut T, usize)> {
+    let data = data_ptr::<T>(mem, L::size_of(Some(mem)));
+    let (lock, _) = L::from_existing(mem, data as *mut u8)?;
+    Ok((lock, data, (data as usize - mem as usize) + size_of::<T>()))
+}
+
+/// A `T` placed in (shared) memory right after the `Mutex` protecting it.
+///
+/// Only the lock is initialized by the process calling `new()`, the `T` has to be
+/// free of pointers for the other process to be able to use it.
+pub struct ShmMutex<T> {
+    lock: Box<dyn LockImpl>,
+    data: *mut T,
+}
+
+impl<T> ShmMutex<T> {
+    /// Size required for the mutex and its data at `addr`
+    pub fn size_of(addr: Option<*mut u8>) -> usize {
+        size_of_lock_and::<Mutex, T>(addr)
+    }
+
+    /// Initializes a new mutex holding `value` in the provided buffer and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn new(mem: *mut u8, value: T) -> Result<(Self, usize)> {
+        let (lock, data, used_bytes) =
+            new_lock_and::<Mutex, T>(mem, Some(value), |mem, data| Mutex::new(mem, data))?;
+        Ok((Self { lock, data }, used_bytes))
+    }
+
+    /// Same as `ShmMutex::new()` with the kind and protocol of the mutex picked by `options`.
+    /// `MutexKind::Recursive` is rejected as it would hand out aliasing `&mut T`.
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn new_with_options(
+        mem: *mut u8,
+        value: T,
+        options: MutexOptions,
+    ) -> Result<(Self, usize)> {
+        if options.kind == MutexKind::Recursive {
+            return Err(Error::InvalidArgument(
+                "a ShmMutex can't be recursive, its guards would alias",
+            ));
+        }
+        let (lock, data, used_bytes) = new_lock_and::<Mutex, T>(mem, Some(value), |mem, data| {
+            Mutex::new_with_options(mem, data, options)
+        })?;
+        Ok((Self { lock, data }, used_bytes))
+    }
+
+    /// Initializes a new mutex in the provided buffer, keeping whatever `T` is already stored
+    /// after it, and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid
+    /// and already holds a valid `T`.
+    pub unsafe fn new_in_place(mem: *mut u8) -> Result<(Self, usize)> {
+        let (lock, data, used_bytes) =
+            new_lock_and::<Mutex, T>(mem, None, |mem, data| Mutex::new(mem, data))?;
+        Ok((Self { lock, data }, used_bytes))
+    }
+
+    /// Re-uses a mutex and its data from an already initialized location and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn from_existing(mem: *mut u8) -> Result<(Self, usize)> {
+        let (lock, data, used_bytes) = existing_lock_and::<Mutex, T>(mem)?;
+        Ok((Self { lock, data }, used_bytes))
+    }
+
+    /// Acquires the lock
+    pub fn lock(&self) -> Result<ShmLockGuard<'_, T>> {
+        Ok(ShmLockGuard::new(self.lock.lock()?, self.data))
+    }
+
+    /// Acquires lock with timeout
+    pub fn try_lock(&self, timeout: Timeout) -> Result<ShmLockGuard<'_, T>> {
+        Ok(ShmLockGuard::new(self.lock.try_lock(timeout)?, self.data))
+    }
+
+    /// The untyped lock, e.g. for waiting on a `Condvar`
+    pub fn as_lock(&self) -> &dyn LockImpl {
+        &*self.lock
+    }
+}
+
+/// A `T` placed in (shared) memory right after the `RwLock` protecting it
+#[cfg(target_family = "unix")]
+pub struct ShmRwLock<T> {
+    lock: Box<dyn LockImpl>,
+    data: *mut T,
+}
+
+#[cfg(target_family = "unix")]
+impl<T> ShmRwLock<T> {
+    /// Size required for the lock and its data at `addr`
+    pub fn size_of(addr: Option<*mut u8>) -> usize {
+        size_of_lock_and::<super::RwLock, T>(addr)
+    }
+
+    /// Initializes a new lock holding `value` in the provided buffer and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn new(mem: *mut u8, value: T) -> Result<(Self, usize)> {
+        let (lock, data, used_bytes) =
+            new_lock_and::<super::RwLock, T>(mem, Some(value), |mem, data| {
+                super::RwLock::new(mem, data)
+            })?;
+        Ok((Self { lock, data }, used_bytes))
+    }
+
+    /// Re-uses a lock and its data from an already initialized location and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn from_existing(mem: *mut u8) -> Result<(Self, usize)> {
+        let (lock, data, used_bytes) = existing_lock_and::<super::RwLock, T>(mem)?;
+        Ok((Self { lock, data }, used_bytes))
+    }
+
+    /// Acquires the lock for read access
+    pub fn read(&self) -> Result<ShmReadLockGuard<'_, T>> {
+        Ok(ShmReadLockGuard::new(self.lock.rlock()?, self.data))
+    }
+
+    /// Acquires the lock for read access with timeout
+    pub fn try_read(&self, timeout: Timeout) -> Result<ShmReadLockGuard<'_, T>> {
+        Ok(ShmReadLockGuard::new(
+            self.lock.try_rlock(timeout)?,
+            self.data,
+        ))
+    }
+
+    /// Acquires the lock for write access
+    pub fn write(&self) -> Result<ShmLockGuard<'_, T>> {
+        Ok(ShmLockGuard::new(self.lock.lock()?, self.data))
+    }
+
+    /// Acquires the lock for write access with timeout
+    pub fn try_write(&self, timeout: Timeout) -> Result<ShmLockGuard<'_, T>> {
+        Ok(ShmLockGuard::new(self.lock.try_lock(timeout)?, self.data))
+    }
+}
+
+/// Typed access to the data of an acquired `ShmMutex` or write locked `ShmRwLock`.
+/// Lock is automatically released on `Drop`
+pub struct ShmLockGuard<'t, T> {
+    guard: LockGuard<'t>,
+    data: *mut T,
+    data_type: PhantomData<&'t mut T>,
+}
+impl<'t, T> ShmLockGuard<'t, T> {
+    fn new(guard: LockGuard<'t>, data: *mut T) -> Self {
+        Self {
+            guard,
+            data,
+            data_type: PhantomData,
+        }
+    }
+    /// Gives back the untyped guard, e.g. for waiting on a `Condvar`
+    pub fn into_inner(self) -> LockGuard<'t> {
+        self.guard
+    }
+    /// Re-types a guard previously returned by `into_inner()`
+    /// # Safety
+    /// `guard` must have been acquired from a lock protecting a `T` at `data`
+    pub unsafe fn from_inner(guard: LockGuard<'t>) -> Self {
+        let data = *guard as *mut T;
+        Self::new(guard, data)
+    }
+}
+impl<'t, T> Deref for ShmLockGuard<'t, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.data }
+    }
+}
+impl<'t, T> DerefMut for ShmLockGuard<'t, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.data }
+    }
+}
+
+/// Typed read only access to the data of a read locked `ShmRwLock`.
+/// Lock is automatically released on `Drop`
+pub struct ShmReadLockGuard<'t, T> {
+    _guard: ReadLockGuard<'t>,
+    data: *const T,
+    data_type: PhantomData<&'t T>,
+}
+impl<'t, T> ShmReadLockGuard<'t, T> {
+    fn new(guard: ReadLockGuard<'t>, data: *const T) -> Self {
+        Self {
+            _guard: guard,
+            data,
+            data_type: PhantomData,
+        }
+    }
+}
+impl<'t, T> Deref for ShmReadLockGuard<'t, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.data }
+    }
+}
@@ -4,6 +4,7 @@ use std::time::Duration;
 
 use libc::{
     clock_gettime,
+    clockid_t,
     pthread_mutex_init,
     pthread_mutex_lock,
     //Mutex defs
@@ -13,6 +14,7 @@ use libc::{
 
     pthread_mutexattr_init,
     pthread_mutexattr_setpshared,
+    pthread_mutexattr_settype,
     pthread_mutexattr_t,
     pthread_rwlock_init,
     pthread_rwlock_rdlock,
@@ -27,21 +29,154 @@ use libc::{
     pthread_rwlockattr_setpshared,
     pthread_rwlockattr_t,
     timespec,
-    CLOCK_REALTIME,
-
+    EPERM,
+    PTHREAD_MUTEX_ERRORCHECK,
+    PTHREAD_MUTEX_NORMAL,
+    PTHREAD_MUTEX_RECURSIVE,
     PTHREAD_PROCESS_SHARED,
 };
 //use log::*;
 
+// Not exposed by libc for every target yet
+extern "C" {
+    fn pthread_mutexattr_setprotocol(attr: *mut pthread_mutexattr_t, protocol: i32) -> i32;
+}
+const PTHREAD_PRIO_INHERIT: i32 = 1;
+
+// Robust mutexes report the death of their owner instead of staying locked forever
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+extern "C" {
+    fn pthread_mutexattr_setrobust(attr: *mut pthread_mutexattr_t, robustness: i32) -> i32;
+    fn pthread_mutex_consistent(lock: *mut pthread_mutex_t) -> i32;
+}
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+const PTHREAD_MUTEX_ROBUST: i32 = 1;
+
 extern "C" {
-    fn pthread_rwlock_timedrdlock(attr: *mut pthread_rwlock_t, host: *const timespec) -> i32;
-    fn pthread_rwlock_timedwrlock(attr: *mut pthread_rwlock_t, host: *const timespec) -> i32;
+    fn pthread_rwlock_timedrdlock(lock: *mut pthread_rwlock_t, abstime: *const timespec) -> i32;
+    fn pthread_rwlock_timedwrlock(lock: *mut pthread_rwlock_t, abstime: *const timespec) -> i32;
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(all(target_os = "linux", target_env = "gnu"))] {
+        // glibc >= 2.30 can wait against CLOCK_MONOTONIC, which doesn't jump with the wall clock.
+        // The functions are looked up when first needed so that binaries still load with older
+        // versions, which wait against CLOCK_REALTIME in slices checked against CLOCK_MONOTONIC.
+        type ClockLock<T> = unsafe extern "C" fn(*mut T, clockid_t, *const timespec) -> i32;
+
+        struct ClockLocks {
+            mutex: Option<ClockLock<pthread_mutex_t>>,
+            rdlock: Option<ClockLock<pthread_rwlock_t>>,
+            wrlock: Option<ClockLock<pthread_rwlock_t>>,
+        }
+
+        fn clock_locks() -> &'static ClockLocks {
+            static CLOCK_LOCKS: std::sync::OnceLock<ClockLocks> = std::sync::OnceLock::new();
+            CLOCK_LOCKS.get_or_init(|| unsafe {
+                ClockLocks {
+                    mutex: find_clock_lock(b"pthread_mutex_clocklock\0"),
+                    rdlock: find_clock_lock(b"pthread_rwlock_clockrdlock\0"),
+                    wrlock: find_clock_lock(b"pthread_rwlock_clockwrlock\0"),
+                }
+            })
+        }
+
+        /// Looks up the function named by the nul-terminated `name`, which must have the
+        /// signature of `ClockLock<T>`
+        unsafe fn find_clock_lock<T>(name: &[u8]) -> Option<ClockLock<T>> {
+            let symbol = libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr() as *const libc::c_char);
+            if symbol.is_null() {
+                None
+            } else {
+                Some(std::mem::transmute::<*mut libc::c_void, ClockLock<T>>(symbol))
+            }
+        }
+
+        /// Clock used for the deadlines of all timed waits
+        pub(crate) const WAIT_CLOCK: clockid_t = libc::CLOCK_MONOTONIC;
+
+        unsafe fn mutex_timedlock(lock: *mut pthread_mutex_t, timeout: Duration) -> i32 {
+            match clock_locks().mutex {
+                Some(clocklock) => {
+                    let abstime = abs_timespec_from_duration(WAIT_CLOCK, timeout);
+                    clocklock(lock, WAIT_CLOCK, &abstime)
+                }
+                None => timedlock_in_slices(timeout, |abstime| {
+                    pthread_mutex_timedlock(lock, abstime)
+                }),
+            }
+        }
+        unsafe fn rwlock_timedrdlock(lock: *mut pthread_rwlock_t, timeout: Duration) -> i32 {
+            match clock_locks().rdlock {
+                Some(clocklock) => {
+                    let abstime = abs_timespec_from_duration(WAIT_CLOCK, timeout);
+                    clocklock(lock, WAIT_CLOCK, &abstime)
+                }
+                None => timedlock_in_slices(timeout, |abstime| {
+                    pthread_rwlock_timedrdlock(lock, abstime)
+                }),
+            }
+        }
+        unsafe fn rwlock_timedwrlock(lock: *mut pthread_rwlock_t, timeout: Duration) -> i32 {
+            match clock_locks().wrlock {
+                Some(clocklock) => {
+                    let abstime = abs_timespec_from_duration(WAIT_CLOCK, timeout);
+                    clocklock(lock, WAIT_CLOCK, &abstime)
+                }
+                None => timedlock_in_slices(timeout, |abstime| {
+                    pthread_rwlock_timedwrlock(lock, abstime)
+                }),
+            }
+        }
+
+        /// Calls `timedlock` with deadlines on CLOCK_REALTIME at most 10ms away until it doesn't
+        /// time out or `timeout` passed on CLOCK_MONOTONIC, so that the wall clock jumping
+        /// shortens or stretches the wait by a slice at most
+        fn timedlock_in_slices(timeout: Duration, timedlock: impl Fn(&timespec) -> i32) -> i32 {
+            const SLICE: Duration = Duration::from_millis(10);
+            // `Instant` measures against CLOCK_MONOTONIC
+            let deadline = std::time::Instant::now() + timeout;
+            loop {
+                let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+                let res = timedlock(&abs_timespec_from_duration(
+                    libc::CLOCK_REALTIME,
+                    remaining.min(SLICE),
+                ));
+                if res != libc::ETIMEDOUT || std::time::Instant::now() >= deadline {
+                    return res;
+                }
+            }
+        }
+    } else {
+        /// Clock used for the deadlines of all timed waits. Only condition variables can
+        /// be switched to CLOCK_MONOTONIC here (see `set_wait_clock()`)
+        #[cfg(not(target_os = "macos"))]
+        pub(crate) const WAIT_CLOCK: clockid_t = libc::CLOCK_MONOTONIC;
+        #[cfg(target_os = "macos")]
+        pub(crate) const WAIT_CLOCK: clockid_t = libc::CLOCK_REALTIME;
+
+        unsafe fn mutex_timedlock(lock: *mut pthread_mutex_t, timeout: Duration) -> i32 {
+            let abstime = abs_timespec_from_duration(libc::CLOCK_REALTIME, timeout);
+            pthread_mutex_timedlock(lock, &abstime)
+        }
+        unsafe fn rwlock_timedrdlock(lock: *mut pthread_rwlock_t, timeout: Duration) -> i32 {
+            let abstime = abs_timespec_from_duration(libc::CLOCK_REALTIME, timeout);
+            pthread_rwlock_timedrdlock(lock, &abstime)
+        }
+        unsafe fn rwlock_timedwrlock(lock: *mut pthread_rwlock_t, timeout: Duration) -> i32 {
+            let abstime = abs_timespec_from_duration(libc::CLOCK_REALTIME, timeout);
+            pthread_rwlock_timedwrlock(lock, &abstime)
+        }
+    }
 }
 
 cfg_if::cfg_if! {
     if #[cfg(target_os = "macos")] {
         #[allow(clippy::missing_safety_doc)]
-        pub unsafe fn pthread_mutex_timedlock(lock: *mut pthread_mutex_t, abstime: &timespec) -> i32 {
+        pub unsafe fn pthread_mutex_timedlock(
+            lock: *mut pthread_mutex_t,
+            abstime: &timespec,
+        ) -> i32 {
             let mut timenow: timespec = timespec {
                 tv_sec: 0,
                 tv_nsec: 0,
@@ -55,8 +190,8 @@ cfg_if::cfg_if! {
                 res = libc::pthread_mutex_trylock(lock);
                 if res == libc::EBUSY {
                     // Check timeout before sleeping
-                    clock_gettime(CLOCK_REALTIME, &mut timenow);
-                    if timenow.tv_sec >= abstime.tv_sec && timenow.tv_nsec >= abstime.tv_nsec {
+                    clock_gettime(libc::CLOCK_REALTIME, &mut timenow);
+                    if (timenow.tv_sec, timenow.tv_nsec) >= (abstime.tv_sec, abstime.tv_nsec) {
                         return libc::ETIMEDOUT;
                     }
                     // Sleep for a bit
@@ -72,20 +207,52 @@ cfg_if::cfg_if! {
    }
 }
 
-use super::{LockGuard, LockImpl, LockInit, ReadLockGuard};
-use crate::{Result, Timeout};
+use super::{LockGuard, LockImpl, LockInit, MutexKind, MutexOptions, ReadLockGuard};
+use crate::{init_result, os_result, Error, Result, Timeout};
 
-/// Adds a duration to the current time
-pub(crate) fn abs_timespec_from_duration(d: Duration) -> timespec {
-    unsafe {
-        #[allow(clippy::uninit_assumed_init)]
-        let mut cur_time: timespec = MaybeUninit::uninit().assume_init();
+/// Configures a condition variable to measure its timeouts against `WAIT_CLOCK`
+#[cfg(not(target_os = "macos"))]
+pub(crate) unsafe fn set_wait_clock(attrs: *mut libc::pthread_condattr_t) -> Result<()> {
+    init_result(
+        "pthread_condattr_setclock",
+        libc::pthread_condattr_setclock(attrs, WAIT_CLOCK),
+    )
+}
+#[cfg(target_os = "macos")]
+pub(crate) unsafe fn set_wait_clock(_attrs: *mut libc::pthread_condattr_t) -> Result<()> {
+    Ok(())
+}
+
+/// Adds a duration to the current time of `clock`
+pub(crate) fn abs_timespec_from_duration(clock: clockid_t, d: Duration) -> timespec {
+    const NANOS_PER_SEC: i64 = 1_000_000_000;
+    let mut cur_time = MaybeUninit::<timespec>::uninit();
+    let mut cur_time = unsafe {
         // Get current time
-        clock_gettime(CLOCK_REALTIME, &mut cur_time);
-        // Add duration
-        cur_time.tv_sec += d.as_secs() as nix::sys::time::time_t;
-        cur_time.tv_nsec += d.subsec_nanos() as nix::sys::time::time_t;
-        cur_time
+        clock_gettime(clock, cur_time.as_mut_ptr());
+        cur_time.assume_init()
+    };
+    // Add duration, carrying nanoseconds over into seconds so tv_nsec stays below 1e9
+    let nsec = cur_time.tv_nsec as i64 + d.subsec_nanos() as i64;
+    let secs = (d.as_secs() as i64).saturating_add(nsec / NANOS_PER_SEC);
+    cur_time.tv_sec = (cur_time.tv_sec as i64).saturating_add(secs) as _;
+    cur_time.tv_nsec = (nsec % NANOS_PER_SEC) as _;
+    cur_time
+}
+
+/// Lets the robust mutex at `lock` be used again after `EOWNERDEAD` handed it to the caller.
+/// Unlocking it without doing so would leave it unusable for good.
+/// # Safety
+/// `lock` has to point to an initialized mutex held by the calling thread.
+pub(crate) unsafe fn mark_consistent(lock: *mut pthread_mutex_t) -> Result<()> {
+    cfg_if::cfg_if! {
+        if #[cfg(any(target_os = "linux", target_os = "freebsd"))] {
+            //trace!("pthread_mutex_consistent({:p})", lock);
+            os_result("pthread_mutex_consistent", pthread_mutex_consistent(lock))
+        } else {
+            let _ = lock;
+            Ok(())
+        }
     }
 }
 
@@ -105,29 +272,15 @@ impl LockInit for Mutex {
 
     #[allow(clippy::new_ret_no_self)]
     unsafe fn new(mem: *mut u8, data: *mut u8) -> Result<(Box<dyn LockImpl>, usize)> {
+        Self::new_with_options(mem, data, MutexOptions::default())
+    }
+
+    unsafe fn from_existing(mem: *mut u8, data: *mut u8) -> Result<(Box<dyn LockImpl>, usize)> {
         let padding = mem.align_offset(size_of::<*mut u8>() as _);
-        #[allow(clippy::uninit_assumed_init)]
-        let mut lock_attr: pthread_mutexattr_t = MaybeUninit::uninit().assume_init();
-        //trace!("pthread_mutexattr_init");
-        if pthread_mutexattr_init(&mut lock_attr) != 0 {
-            return Err(From::from(
-                "Failed to initialize pthread_mutexattr_t".to_string(),
-            ));
-        }
-        //trace!("pthread_mutexattr_setpshared");
-        if pthread_mutexattr_setpshared(&mut lock_attr, PTHREAD_PROCESS_SHARED) != 0 {
-            return Err(From::from(
-                "Failed to set pthread_mutexattr_setpshared(PTHREAD_PROCESS_SHARED)".to_string(),
-            ));
-        }
+
         let ptr = mem.add(padding) as *mut _;
-        //trace!("pthread_mutex_init({:p})", ptr);
-        if pthread_mutex_init(ptr, &lock_attr) != 0 {
-            return Err(From::from(
-                "Failed to initialize mutex pthread_mutex_init".to_string(),
-            ));
-        }
 
+        //trace!("existing mutex ({:p})", ptr);
         let mutex = Box::new(Self {
             ptr,
             data: UnsafeCell::new(data),
@@ -135,13 +288,59 @@ impl LockInit for Mutex {
 
         Ok((mutex, (ptr as usize - mem as usize) + Self::size_of(None)))
     }
+}
 
-    unsafe fn from_existing(mem: *mut u8, data: *mut u8) -> Result<(Box<dyn LockImpl>, usize)> {
+impl Mutex {
+    /// Same as `Mutex::new()` with the kind and protocol of the mutex picked by `options`
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn new_with_options(
+        mem: *mut u8,
+        data: *mut u8,
+        options: MutexOptions,
+    ) -> Result<(Box<dyn LockImpl>, usize)> {
         let padding = mem.align_offset(size_of::<*mut u8>() as _);
-
+        #[allow(clippy::uninit_assumed_init)]
+        let mut lock_attr: pthread_mutexattr_t = MaybeUninit::uninit().assume_init();
+        //trace!("pthread_mutexattr_init");
+        init_result(
+            "pthread_mutexattr_init",
+            pthread_mutexattr_init(&mut lock_attr),
+        )?;
+        //trace!("pthread_mutexattr_setpshared");
+        init_result(
+            "pthread_mutexattr_setpshared",
+            pthread_mutexattr_setpshared(&mut lock_attr, PTHREAD_PROCESS_SHARED),
+        )?;
+        let kind = match options.kind {
+            MutexKind::Normal => PTHREAD_MUTEX_NORMAL,
+            MutexKind::ErrorCheck => PTHREAD_MUTEX_ERRORCHECK,
+            MutexKind::Recursive => PTHREAD_MUTEX_RECURSIVE,
+        };
+        //trace!("pthread_mutexattr_settype");
+        init_result(
+            "pthread_mutexattr_settype",
+            pthread_mutexattr_settype(&mut lock_attr, kind),
+        )?;
+        if options.priority_inheritance {
+            //trace!("pthread_mutexattr_setprotocol");
+            init_result(
+                "pthread_mutexattr_setprotocol",
+                pthread_mutexattr_setprotocol(&mut lock_attr, PTHREAD_PRIO_INHERIT),
+            )?;
+        }
+        #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+        {
+            //trace!("pthread_mutexattr_setrobust");
+            init_result(
+                "pthread_mutexattr_setrobust",
+                pthread_mutexattr_setrobust(&mut lock_attr, PTHREAD_MUTEX_ROBUST),
+            )?;
+        }
         let ptr = mem.add(padding) as *mut _;
+        //trace!("pthread_mutex_init({:p})", ptr);
+        init_result("pthread_mutex_init", pthread_mutex_init(ptr, &lock_attr))?;
 
-        //trace!("existing mutex ({:p})", ptr);
         let mutex = Box::new(Self {
             ptr,
             data: UnsafeCell::new(data),
@@ -149,6 +348,22 @@ impl LockInit for Mutex {
 
         Ok((mutex, (ptr as usize - mem as usize) + Self::size_of(None)))
     }
+
+    /// Turns the result of `call` into a guard. When the previous owner died while holding
+    /// the mutex, it is marked consistent and released again before reporting `OwnerDead`,
+    /// the same as on Windows, so that the next attempt gets the lock.
+    fn lock_result(&self, call: &'static str, res: i32) -> Result<LockGuard<'_>> {
+        if res == libc::EOWNERDEAD {
+            unsafe {
+                mark_consistent(self.ptr)?;
+                pthread_mutex_unlock(self.ptr);
+            }
+            return Err(Error::OwnerDead);
+        }
+        os_result(call, res)?;
+
+        Ok(LockGuard::new(self))
+    }
 }
 
 impl Drop for Mutex {
@@ -163,34 +378,25 @@ impl LockImpl for Mutex {
     fn lock(&self) -> Result<LockGuard<'_>> {
         let res = unsafe { pthread_mutex_lock(self.ptr) };
         //trace!("pthread_mutex_lock({:p})", self.ptr);
-        if res != 0 {
-            return Err(From::from(format!("Failed to acquire mutex : {}", res)));
-        }
-
-        Ok(LockGuard::new(self))
+        self.lock_result("pthread_mutex_lock", res)
     }
 
     fn try_lock(&self, timeout: Timeout) -> Result<LockGuard<'_>> {
-        let timespec: timespec = match timeout {
+        let res = match timeout {
             Timeout::Infinite => return self.lock(),
-            Timeout::Val(d) => abs_timespec_from_duration(d),
+            Timeout::Val(d) => unsafe { mutex_timedlock(self.ptr, d) },
         };
-
-        let res = unsafe { pthread_mutex_timedlock(self.ptr, &timespec) };
         //trace!("pthread_mutex_timedlock({:p})", self.ptr);
-        if res != 0 {
-            return Err(From::from(format!("Failed to acquire mutex : {}", res)));
-        }
-
-        Ok(LockGuard::new(self))
+        self.lock_result("pthread_mutex_timedlock", res)
     }
 
     fn release(&self) -> Result<()> {
         let res = unsafe { pthread_mutex_unlock(self.ptr) };
         //trace!("pthread_mutex_unlock({:p})", self.ptr);
-        if res != 0 {
-            return Err(From::from(format!("Failed to release mutex : {}", res)));
+        if res == EPERM {
+            return Err(Error::NotOwner);
         }
+        os_result("pthread_mutex_unlock", res)?;
         Ok(())
     }
     unsafe fn get_inner(&self) -> &mut *mut u8 {
@@ -217,23 +423,17 @@ impl LockInit for RwLock {
         let padding = mem.align_offset(size_of::<*mut u8>() as _);
         #[allow(clippy::uninit_assumed_init)]
         let mut lock_attr: pthread_rwlockattr_t = MaybeUninit::uninit().assume_init();
-        if pthread_rwlockattr_init(&mut lock_attr) != 0 {
-            return Err(From::from(
-                "Failed to initialize pthread_rwlockattr_t".to_string(),
-            ));
-        }
-        if pthread_rwlockattr_setpshared(&mut lock_attr, PTHREAD_PROCESS_SHARED) != 0 {
-            return Err(From::from(
-                "Failed to set pthread_rwlockattr_setpshared(PTHREAD_PROCESS_SHARED)".to_string(),
-            ));
-        }
+        init_result(
+            "pthread_rwlockattr_init",
+            pthread_rwlockattr_init(&mut lock_attr),
+        )?;
+        init_result(
+            "pthread_rwlockattr_setpshared",
+            pthread_rwlockattr_setpshared(&mut lock_attr, PTHREAD_PROCESS_SHARED),
+        )?;
         let ptr = mem.add(padding) as *mut _;
         //trace!("pthread_rwlock_init({:p})", ptr);
-        if pthread_rwlock_init(ptr, &lock_attr) != 0 {
-            return Err(From::from(
-                "Failed to initialize pthread_rwlock_init".to_string(),
-            ));
-        }
+        init_result("pthread_rwlock_init", pthread_rwlock_init(ptr, &lock_attr))?;
 
         let lock = Box::new(Self {
             ptr,
@@ -270,30 +470,18 @@ impl LockImpl for RwLock {
     fn lock(&self) -> Result<LockGuard<'_>> {
         let res = unsafe { pthread_rwlock_wrlock(self.ptr) };
         //trace!("pthread_rwlock_wrlock({:p})", self.ptr);
-        if res != 0 {
-            return Err(From::from(format!(
-                "Failed to acquire writeable rwlock : {}",
-                res
-            )));
-        }
+        os_result("pthread_rwlock_wrlock", res)?;
 
         Ok(LockGuard::new(self))
     }
 
     fn try_lock(&self, timeout: Timeout) -> Result<LockGuard<'_>> {
-        let timespec: timespec = match timeout {
+        let res = match timeout {
             Timeout::Infinite => return self.lock(),
-            Timeout::Val(d) => abs_timespec_from_duration(d),
+            Timeout::Val(d) => unsafe { rwlock_timedwrlock(self.ptr, d) },
         };
-
-        let res = unsafe { pthread_rwlock_timedwrlock(self.ptr, &timespec) };
         //trace!("pthread_rwlock_timedwrlock({:p})", self.ptr);
-        if res != 0 {
-            return Err(From::from(format!(
-                "Failed to acquire writeable rwlock : {}",
-                res
-            )));
-        }
+        os_result("pthread_rwlock_timedwrlock", res)?;
 
         Ok(LockGuard::new(self))
     }
@@ -301,30 +489,18 @@ impl LockImpl for RwLock {
     fn rlock(&self) -> Result<ReadLockGuard<'_>> {
         let res = unsafe { pthread_rwlock_rdlock(self.ptr) };
         //trace!("pthread_rwlock_rdlock({:p})", self.ptr);
-        if res != 0 {
-            return Err(From::from(format!(
-                "Failed to acquire readable rwlock : {}",
-                res
-            )));
-        }
+        os_result("pthread_rwlock_rdlock", res)?;
 
         Ok(ReadLockGuard::new(self))
     }
 
     fn try_rlock(&self, timeout: Timeout) -> Result<ReadLockGuard<'_>> {
-        let timespec: timespec = match timeout {
+        let res = match timeout {
             Timeout::Infinite => return self.rlock(),
-            Timeout::Val(d) => abs_timespec_from_duration(d),
+            Timeout::Val(d) => unsafe { rwlock_timedrdlock(self.ptr, d) },
         };
-
-        let res = unsafe { pthread_rwlock_timedrdlock(self.ptr, &timespec) };
         //trace!("pthread_rwlock_timedrdlock({:p})", self.ptr);
-        if res != 0 {
-            return Err(From::from(format!(
-                "Failed to acquire readable rwlock : {}",
-                res
-            )));
-        }
+        os_result("pthread_rwlock_timedrdlock", res)?;
 
         Ok(ReadLockGuard::new(self))
     }
@@ -332,9 +508,7 @@ impl LockImpl for RwLock {
     fn release(&self) -> Result<()> {
         let res = unsafe { pthread_rwlock_unlock(self.ptr) };
         //trace!("pthread_rwlock_unlock({:p})", self.ptr);
-        if res != 0 {
-            return Err(From::from(format!("Failed to release rwlock : {}", res)));
-        }
+        os_result("pthread_rwlock_unlock", res)?;
         Ok(())
     }
     unsafe fn get_inner(&self) -> &mut *mut u8 {
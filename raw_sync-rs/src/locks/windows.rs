@@ -2,33 +2,102 @@ use std::cell::UnsafeCell;
 use std::ffi::CString;
 use std::mem::size_of;
 use std::ptr::null_mut;
+use std::sync::atomic::{AtomicU32, Ordering};
 
 pub const MUTEX_ALL_ACCESS: u32 = 0x1F0001;
 use winapi::{
-    shared::ntdef::{FALSE, NULL},
+    shared::{
+        ntdef::{FALSE, NULL},
+        winerror::WAIT_TIMEOUT,
+    },
     um::{
+        errhandlingapi::GetLastError,
         handleapi::CloseHandle,
+        processthreadsapi::GetCurrentThreadId,
         synchapi::{CreateMutexExA, ReleaseMutex, WaitForSingleObject, CREATE_MUTEX_INITIAL_OWNER},
         winbase::{OpenMutexA, INFINITE, WAIT_ABANDONED, WAIT_OBJECT_0},
         winnt::{HANDLE, SYNCHRONIZE},
     },
 };
 
-use super::{LockGuard, LockImpl, LockInit};
-use crate::{Result, Timeout};
+use super::{LockGuard, LockImpl, LockInit, MutexKind, MutexOptions};
+use crate::{Error, Result, Timeout};
+
+/// What the backing memory of a mutex holds
+#[repr(C)]
+struct MutexState {
+    /// Name of the OS mutex is `mutex_<id>`
+    id: u32,
+    /// Non zero when the owner may lock the mutex again
+    recursive: u32,
+    /// Id of the thread holding a non recursive mutex, 0 when unlocked. Windows mutexes are
+    /// always recursive, this lets a second `lock()` from the owner fail instead.
+    owner: AtomicU32,
+}
 
 pub struct Mutex {
     handle: HANDLE,
+    state: *const MutexState,
     data: UnsafeCell<*mut u8>,
 }
 
 impl LockInit for Mutex {
     fn size_of(_addr: Option<*mut u8>) -> usize {
-        size_of::<u32>()
+        size_of::<MutexState>()
     }
 
     #[allow(clippy::new_ret_no_self)]
     unsafe fn new(mem: *mut u8, data: *mut u8) -> Result<(Box<dyn LockImpl>, usize)> {
+        Self::new_with_options(mem, data, MutexOptions::default())
+    }
+
+    unsafe fn from_existing(mem: *mut u8, data: *mut u8) -> Result<(Box<dyn LockImpl>, usize)> {
+        let state = mem as *const MutexState;
+        let mutex_id = (*state).id;
+        let path = CString::new(format!("mutex_{}", mutex_id)).unwrap();
+        //trace!("OpenMutexA(0x{:X}, 0x{:X}, '{}')", SYNCHRONIZE,FALSE,path.to_string_lossy());
+        let mutex_handle = OpenMutexA(SYNCHRONIZE, FALSE as _, path.as_ptr() as *mut _);
+        if mutex_handle == NULL {
+            return Err(Error::InitFailed {
+                call: "OpenMutexA",
+                errno: GetLastError() as _,
+            });
+        }
+
+        let mutex = Box::new(Self {
+            handle: mutex_handle,
+            state,
+            data: UnsafeCell::new(data),
+        });
+
+        Ok((mutex, Self::size_of(None)))
+    }
+}
+
+impl Drop for Mutex {
+    fn drop(&mut self) {
+        //trace!("CloseHandle(0x{:X})", self.handle as usize);
+        unsafe { CloseHandle(self.handle) };
+    }
+}
+
+impl Mutex {
+    /// Same as `Mutex::new()` with the kind of the mutex picked by `options`.
+    /// `Normal` and `ErrorCheck` mutexes both fail with `Error::Deadlock` when their owner locks
+    /// them again. Windows doesn't support priority inheritance.
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn new_with_options(
+        mem: *mut u8,
+        data: *mut u8,
+        options: MutexOptions,
+    ) -> Result<(Box<dyn LockImpl>, usize)> {
+        if options.priority_inheritance {
+            return Err(Error::InvalidArgument(
+                "priority inheritance is not supported on Windows",
+            ));
+        }
+
         // Find a mutex id that doesnt collide with another
         let mut mutex_handle: HANDLE = NULL;
         let mut mutex_id: u32 = 0;
@@ -44,44 +113,58 @@ impl LockInit for Mutex {
             );
         }
 
+        // Write the mutex id to the backing memory, we own the mutex until released below
+        let state = mem as *mut MutexState;
+        state.write(MutexState {
+            id: mutex_id,
+            recursive: (options.kind == MutexKind::Recursive) as u32,
+            owner: AtomicU32::new(GetCurrentThreadId()),
+        });
+
         // Create our mutex struct
         let mutex = Box::new(Self {
             handle: mutex_handle,
+            state,
             data: UnsafeCell::new(data),
         });
         mutex.release()?;
 
-        // Write the mutex id to the backing memory
-        *(mem as *mut u32) = mutex_id;
-
         Ok((mutex, Self::size_of(None)))
     }
 
-    unsafe fn from_existing(mem: *mut u8, data: *mut u8) -> Result<(Box<dyn LockImpl>, usize)> {
-        let mutex_id = *(mem as *mut u32);
-        let path = CString::new(format!("mutex_{}", mutex_id)).unwrap();
-        //trace!("OpenMutexA(0x{:X}, 0x{:X}, '{}')", SYNCHRONIZE,FALSE,path.to_string_lossy());
-        let mutex_handle = OpenMutexA(SYNCHRONIZE, FALSE as _, path.as_ptr() as *mut _);
-        if mutex_handle == NULL {
-            return Err(From::from(format!(
-                "Failed to open mutex {}",
-                path.to_string_lossy()
-            )));
-        }
-
-        let mutex = Box::new(Self {
-            handle: mutex_handle,
-            data: UnsafeCell::new(data),
-        });
+    fn is_recursive(&self) -> bool {
+        unsafe { (*self.state).recursive != 0 }
+    }
 
-        Ok((mutex, Self::size_of(None)))
+    fn owner(&self) -> &AtomicU32 {
+        unsafe { &(*self.state).owner }
     }
-}
 
-impl Drop for Mutex {
-    fn drop(&mut self) {
-        //trace!("CloseHandle(0x{:X})", self.handle as usize);
-        unsafe { CloseHandle(self.handle) };
+    fn wait_result(&self, wait_res: u32) -> Result<LockGuard<'_>> {
+        if wait_res == WAIT_OBJECT_0 {
+            if !self.is_recursive() {
+                // Only the owner itself can have left its id there
+                let thread_id = unsafe { GetCurrentThreadId() };
+                if self.owner().load(Ordering::Relaxed) == thread_id {
+                    unsafe { ReleaseMutex(self.handle) };
+                    return Err(Error::Deadlock);
+                }
+                self.owner().store(thread_id, Ordering::Relaxed);
+            }
+            Ok(LockGuard::new(self))
+        } else if wait_res == WAIT_ABANDONED {
+            // We were handed ownership of the abandoned mutex, don't keep it locked
+            self.owner().store(0, Ordering::Relaxed);
+            unsafe { ReleaseMutex(self.handle) };
+            Err(Error::OwnerDead)
+        } else if wait_res == WAIT_TIMEOUT {
+            Err(Error::TimedOut)
+        } else {
+            Err(Error::Os {
+                call: "WaitForSingleObject",
+                errno: unsafe { GetLastError() } as _,
+            })
+        }
     }
 }
 
@@ -93,16 +176,7 @@ impl LockImpl for Mutex {
     fn lock(&self) -> Result<LockGuard<'_>> {
         let wait_res = unsafe { WaitForSingleObject(self.handle, INFINITE) };
         //trace!("WaitForSingleObject(0x{:X})", self.handle as usize);
-        if wait_res == WAIT_OBJECT_0 {
-            Ok(LockGuard::new(self))
-        } else if wait_res == WAIT_ABANDONED {
-            panic!("A thread holding the mutex has left it in a poisened state");
-        } else {
-            Err(From::from(format!(
-                "Failed to aquire lock with value : 0x{:X}",
-                wait_res
-            )))
-        }
+        self.wait_result(wait_res)
     }
 
     fn try_lock(&self, timeout: Timeout) -> Result<LockGuard<'_>> {
@@ -116,24 +190,22 @@ impl LockImpl for Mutex {
             )
         };
         //trace!("WaitForSingleObject(0x{:X})", self.handle as usize);
-        if wait_res == WAIT_OBJECT_0 {
-            Ok(LockGuard::new(self))
-        } else if wait_res == WAIT_ABANDONED {
-            panic!("A thread holding the mutex has left it in a poisened state");
-        } else {
-            Err(From::from(format!(
-                "Failed to aquire lock with value : 0x{:X}",
-                wait_res
-            )))
-        }
+        self.wait_result(wait_res)
     }
 
     fn release(&self) -> Result<()> {
+        if !self.is_recursive() {
+            if self.owner().load(Ordering::Relaxed) != unsafe { GetCurrentThreadId() } {
+                return Err(Error::NotOwner);
+            }
+            self.owner().store(0, Ordering::Relaxed);
+        }
         //trace!("ReleaseMutex(0x{:X})", self.handle as usize);
         if unsafe { ReleaseMutex(self.handle) } == 0 {
-            Err(From::from(
-                "Could not release mutex as we did not own it".to_string(),
-            ))
+            Err(Error::Os {
+                call: "ReleaseMutex",
+                errno: unsafe { GetLastError() } as _,
+            })
         } else {
             Ok(())
         }
@@ -0,0 +1,34 @@
+use shared_memory::*;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Room for a 4K frame of u32 pixels
+    let size = 3840 * 2160 * 4;
+    let options = MemoryOptions::default().huge_pages().populate().lock();
+
+    println!("Creating a {}MB mapping", size / (1024 * 1024));
+    let owner = ShmemConf::new()
+        .size(size)
+        .memory_options(options)
+        .create()?;
+    println!("Owner : {:?}", owner.memory_outcome());
+
+    // Every process applies the options to its own mapping
+    let opener = ShmemConf::new()
+        .os_id(owner.get_os_id())
+        .memory_options(options)
+        .open()?;
+    println!("Opener : {:?}", opener.memory_outcome());
+
+    #[cfg(target_os = "linux")]
+    {
+        // Memfd mappings can use the huge pages reserved in /proc/sys/vm/nr_hugepages
+        let memfd = ShmemConf::new()
+            .size(size)
+            .memfd()
+            .memory_options(options)
+            .create()?;
+        println!("Memfd : {:?}", memfd.memory_outcome());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,49 @@
+//! Shares an anonymous memfd mapping with a child process, first by letting it inherit the
+//! file descriptor, then by sending it over a unix socket. Nothing shows up in /dev/shm.
+
+#[cfg(target_os = "linux")]
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    use shared_memory::*;
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::UnixStream;
+
+    // Child : open the mapping from the inherited fd number
+    if let Some(fd) = std::env::args().nth(1) {
+        let shmem = ShmemConf::new().fd(fd.parse()?).open()?;
+        println!("[child] Read {} from fd {}", unsafe { *shmem.as_ptr() }, fd);
+        return Ok(());
+    }
+
+    println!("Creating a memfd mapping");
+    let shmem = ShmemConf::new()
+        .size(4096)
+        .os_id("example")
+        .memfd()
+        .create()?;
+    unsafe { *shmem.as_ptr() = 42 };
+
+    println!("Spawning a child that inherits fd {}", shmem.as_raw_fd());
+    shmem.set_inheritable(true)?;
+    let status = std::process::Command::new(std::env::current_exe()?)
+        .arg(shmem.as_raw_fd().to_string())
+        .status()?;
+    shmem.set_inheritable(false)?;
+    assert!(status.success());
+
+    println!("Sending the fd over a unix socket");
+    let (sender, receiver) = UnixStream::pair()?;
+    send_fd(&sender, shmem.as_raw_fd())?;
+    let received = ShmemConf::new().fd(recv_fd(&receiver)?).open()?;
+    println!(
+        "Read {} through {}",
+        unsafe { *received.as_ptr() },
+        received.get_os_id()
+    );
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn main() {
+    println!("memfd mappings are only available on Linux");
+}
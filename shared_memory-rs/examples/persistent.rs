@@ -0,0 +1,22 @@
+use shared_memory::*;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Every run picks up the counter left in the file by the previous one
+    let path = std::env::temp_dir().join("shared_memory_persistent_example");
+    let shmem = ShmemConf::new()
+        .size(std::mem::size_of::<u64>())
+        .file_backed(&path)
+        .create()?;
+
+    let runs = unsafe { &mut *(shmem.as_ptr() as *mut u64) };
+    if shmem.is_restored() {
+        println!("Restored {} from {}", *runs, path.display());
+    } else {
+        println!("Created {}", path.display());
+    }
+    *runs += 1;
+    shmem.flush()?;
+    println!("This program ran {} time(s)", *runs);
+
+    Ok(())
+}
@@ -0,0 +1,23 @@
+use shared_memory::*;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("Creating a 4KB mapping");
+    let mut owner = ShmemConf::new().size(4096).create()?;
+    let mut opener = ShmemConf::new().os_id(owner.get_os_id()).open()?;
+    unsafe { *owner.as_ptr() = 42 };
+
+    println!("Growing the mapping to 1MB");
+    owner.resize(1024 * 1024)?;
+    unsafe { *owner.as_ptr().add(owner.len() - 1) = 24 };
+
+    // The opener still sees the old size until it remaps
+    println!("Opener sees {} bytes", opener.len());
+    if opener.remap_if_resized()? {
+        println!("Opener remapped to {} bytes", opener.len());
+    }
+    let (first, last) = unsafe { (*opener.as_ptr(), *opener.as_ptr().add(opener.len() - 1)) };
+    println!("First byte : {}, last byte : {}", first, last);
+    assert_eq!((first, last), (42, 24));
+
+    Ok(())
+}
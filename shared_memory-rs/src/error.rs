@@ -1,57 +1,144 @@
-use ::quick_error::quick_error;
+use std::fmt;
+use std::io;
 
-quick_error! {
-    #[derive(Debug)]
-    pub enum ShmemError {
-        MapSizeZero {
-            description("You cannot create a shared memory mapping of 0 size")
-        }
-        NoLinkOrOsId {
-            description("Tried to open mapping without flink path or os_id")
-        }
-        FlinkInvalidOsId {
-            description("Tried to open mapping from both flink and os_id but the flink did not point to the same os_id")
-        }
-        LinkCreateFailed(err: std::io::Error) {
-            description("Creating the link file failed")
-            display(x) -> ("{} : {}", x, err)
-            cause(err)
-        }
-        LinkWriteFailed(err: std::io::Error) {
-            description("Writing the link file failed")
-            display(x) -> ("{} : {}", x, err)
-            cause(err)
-        }
-        LinkExists {
-            description("Shared memory link already exists")
-        }
-        LinkOpenFailed(err: std::io::Error) {
-            description("Openning the link file failed")
-            display(x) -> ("{} : {}", x, err)
-            cause(err)
-        }
-        LinkReadFailed(err: std::io::Error) {
-            description("Reading the link file failed")
-            display(x) -> ("{} : {}", x, err)
-            cause(err)
-        }
-        LinkDoesNotExist {
-            description("Requested link file does not exist")
-        }
-        MappingIdExists {
-            description("Shared memory OS specific ID already exists")
+/// A failed system call, with the mapping it was made for
+#[derive(Debug)]
+pub struct OsError {
+    syscall: &'static str,
+    os_id: String,
+    size: usize,
+    error: io::Error,
+}
+impl OsError {
+    pub(crate) fn new(syscall: &'static str, os_id: &str, size: usize, code: i32) -> Self {
+        Self {
+            syscall,
+            os_id: String::from(os_id),
+            size,
+            error: io::Error::from_raw_os_error(code),
         }
-        MapCreateFailed(err: u32) {
-            description("Creating the shared memory failed")
-            display(x) -> ("{} : os error {}", x, err)
+    }
+    /// Returns the name of the system call that failed (e.g. `shm_open`, `ftruncate`, `mmap`)
+    pub fn syscall(&self) -> &'static str {
+        self.syscall
+    }
+    /// Returns the os_id or path of the mapping
+    pub fn os_id(&self) -> &str {
+        &self.os_id
+    }
+    /// Returns the size of the mapping at the time of the call, 0 when it wasn't known yet
+    pub fn size(&self) -> usize {
+        self.size
+    }
+    /// Returns the error code reported by the OS (`errno` or `GetLastError()`)
+    pub fn raw_os_error(&self) -> i32 {
+        self.error.raw_os_error().unwrap_or(0)
+    }
+    /// Returns the `errno` the system call failed with
+    #[cfg(unix)]
+    pub fn errno(&self) -> nix::errno::Errno {
+        nix::errno::Errno::from_i32(self.raw_os_error())
+    }
+}
+impl fmt::Display for OsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}(\"{}\", {} bytes) failed",
+            self.syscall, self.os_id, self.size
+        )
+    }
+}
+impl std::error::Error for OsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+#[derive(Debug)]
+pub enum ShmemError {
+    MapSizeZero,
+    NoLinkOrOsId,
+    FlinkInvalidOsId,
+    LinkCreateFailed(io::Error),
+    LinkWriteFailed(io::Error),
+    LinkExists,
+    LinkOpenFailed(io::Error),
+    LinkReadFailed(io::Error),
+    LinkDoesNotExist,
+    MappingIdExists,
+    MapCreateFailed(OsError),
+    MapOpenFailed(OsError),
+    InvalidHeader,
+    NameMismatch,
+    TagMismatch,
+    ResizeNotOwner,
+    ResizeUnsupported,
+    ResizeFailed(OsError),
+    FlinkUnsupported,
+    FileOpenFailed(io::Error),
+    FlushFailed(OsError),
+    FdPassingFailed(OsError),
+    UnknownOsError(OsError),
+}
+impl ShmemError {
+    /// Returns the failed system call behind the error, if any
+    pub fn os_error(&self) -> Option<&OsError> {
+        use ShmemError::*;
+        match self {
+            MapCreateFailed(e) | MapOpenFailed(e) | ResizeFailed(e) | FlushFailed(e)
+            | FdPassingFailed(e) | UnknownOsError(e) => Some(e),
+            _ => None,
         }
-        MapOpenFailed(err: u32) {
-            description("Openning the shared memory failed")
-            display(x) -> ("{} : os error {}", x, err)
+    }
+}
+impl fmt::Display for ShmemError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ShmemError::*;
+        let msg = match self {
+            MapSizeZero => "You cannot create a shared memory mapping of 0 size",
+            NoLinkOrOsId => "Tried to open mapping without flink path or os_id",
+            FlinkInvalidOsId => "Tried to open mapping from both flink and os_id but the flink did not point to the same os_id",
+            LinkCreateFailed(_) => "Creating the link file failed",
+            LinkWriteFailed(_) => "Writing the link file failed",
+            LinkExists => "Shared memory link already exists",
+            LinkOpenFailed(_) => "Openning the link file failed",
+            LinkReadFailed(_) => "Reading the link file failed",
+            LinkDoesNotExist => "Requested link file does not exist",
+            MappingIdExists => "Shared memory OS specific ID already exists",
+            MapCreateFailed(_) => "Creating the shared memory failed",
+            MapOpenFailed(_) => "Openning the shared memory failed",
+            InvalidHeader => "The shared memory was not created by this crate or is corrupted",
+            NameMismatch => "The shared memory was created with another name whose os_id collides",
+            TagMismatch => "The file backing the shared memory was saved with another tag",
+            ResizeNotOwner => "Only the owner of the shared memory can resize it",
+            ResizeUnsupported => "Resizing shared memory is not supported on this platform",
+            ResizeFailed(_) => "Resizing the shared memory failed",
+            FlinkUnsupported => "File links can only be used with named mappings, not memfd or file backed ones",
+            FileOpenFailed(_) => "Openning the file backing the mapping failed",
+            FlushFailed(_) => "Flushing the mapping to its backing file failed",
+            FdPassingFailed(_) => "Passing the shared memory file descriptor failed",
+            UnknownOsError(_) => "An unexpected OS error occured",
+        };
+        //The underlying error is reported by `source()`, but keep it in the message for
+        //callers that only print the top level error
+        match self {
+            LinkCreateFailed(e) | LinkWriteFailed(e) | LinkOpenFailed(e) | LinkReadFailed(e)
+            | FileOpenFailed(e) => write!(f, "{} : {}", msg, e),
+            _ => match self.os_error() {
+                Some(e) => write!(f, "{} : {} : {}", msg, e, e.error),
+                None => f.write_str(msg),
+            },
         }
-        UnknownOsError(err: u32) {
-            description("An unexpected OS error occured")
-            display(x) -> ("{} : os error {}", x, err)
+    }
+}
+impl std::error::Error for ShmemError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use ShmemError::*;
+        match self {
+            LinkCreateFailed(e) | LinkWriteFailed(e) | LinkOpenFailed(e) | LinkReadFailed(e)
+            | FileOpenFailed(e) => Some(e),
+            _ => self.os_error().map(|e| e as _),
         }
     }
 }
@@ -0,0 +1,164 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::mem::size_of;
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use crate::ShmemError;
+
+/// Identifies mappings created by this crate ("SHM3"). Changes with the layout of the header
+/// so that files written by older versions are refused instead of misread
+const HEADER_MAGIC: u32 = 0x5348_4D33;
+
+/// Bytes reserved at the start of every mapping for the header. Keeps the user
+/// data aligned to a cache line.
+pub(crate) const HEADER_SIZE: usize = 256;
+
+/// Longest name kept in the header in bytes, longer names are truncated
+pub const NAME_CAPACITY: usize = HEADER_SIZE - 32;
+
+/// Bookkeeping stored in front of the user data of every mapping
+#[repr(C)]
+pub(crate) struct Header {
+    magic: u32,
+    /// Bumped by the owner every time the mapping is resized
+    generation: AtomicU32,
+    /// Size of the user data, excluding the header
+    size: AtomicU64,
+    /// Process id of the current owner, used to find mappings left behind by crashed owners
+    owner_pid: AtomicU32,
+    /// Length of the full logical name, which can exceed NAME_CAPACITY
+    name_len: u32,
+    /// Application defined value describing the content of the mapping
+    tag: AtomicU64,
+    /// Logical name the mapping was created with, empty if none
+    name: [u8; NAME_CAPACITY],
+}
+
+impl Header {
+    /// Writes a fresh header at the start of a newly created mapping
+    /// # Safety
+    /// `map_ptr` must point to at least `HEADER_SIZE` writable bytes
+    pub(crate) unsafe fn init<'a>(
+        map_ptr: *mut u8,
+        size: usize,
+        name: Option<&str>,
+        tag: u64,
+    ) -> &'a Self {
+        let name = name.unwrap_or("");
+        let mut stored_name = [0u8; NAME_CAPACITY];
+        let stored_len = truncated_len(name);
+        stored_name[..stored_len].copy_from_slice(&name.as_bytes()[..stored_len]);
+
+        let header = map_ptr as *mut Header;
+        header.write(Header {
+            magic: HEADER_MAGIC,
+            generation: AtomicU32::new(0),
+            size: AtomicU64::new(size as u64),
+            owner_pid: AtomicU32::new(std::process::id()),
+            name_len: name.len() as u32,
+            tag: AtomicU64::new(tag),
+            name: stored_name,
+        });
+        &*header
+    }
+
+    /// Validates the header of an existing mapping of `map_size` bytes. Only the magic is checked,
+    /// callers check that `size()` fits in the mapping
+    /// # Safety
+    /// `map_ptr` must point to `map_size` readable bytes
+    pub(crate) unsafe fn from_existing<'a>(
+        map_ptr: *mut u8,
+        map_size: usize,
+    ) -> Result<&'a Self, ShmemError> {
+        if map_size < HEADER_SIZE {
+            return Err(ShmemError::InvalidHeader);
+        }
+        let header = &*(map_ptr as *const Header);
+        if header.magic != HEADER_MAGIC {
+            return Err(ShmemError::InvalidHeader);
+        }
+        Ok(header)
+    }
+
+    /// Checks whether the file at `path` holds a mapping created by this crate. A missing or empty
+    /// file holds nothing yet, any other content is refused rather than overwritten.
+    pub(crate) fn is_in_file(path: &Path) -> Result<bool, ShmemError> {
+        let mut file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(ShmemError::FileOpenFailed(e)),
+        };
+        let mut magic = [0u8; size_of::<u32>()];
+        match file.read_exact(&mut magic) {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                return match file.metadata() {
+                    Ok(m) if m.len() == 0 => Ok(false),
+                    Ok(_) => Err(ShmemError::InvalidHeader),
+                    Err(e) => Err(ShmemError::FileOpenFailed(e)),
+                }
+            }
+            Err(e) => return Err(ShmemError::FileOpenFailed(e)),
+        }
+        if u32::from_ne_bytes(magic) != HEADER_MAGIC {
+            return Err(ShmemError::InvalidHeader);
+        }
+        Ok(true)
+    }
+
+    /// Returns the logical name of the mapping, truncated to NAME_CAPACITY bytes
+    pub(crate) fn name(&self) -> Option<&str> {
+        let stored_len = (self.name_len as usize).min(NAME_CAPACITY);
+        match std::str::from_utf8(&self.name[..stored_len]) {
+            Ok(name) if !name.is_empty() => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Checks that the mapping was created with the logical name `name`, as far as the
+    /// header can tell for names longer than NAME_CAPACITY
+    pub(crate) fn has_name(&self, name: &str) -> bool {
+        let stored_len = truncated_len(name);
+        self.name_len as usize == name.len()
+            && self.name[..stored_len] == name.as_bytes()[..stored_len]
+    }
+
+    pub(crate) fn generation(&self) -> u32 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn size(&self) -> usize {
+        self.size.load(Ordering::Acquire) as usize
+    }
+
+    pub(crate) fn owner_pid(&self) -> u32 {
+        self.owner_pid.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn tag(&self) -> u64 {
+        self.tag.load(Ordering::Acquire)
+    }
+
+    /// Records the calling process as the owner of the mapping
+    pub(crate) fn set_owner_pid(&self) {
+        self.owner_pid.store(std::process::id(), Ordering::Release);
+    }
+
+    /// Publishes a new size to the other processes and returns the new generation
+    pub(crate) fn set_size(&self, size: usize) -> u32 {
+        self.size.store(size as u64, Ordering::Release);
+        self.generation
+            .fetch_add(1, Ordering::AcqRel)
+            .wrapping_add(1)
+    }
+}
+
+/// Number of bytes of `name` that fit in the header, without splitting a character
+fn truncated_len(name: &str) -> usize {
+    let mut len = name.len().min(NAME_CAPACITY);
+    while !name.is_char_boundary(len) {
+        len -= 1;
+    }
+    len
+}
@@ -12,6 +12,11 @@ use ::cfg_if::*;
 
 mod error;
 pub use error::*;
+mod header;
+pub use header::NAME_CAPACITY;
+use header::{Header, HEADER_SIZE};
+mod memory;
+pub use memory::*;
 
 //Load up the proper OS implementation
 cfg_if! {
@@ -21,18 +26,45 @@ cfg_if! {
     } else if #[cfg(any(target_os="freebsd", target_os="linux", target_os="macos"))] {
         mod unix;
         use crate::unix as os_impl;
+        pub use os_impl::{recv_fd, send_fd};
+        use std::os::unix::io::{AsRawFd, RawFd};
     } else {
         compile_error!("shared_memory isnt implemented for this platform...");
     }
 }
 
+/// Builds a short os_id, usable on every platform, from an arbitrarily long logical name
+///
+/// The os_id is `prefix` followed by a 64 bit hash of `name`, so `prefix` must stay short
+/// (macOS limits os_ids to 31 characters).
+pub fn os_id_for_name(prefix: &str, name: &str) -> String {
+    format!("/{}{:016X}", prefix, stable_hash(name.as_bytes()))
+}
+
+/// 64 bit FNV-1a hash of `bytes`, which unlike std's hasher is stable across platforms and
+/// compiler versions
+pub fn stable_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
 /// Struct used to configure different parameters before creating a shared memory mapping
 pub struct ShmemConf {
     owner: bool,
     os_id: Option<String>,
+    name: Option<String>,
     overwrite_flink: bool,
     flink_path: Option<PathBuf>,
+    file_path: Option<PathBuf>,
     size: usize,
+    read_only: bool,
+    tag: u64,
+    memory_options: MemoryOptions,
+    #[cfg(target_os = "linux")]
+    memfd: bool,
+    #[cfg(unix)]
+    fd: Option<RawFd>,
 }
 impl Drop for ShmemConf {
     fn drop(&mut self) {
@@ -51,9 +83,18 @@ impl ShmemConf {
         Self {
             owner: false,
             os_id: None,
+            name: None,
             overwrite_flink: false,
             flink_path: None,
+            file_path: None,
             size: 0,
+            read_only: false,
+            tag: 0,
+            memory_options: MemoryOptions::default(),
+            #[cfg(target_os = "linux")]
+            memfd: false,
+            #[cfg(unix)]
+            fd: None,
         }
     }
     /// Provide a specific os identifier for the mapping
@@ -64,6 +105,16 @@ impl ShmemConf {
         self
     }
 
+    /// Identify the mapping by a logical name of any length
+    ///
+    /// The name is stored in the mapping, `open()` fails with `ShmemError::NameMismatch` when
+    /// the mapping was created under another name. Unless `os_id()` is also given, the os_id is
+    /// derived from the name with `os_id_for_name("shmem_", name)`.
+    pub fn name<S: AsRef<str>>(mut self, name: S) -> Self {
+        self.name = Some(String::from(name.as_ref()));
+        self
+    }
+
     /// Overwrites file links if it already exist when calling `create()`
     pub fn force_create_flink(mut self) -> Self {
         self.overwrite_flink = true;
@@ -85,27 +136,132 @@ impl ShmemConf {
         self
     }
 
+    /// Back the mapping with a regular file instead of memory only shared memory
+    ///
+    /// The file is kept when the owner drops the mapping. `create()` picks up the data of a file
+    /// previously created this way (see `Shmem::is_restored()`) and refuses to overwrite any other
+    /// non-empty file. Takes precedence over `memfd()`, use `Shmem::flush()` to control when the
+    /// data reaches the disk.
+    pub fn file_backed<S: AsRef<Path>>(mut self, path: S) -> Self {
+        self.file_path = Some(PathBuf::from(path.as_ref()));
+        self
+    }
+
+    /// Back the mapping with an anonymous memfd instead of a named object in /dev/shm
+    ///
+    /// Nothing is left behind if the owner crashes and the os_id, if any, is only used as a debugging name.
+    /// Other processes open the mapping with `fd()`, either inheriting the file descriptor at spawn
+    /// (see `Shmem::set_inheritable()`) or receiving it over a unix socket (see `send_fd()`/`recv_fd()`).
+    /// The mapping is sealed against shrinking, so `Shmem::resize()` can only grow it.
+    #[cfg(target_os = "linux")]
+    pub fn memfd(mut self) -> Self {
+        self.memfd = true;
+        self
+    }
+
+    /// Map the pages without write access in `open()`, so the mapping can't be modified through this process
+    ///
+    /// Writing through the pointer returned by `Shmem::as_ptr()` then crashes the process
+    /// instead of corrupting the mapping. Mappings are always writable for their creator.
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    /// Application defined value stored in the header by `create()`, e.g. to describe the
+    /// content of the mapping to tools inspecting it. `open()` doesn't check it, see `Shmem::tag()`,
+    /// but `create()` refuses to pick up a backing file saved with another tag.
+    pub fn tag(mut self, tag: u64) -> Self {
+        self.tag = tag;
+        self
+    }
+
+    /// Huge pages, pre-faulting and locking to apply to the pages of the mapping in this process
+    ///
+    /// The options are re-applied whenever the mapping is resized or remapped. They never make
+    /// `create()`/`open()` fail, check `Shmem::memory_outcome()` to see what the system allowed.
+    pub fn memory_options(mut self, options: MemoryOptions) -> Self {
+        self.memory_options = options;
+        self
+    }
+
+    /// Open the mapping behind a file descriptor instead of an os_id or flink
+    ///
+    /// The opened mapping takes ownership of `fd` and closes it when dropped
+    #[cfg(unix)]
+    pub fn fd(mut self, fd: RawFd) -> Self {
+        self.fd = Some(fd);
+        self
+    }
+
     /// Create a new mapping using the current configuration
     pub fn create(mut self) -> Result<Shmem, ShmemError> {
         if self.size == 0 {
             return Err(ShmemError::MapSizeZero);
         }
+        self.derive_os_id();
 
         // Create the mapping
-        let mapping = match self.os_id {
-            None => {
+        let mut restored = false;
+        let unnamed_mapping = if let Some(ref file_path) = self.file_path {
+            if self.flink_path.is_some() {
+                return Err(ShmemError::FlinkUnsupported);
+            }
+            restored = Header::is_in_file(file_path)?;
+            Some(os_impl::create_file_mapping(
+                file_path,
+                HEADER_SIZE + self.size,
+            )?)
+        } else {
+            None
+        };
+        #[cfg(target_os = "linux")]
+        let unnamed_mapping = match unnamed_mapping {
+            None if self.memfd => {
+                if self.flink_path.is_some() {
+                    return Err(ShmemError::FlinkUnsupported);
+                }
+                let name = self.os_id.as_deref().unwrap_or("shmem");
+                Some(os_impl::create_memfd_mapping(
+                    name,
+                    HEADER_SIZE + self.size,
+                    self.memory_options.wants_huge_pages(),
+                )?)
+            }
+            m => m,
+        };
+
+        let mapping = match (unnamed_mapping, self.os_id.as_ref()) {
+            (Some(m), _) => m,
+            (None, None) => {
                 // Generate random ID until one works
                 loop {
                     let cur_id = format!("/shmem_{:X}", rand::random::<u64>());
-                    match os_impl::create_mapping(&cur_id, self.size) {
+                    match os_impl::create_mapping(&cur_id, HEADER_SIZE + self.size) {
                         Err(ShmemError::MappingIdExists) => continue,
                         Ok(m) => break m,
                         Err(e) => return Err(e),
                     };
                 }
             }
-            Some(ref specific_id) => os_impl::create_mapping(specific_id, self.size)?,
+            (None, Some(specific_id)) => {
+                os_impl::create_mapping(specific_id, HEADER_SIZE + self.size)?
+            }
+        };
+        let header = if restored {
+            // Keep the data, only the size may have changed since it was saved
+            let header = unsafe { Header::from_existing(mapping.map_ptr, mapping.map_size)? };
+            self.check_name(header)?;
+            if header.tag() != self.tag {
+                return Err(ShmemError::TagMismatch);
+            }
+            header.set_owner_pid();
+            header.set_size(self.size);
+            header
+        } else {
+            unsafe { Header::init(mapping.map_ptr, self.size, self.name.as_deref(), self.tag) }
         };
+        let generation = header.generation();
 
         // Create flink
         if let Some(ref flink_path) = self.flink_path {
@@ -134,16 +290,30 @@ impl ShmemConf {
         }
 
         self.owner = true;
-        self.size = mapping.map_size;
 
+        let memory_outcome = os_impl::apply_memory_options(&mapping, self.memory_options);
         Ok(Shmem {
             config: self,
             mapping,
+            generation,
+            restored,
+            memory_outcome,
         })
     }
 
     /// Opens an existing mapping using the current configuration
     pub fn open(mut self) -> Result<Shmem, ShmemError> {
+        self.derive_os_id();
+        if let Some(ref file_path) = self.file_path {
+            let mapping = os_impl::open_file_mapping(file_path, !self.read_only)?;
+            return self.opened(mapping);
+        }
+        #[cfg(unix)]
+        if let Some(fd) = self.fd.take() {
+            let mapping = os_impl::open_fd_mapping(fd, !self.read_only)?;
+            return self.opened(mapping);
+        }
+
         // Must at least have a flink or an os_id
         if self.flink_path.is_none() && self.os_id.is_none() {
             return Err(ShmemError::NoLinkOrOsId);
@@ -177,22 +347,61 @@ impl ShmemConf {
             None => return Err(ShmemError::NoLinkOrOsId),
         };
 
-        let mapping = os_impl::open_mapping(os_id)?;
+        let mapping = os_impl::open_mapping(os_id, !self.read_only)?;
+        self.opened(mapping)
+    }
+
+    fn opened(mut self, mapping: os_impl::MapData) -> Result<Shmem, ShmemError> {
+        let header = unsafe { Header::from_existing(mapping.map_ptr, mapping.map_size)? };
+        self.check_name(header)?;
+        let generation = header.generation();
 
-        self.size = mapping.map_size;
+        self.size = header.size();
+        if !fits_after_header(self.size, mapping.map_size) {
+            return Err(ShmemError::InvalidHeader);
+        }
         self.owner = false;
 
+        let memory_outcome = os_impl::apply_memory_options(&mapping, self.memory_options);
         Ok(Shmem {
             config: self,
             mapping,
+            generation,
+            restored: false,
+            memory_outcome,
         })
     }
+
+    fn derive_os_id(&mut self) {
+        if let (None, Some(name)) = (self.os_id.as_ref(), self.name.as_ref()) {
+            self.os_id = Some(os_id_for_name("shmem_", name));
+        }
+    }
+
+    //Detects two names hashing to the same os_id
+    fn check_name(&self, header: &Header) -> Result<(), ShmemError> {
+        match self.name.as_ref() {
+            Some(name) if !header.has_name(name) => Err(ShmemError::NameMismatch),
+            _ => Ok(()),
+        }
+    }
+}
+
+//Whether `size` usable bytes fit after the header in a mapping or object of `map_size` bytes
+fn fits_after_header(size: usize, map_size: usize) -> bool {
+    matches!(HEADER_SIZE.checked_add(size), Some(needed) if needed <= map_size)
 }
 
 /// Structure used to extract information from an existing shared memory mapping
 pub struct Shmem {
     config: ShmemConf,
     mapping: os_impl::MapData,
+    /// Generation of the header when the mapping was last (re)mapped
+    generation: u32,
+    /// Whether `create()` picked up the data of an existing backing file
+    restored: bool,
+    /// What the memory options achieved on the current mapping
+    memory_outcome: MemoryOutcome,
 }
 #[allow(clippy::len_without_is_empty)]
 impl Shmem {
@@ -207,25 +416,117 @@ impl Shmem {
         #[cfg(any(target_os = "freebsd", target_os = "linux", target_os = "macos"))]
         self.mapping.set_owner(is_owner);
 
+        if is_owner && !self.is_read_only() {
+            self.header().set_owner_pid();
+        }
+
         let prev_val = self.config.owner;
         self.config.owner = is_owner;
         prev_val
     }
+    /// Returns the process id of the owner of the mapping, as recorded when it created the
+    /// mapping or called `set_owner(true)`
+    pub fn owner_pid(&self) -> u32 {
+        self.header().owner_pid()
+    }
+    /// Controls whether processes spawned from now on inherit the file descriptor of the mapping,
+    /// so they can open it with `ShmemConf::fd()`
+    #[cfg(unix)]
+    pub fn set_inheritable(&self, inheritable: bool) -> Result<(), ShmemError> {
+        self.mapping.set_inheritable(inheritable)
+    }
+    /// Returns whether the mapping was opened with `ShmemConf::read_only()`
+    pub fn is_read_only(&self) -> bool {
+        !self.mapping.is_writable()
+    }
+    /// Returns what the system allowed of `ShmemConf::memory_options()` for the current mapping
+    pub fn memory_outcome(&self) -> MemoryOutcome {
+        self.memory_outcome
+    }
+    /// Applies new memory options to the mapping. Pages locked by previous options stay locked
+    pub fn set_memory_options(&mut self, options: MemoryOptions) -> MemoryOutcome {
+        self.config.memory_options = options;
+        self.memory_outcome = os_impl::apply_memory_options(&self.mapping, options);
+        self.memory_outcome
+    }
+    /// Returns whether the mapping was created from the data left in its backing file by a previous owner
+    pub fn is_restored(&self) -> bool {
+        self.restored
+    }
+    /// Writes the modified data back to the backing file and waits for it to reach the disk
+    pub fn flush(&self) -> Result<(), ShmemError> {
+        self.mapping.flush(true)
+    }
+    /// Schedules writing the modified data back to the backing file without waiting for it
+    pub fn flush_async(&self) -> Result<(), ShmemError> {
+        self.mapping.flush(false)
+    }
     /// Returns the OS unique identifier for the mapping
     pub fn get_os_id(&self) -> &str {
         self.mapping.unique_id.as_str()
     }
+    /// Returns the logical name the mapping was created with, truncated to at most
+    /// [`NAME_CAPACITY`] bytes
+    pub fn get_name(&self) -> Option<&str> {
+        self.header().name()
+    }
+    /// Returns the tag the mapping was created with, 0 if none
+    pub fn tag(&self) -> u64 {
+        self.header().tag()
+    }
     /// Returns the flink path if present
     pub fn get_flink_path(&self) -> Option<&PathBuf> {
         self.config.flink_path.as_ref()
     }
-    /// Returns the total size of the mapping
+    /// Returns the usable size of the mapping
     pub fn len(&self) -> usize {
-        self.mapping.map_size
+        self.config.size
     }
-    /// Returns a raw pointer to the mapping
+    /// Returns a raw pointer to the usable part of the mapping
     pub fn as_ptr(&self) -> *mut u8 {
-        self.mapping.map_ptr
+        unsafe { self.mapping.map_ptr.add(HEADER_SIZE) }
+    }
+    fn header(&self) -> &Header {
+        unsafe { &*(self.mapping.map_ptr as *const Header) }
+    }
+    /// Grows or shrinks the mapping to `new_size` usable bytes. Only the owner can resize a mapping,
+    /// other processes pick up the new size through `remap_if_resized()`.
+    ///
+    /// The mapping can move, so pointers previously returned by `as_ptr()` must not be used anymore.
+    /// Shrinking is only safe once no other process accesses the bytes being removed.
+    pub fn resize(&mut self, new_size: usize) -> Result<(), ShmemError> {
+        if !self.is_owner() || self.is_read_only() {
+            return Err(ShmemError::ResizeNotOwner);
+        }
+        if new_size == 0 {
+            return Err(ShmemError::MapSizeZero);
+        }
+        os_impl::resize_mapping(&mut self.mapping, HEADER_SIZE + new_size)?;
+        self.config.size = new_size;
+        self.generation = self.header().set_size(new_size);
+        self.memory_outcome =
+            os_impl::apply_memory_options(&self.mapping, self.config.memory_options);
+        Ok(())
+    }
+    /// Maps the mapping again if its owner resized it since it was opened or last remapped.
+    /// Returns whether the mapping changed, in which case pointers previously returned by
+    /// `as_ptr()` must not be used anymore.
+    pub fn remap_if_resized(&mut self) -> Result<bool, ShmemError> {
+        let generation = self.header().generation();
+        if generation == self.generation {
+            return Ok(false);
+        }
+        let new_size = self.header().size();
+        //A corrupt header must not make the mapping reach past the end of the object
+        if !fits_after_header(new_size, os_impl::object_size(&self.mapping)?) {
+            return Err(ShmemError::InvalidHeader);
+        }
+        os_impl::remap(&mut self.mapping, HEADER_SIZE + new_size)?;
+        self.config.size = new_size;
+        self.generation = generation;
+        self.memory_outcome =
+            os_impl::apply_memory_options(&self.mapping, self.config.memory_options);
+        Ok(true)
     }
     /// Returns mapping as a byte slice
     /// # Safety
@@ -240,3 +541,10 @@ impl Shmem {
         std::slice::from_raw_parts_mut(self.as_ptr(), self.len())
     }
 }
+
+#[cfg(unix)]
+impl AsRawFd for Shmem {
+    fn as_raw_fd(&self) -> RawFd {
+        self.mapping.as_raw_fd()
+    }
+}
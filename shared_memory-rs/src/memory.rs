@@ -0,0 +1,78 @@
+//! Hints about how the pages of a mapping should be backed and faulted in
+
+/// Options applied to the pages of a mapping once it is in our address space
+///
+/// They are best effort : a mapping is still created/opened when the system can't honor
+/// them, see `MemoryOutcome` for what was actually achieved.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryOptions {
+    huge_pages: bool,
+    populate: bool,
+    lock: bool,
+}
+impl MemoryOptions {
+    /// Back the mapping with huge pages to reduce TLB misses on large mappings
+    ///
+    /// Memfd mappings are created on hugetlbfs when huge pages are reserved, other
+    /// shared memory is advised to use transparent huge pages (`madvise(MADV_HUGEPAGE)`).
+    pub fn huge_pages(mut self) -> Self {
+        self.huge_pages = true;
+        self
+    }
+
+    /// Fault every page in up front, like `MAP_POPULATE`, so the first access to a page doesn't stall
+    pub fn populate(mut self) -> Self {
+        self.populate = true;
+        self
+    }
+
+    /// Lock the pages in RAM (`mlock()`/`VirtualLock()`) so they are never paged out
+    pub fn lock(mut self) -> Self {
+        self.lock = true;
+        self
+    }
+
+    pub fn wants_huge_pages(&self) -> bool {
+        self.huge_pages
+    }
+
+    pub fn wants_populate(&self) -> bool {
+        self.populate
+    }
+
+    pub fn wants_lock(&self) -> bool {
+        self.lock
+    }
+}
+
+/// Which kind of pages back a mapping
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum HugePages {
+    /// Huge pages were not requested
+    #[default]
+    NotRequested,
+    /// The mapping lives on hugetlbfs, every page is a huge page
+    Explicit,
+    /// The kernel was advised to use transparent huge pages and has them enabled for this mapping
+    Transparent,
+    /// Huge pages were requested but the system can't provide them, regular pages are used
+    Unavailable,
+}
+
+/// What was achieved when applying `MemoryOptions` to a mapping
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryOutcome {
+    pub huge_pages: HugePages,
+    /// Every page was faulted in
+    pub populated: bool,
+    /// The pages are locked in RAM. `mlock()` usually fails for unprivileged processes
+    /// once the mapping exceeds `RLIMIT_MEMLOCK`
+    pub locked: bool,
+}
+
+/// Reads one byte of every page so they are all faulted in, works for read only mappings too
+pub(crate) fn touch_pages(map_ptr: *const u8, map_size: usize, page_size: usize) {
+    for offset in (0..map_size).step_by(page_size) {
+        unsafe { std::ptr::read_volatile(map_ptr.add(offset)) };
+    }
+}
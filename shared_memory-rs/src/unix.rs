@@ -1,17 +1,29 @@
 use ::nix::errno::Errno;
-use ::nix::fcntl::OFlag;
-use ::nix::sys::mman::{mmap, munmap, shm_open, shm_unlink, MapFlags, ProtFlags};
+use ::nix::fcntl::{fcntl, FcntlArg, FdFlag, OFlag};
+use ::nix::sys::mman::{
+    mlock, mmap, msync, munmap, shm_open, shm_unlink, MapFlags, MsFlags, ProtFlags,
+};
+use ::nix::sys::socket::{recvmsg, sendmsg, ControlMessage, ControlMessageOwned, MsgFlags};
 use ::nix::sys::stat::{fstat, Mode};
-use ::nix::unistd::{close, ftruncate};
+use ::nix::sys::uio::IoVec;
+use ::nix::unistd::{close, ftruncate, sysconf, SysconfVar};
 
-use crate::ShmemError;
+use crate::memory::touch_pages;
+use crate::{HugePages, MemoryOptions, MemoryOutcome, OsError, ShmemError};
 
-use std::os::unix::io::RawFd;
+use std::fs::OpenOptions;
+use std::os::unix::io::{AsRawFd, IntoRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
 use std::ptr::null_mut;
 
 pub struct MapData {
     //On linux, you must shm_unlink() the object created for the mapping. It wont disappear automatically.
     owner: bool,
+    //Whether map_fd is a named shm_open() object (as opposed to a memfd or a received fd)
+    named: bool,
+    //Whether the pages are mapped with PROT_WRITE
+    writable: bool,
 
     //File descriptor to our open mapping
     map_fd: RawFd,
@@ -42,7 +54,7 @@ impl Drop for MapData {
         //Unlink shmem
         if self.map_fd != 0 {
             //unlink shmem if we created it
-            if self.owner {
+            if self.owner && self.named {
                 match shm_unlink(self.unique_id.as_str()) {
                     Ok(_) => {
                         //debug!("shm_unlink()");
@@ -68,11 +80,92 @@ impl Drop for MapData {
 }
 
 impl MapData {
+    pub fn is_writable(&self) -> bool {
+        self.writable
+    }
+
     pub fn set_owner(&mut self, is_owner: bool) -> bool {
         let prev_val = self.owner;
         self.owner = is_owner;
         prev_val
     }
+
+    /// Controls whether child processes inherit the file descriptor of the mapping
+    pub fn set_inheritable(&self, inheritable: bool) -> Result<(), ShmemError> {
+        let flags = match fcntl(self.map_fd, FcntlArg::F_GETFD) {
+            Ok(v) => FdFlag::from_bits_truncate(v),
+            Err(e) => return Err(ShmemError::UnknownOsError(self.os_error("fcntl", e))),
+        };
+        let flags = if inheritable {
+            flags - FdFlag::FD_CLOEXEC
+        } else {
+            flags | FdFlag::FD_CLOEXEC
+        };
+        match fcntl(self.map_fd, FcntlArg::F_SETFD(flags)) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(ShmemError::UnknownOsError(self.os_error("fcntl", e))),
+        }
+    }
+
+    fn os_error(&self, syscall: &'static str, err: nix::Error) -> OsError {
+        os_error(syscall, &self.unique_id, self.map_size, err)
+    }
+}
+
+impl MapData {
+    /// Writes the modified pages of the mapping back to the object backing it
+    pub fn flush(&self, wait: bool) -> Result<(), ShmemError> {
+        let flags = if wait {
+            MsFlags::MS_SYNC
+        } else {
+            MsFlags::MS_ASYNC
+        };
+        match unsafe { msync(self.map_ptr as *mut _, self.map_size, flags) } {
+            Ok(_) => Ok(()),
+            Err(e) => Err(ShmemError::FlushFailed(self.os_error("msync", e))),
+        }
+    }
+}
+
+impl AsRawFd for MapData {
+    fn as_raw_fd(&self) -> RawFd {
+        self.map_fd
+    }
+}
+
+/// Describes a failed call made for the mapping `os_id` of `size` bytes
+fn os_error(syscall: &'static str, os_id: &str, size: usize, err: nix::Error) -> OsError {
+    let errno = match err {
+        nix::Error::Sys(e) => e,
+        nix::Error::UnsupportedOperation => Errno::EOPNOTSUPP,
+        //nix rejected the arguments before making the call
+        _ => Errno::EINVAL,
+    };
+    OsError::new(syscall, os_id, size, errno as i32)
+}
+
+/// Permissions on the pages of a mapping
+fn prot_flags(writable: bool) -> ProtFlags {
+    if writable {
+        ProtFlags::PROT_READ | ProtFlags::PROT_WRITE
+    } else {
+        ProtFlags::PROT_READ
+    }
+}
+
+/// Puts the whole object behind `map_fd` in our address space
+fn map_whole_fd(map_fd: RawFd, map_size: usize, writable: bool) -> nix::Result<*mut u8> {
+    let ptr = unsafe {
+        mmap(
+            null_mut(),           //Desired addr
+            map_size,             //size of mapping
+            prot_flags(writable), //Permissions on pages
+            MapFlags::MAP_SHARED, //What kind of mapping
+            map_fd,               //fd
+            0,                    //Offset into fd
+        )
+    }?;
+    Ok(ptr as *mut _)
 }
 
 /// Creates a mapping specified by the uid and size
@@ -85,12 +178,17 @@ pub fn create_mapping(unique_id: &str, map_size: usize) -> Result<MapData, Shmem
     ) {
         Ok(v) => v,
         Err(nix::Error::Sys(Errno::EEXIST)) => return Err(ShmemError::MappingIdExists),
-        Err(nix::Error::Sys(e)) => return Err(ShmemError::MapCreateFailed(e as u32)),
-        _ => return Err(ShmemError::UnknownOsError(0xffff_ffff)),
+        Err(e) => {
+            return Err(ShmemError::MapCreateFailed(os_error(
+                "shm_open", unique_id, map_size, e,
+            )))
+        }
     };
 
     let mut new_map: MapData = MapData {
         owner: true,
+        named: true,
+        writable: true,
         unique_id: String::from(unique_id),
         map_fd: shmem_fd,
         map_size,
@@ -100,8 +198,11 @@ pub fn create_mapping(unique_id: &str, map_size: usize) -> Result<MapData, Shmem
     //Enlarge the memory descriptor file size to the requested map size
     match ftruncate(new_map.map_fd, new_map.map_size as _) {
         Ok(_) => {}
-        Err(nix::Error::Sys(e)) => return Err(ShmemError::UnknownOsError(e as u32)),
-        _ => return Err(ShmemError::UnknownOsError(0xffff_ffff)),
+        Err(e) => {
+            return Err(ShmemError::MapCreateFailed(
+                new_map.os_error("ftruncate", e),
+            ))
+        }
     };
 
     //Put the mapping in our address space
@@ -116,28 +217,36 @@ pub fn create_mapping(unique_id: &str, map_size: usize) -> Result<MapData, Shmem
         )
     } {
         Ok(v) => v as *mut _,
-        Err(nix::Error::Sys(e)) => return Err(ShmemError::MapCreateFailed(e as u32)),
-        _ => return Err(ShmemError::UnknownOsError(0xffff_ffff)),
+        Err(e) => return Err(ShmemError::MapCreateFailed(new_map.os_error("mmap", e))),
     };
 
     Ok(new_map)
 }
 
 /// Opens an existing mapping specified by its uid
-pub fn open_mapping(unique_id: &str) -> Result<MapData, ShmemError> {
+pub fn open_mapping(unique_id: &str, writable: bool) -> Result<MapData, ShmemError> {
     //Open shared memory
     let shmem_fd = match shm_open(
         unique_id,
-        OFlag::O_RDWR, //Open read write
+        if writable {
+            OFlag::O_RDWR //Open read write
+        } else {
+            OFlag::O_RDONLY
+        },
         Mode::S_IRUSR,
     ) {
         Ok(v) => v,
-        Err(nix::Error::Sys(e)) => return Err(ShmemError::MapOpenFailed(e as u32)),
-        _ => return Err(ShmemError::UnknownOsError(0xffff_ffff)),
+        Err(e) => {
+            return Err(ShmemError::MapOpenFailed(os_error(
+                "shm_open", unique_id, 0, e,
+            )))
+        }
     };
 
     let mut new_map: MapData = MapData {
         owner: false,
+        named: true,
+        writable,
         unique_id: String::from(unique_id),
         map_fd: shmem_fd,
         map_size: 0,
@@ -147,25 +256,444 @@ pub fn open_mapping(unique_id: &str) -> Result<MapData, ShmemError> {
     //Get mmap size
     new_map.map_size = match fstat(new_map.map_fd) {
         Ok(v) => v.st_size as usize,
-        Err(nix::Error::Sys(e)) => return Err(ShmemError::MapOpenFailed(e as u32)),
-        _ => return Err(ShmemError::UnknownOsError(0xffff_ffff)),
+        Err(e) => return Err(ShmemError::MapOpenFailed(new_map.os_error("fstat", e))),
     };
 
     //Map memory into our address space
     new_map.map_ptr = match unsafe {
         mmap(
-            null_mut(),                                   //Desired addr
-            new_map.map_size,                             //size of mapping
-            ProtFlags::PROT_READ | ProtFlags::PROT_WRITE, //Permissions on pages
-            MapFlags::MAP_SHARED,                         //What kind of mapping
-            new_map.map_fd,                               //fd
-            0,                                            //Offset into fd
+            null_mut(),           //Desired addr
+            new_map.map_size,     //size of mapping
+            prot_flags(writable), //Permissions on pages
+            MapFlags::MAP_SHARED, //What kind of mapping
+            new_map.map_fd,       //fd
+            0,                    //Offset into fd
         )
     } {
         Ok(v) => v as *mut _,
-        Err(nix::Error::Sys(e)) => return Err(ShmemError::MapOpenFailed(e as u32)),
-        _ => return Err(ShmemError::UnknownOsError(0xffff_ffff)),
+        Err(e) => return Err(ShmemError::MapOpenFailed(new_map.os_error("mmap", e))),
     };
 
     Ok(new_map)
 }
+
+/// Changes the size of the object backing the mapping then maps it again with that size
+pub fn resize_mapping(map: &mut MapData, map_size: usize) -> Result<(), ShmemError> {
+    //hugetlbfs objects can only hold whole huge pages
+    #[cfg(target_os = "linux")]
+    let map_size = match huge_page_size(map.map_fd) {
+        Some(page_size) => round_up(map_size, page_size),
+        None => map_size,
+    };
+    match ftruncate(map.map_fd, map_size as _) {
+        Ok(_) => {}
+        Err(e) => {
+            return Err(ShmemError::ResizeFailed(os_error(
+                "ftruncate",
+                &map.unique_id,
+                map_size,
+                e,
+            )))
+        }
+    };
+    remap(map, map_size)
+}
+
+/// Maps the (already resized) object backing the mapping again with a new size
+#[cfg(target_os = "linux")]
+pub fn remap(map: &mut MapData, map_size: usize) -> Result<(), ShmemError> {
+    //Older kernels can't mremap() hugetlb mappings, map them again instead
+    if let Some(page_size) = huge_page_size(map.map_fd) {
+        return map_again(map, round_up(map_size, page_size));
+    }
+    let new_ptr = unsafe {
+        libc::mremap(
+            map.map_ptr as *mut _,
+            map.map_size,
+            map_size,
+            libc::MREMAP_MAYMOVE,
+        )
+    };
+    if new_ptr == libc::MAP_FAILED {
+        return Err(ShmemError::ResizeFailed(OsError::new(
+            "mremap",
+            &map.unique_id,
+            map_size,
+            Errno::last() as i32,
+        )));
+    }
+    map.map_ptr = new_ptr as *mut _;
+    map.map_size = map_size;
+    Ok(())
+}
+
+/// Maps the (already resized) object backing the mapping again with a new size
+#[cfg(not(target_os = "linux"))]
+pub fn remap(map: &mut MapData, map_size: usize) -> Result<(), ShmemError> {
+    map_again(map, map_size)
+}
+
+/// Returns the current size of the object backing the mapping, which its owner may have resized
+pub fn object_size(map: &MapData) -> Result<usize, ShmemError> {
+    match fstat(map.map_fd) {
+        Ok(v) => Ok(v.st_size as usize),
+        Err(e) => Err(ShmemError::ResizeFailed(map.os_error("fstat", e))),
+    }
+}
+
+/// Replaces the mapping by a new one of the whole object
+fn map_again(map: &mut MapData, map_size: usize) -> Result<(), ShmemError> {
+    //Map the new size first so a failure leaves the current mapping intact
+    let new_ptr = match unsafe {
+        mmap(
+            null_mut(),               //Desired addr
+            map_size,                 //size of mapping
+            prot_flags(map.writable), //Permissions on pages
+            MapFlags::MAP_SHARED,     //What kind of mapping
+            map.map_fd,               //fd
+            0,                        //Offset into fd
+        )
+    } {
+        Ok(v) => v as *mut u8,
+        Err(e) => {
+            return Err(ShmemError::ResizeFailed(os_error(
+                "mmap",
+                &map.unique_id,
+                map_size,
+                e,
+            )))
+        }
+    };
+    let _ = unsafe { munmap(map.map_ptr as *mut _, map.map_size) };
+    map.map_ptr = new_ptr;
+    map.map_size = map_size;
+    Ok(())
+}
+
+/// Creates an anonymous mapping backed by a memfd, which disappears with the last fd/mapping
+/// referencing it instead of lingering in /dev/shm
+///
+/// With `huge_pages`, the memfd is created on hugetlbfs when enough huge pages are reserved
+/// and falls back to regular pages otherwise.
+#[cfg(target_os = "linux")]
+pub fn create_memfd_mapping(
+    name: &str,
+    map_size: usize,
+    huge_pages: bool,
+) -> Result<MapData, ShmemError> {
+    use ::nix::fcntl::SealFlag;
+    use ::nix::sys::memfd::{memfd_create, MemFdCreateFlag};
+
+    //The name only shows up in /proc/<pid>/fd, it doesn't have to be unique
+    let c_name = match std::ffi::CString::new(name) {
+        Ok(v) => v,
+        Err(_) => {
+            return Err(ShmemError::MapCreateFailed(OsError::new(
+                "memfd_create",
+                name,
+                map_size,
+                Errno::EINVAL as i32,
+            )))
+        }
+    };
+    if huge_pages {
+        if let Some(new_map) = create_hugetlb_memfd_mapping(&c_name, name, map_size) {
+            return Ok(new_map);
+        }
+    }
+    let memfd = match memfd_create(
+        &c_name,
+        MemFdCreateFlag::MFD_CLOEXEC | MemFdCreateFlag::MFD_ALLOW_SEALING,
+    ) {
+        Ok(v) => v,
+        Err(e) => {
+            return Err(ShmemError::MapCreateFailed(os_error(
+                "memfd_create",
+                name,
+                map_size,
+                e,
+            )))
+        }
+    };
+
+    let mut new_map: MapData = MapData {
+        owner: true,
+        named: false,
+        writable: true,
+        unique_id: format!("memfd:{}", name),
+        map_fd: memfd,
+        map_size,
+        map_ptr: null_mut(),
+    };
+
+    //Enlarge the memory descriptor file size to the requested map size
+    match ftruncate(new_map.map_fd, new_map.map_size as _) {
+        Ok(_) => {}
+        Err(e) => {
+            return Err(ShmemError::MapCreateFailed(
+                new_map.os_error("ftruncate", e),
+            ))
+        }
+    };
+
+    //Whoever we hand the fd to can grow the mapping but never shrink it under our feet
+    match fcntl(
+        new_map.map_fd,
+        FcntlArg::F_ADD_SEALS(SealFlag::F_SEAL_SHRINK | SealFlag::F_SEAL_SEAL),
+    ) {
+        Ok(_) => {}
+        Err(e) => return Err(ShmemError::MapCreateFailed(new_map.os_error("fcntl", e))),
+    };
+
+    new_map.map_ptr = match map_whole_fd(new_map.map_fd, new_map.map_size, true) {
+        Ok(v) => v,
+        Err(e) => return Err(ShmemError::MapCreateFailed(new_map.os_error("mmap", e))),
+    };
+
+    Ok(new_map)
+}
+
+/// Same as `create_memfd_mapping()` on hugetlbfs. Returns None when the kernel has no huge
+/// pages to give us
+#[cfg(target_os = "linux")]
+fn create_hugetlb_memfd_mapping(
+    c_name: &std::ffi::CStr,
+    name: &str,
+    map_size: usize,
+) -> Option<MapData> {
+    //nix doesn't know about MFD_HUGETLB
+    let flags = libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING | libc::MFD_HUGETLB;
+    let memfd = unsafe { libc::syscall(libc::SYS_memfd_create, c_name.as_ptr(), flags) };
+    if memfd < 0 {
+        return None;
+    }
+
+    let mut new_map: MapData = MapData {
+        owner: true,
+        named: false,
+        writable: true,
+        unique_id: format!("memfd:{}", name),
+        map_fd: memfd as RawFd,
+        map_size,
+        map_ptr: null_mut(),
+    };
+
+    //The size must be a multiple of the huge page size
+    new_map.map_size = round_up(map_size, huge_page_size(new_map.map_fd)?);
+    ftruncate(new_map.map_fd, new_map.map_size as _).ok()?;
+    fcntl(
+        new_map.map_fd,
+        FcntlArg::F_ADD_SEALS(
+            ::nix::fcntl::SealFlag::F_SEAL_SHRINK | ::nix::fcntl::SealFlag::F_SEAL_SEAL,
+        ),
+    )
+    .ok()?;
+    //Fails with ENOMEM when not enough huge pages are reserved
+    new_map.map_ptr = map_whole_fd(new_map.map_fd, new_map.map_size, true).ok()?;
+
+    Some(new_map)
+}
+
+/// Returns the size of the pages of the object behind `fd` if it lives on hugetlbfs
+#[cfg(target_os = "linux")]
+fn huge_page_size(fd: RawFd) -> Option<usize> {
+    use ::nix::sys::statfs::{fstatfs, HUGETLBFS_MAGIC};
+
+    match fstatfs(&fd) {
+        Ok(v) if v.filesystem_type() == HUGETLBFS_MAGIC => Some(v.block_size() as usize),
+        _ => None,
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn round_up(size: usize, page_size: usize) -> usize {
+    size.div_ceil(page_size) * page_size
+}
+
+/// Applies the options to the pages of the mapping, as far as the system allows
+pub fn apply_memory_options(map: &MapData, options: MemoryOptions) -> MemoryOutcome {
+    let mut outcome = MemoryOutcome::default();
+    //Advise before faulting the pages in, the kind of page is picked on the first fault
+    if options.wants_huge_pages() {
+        outcome.huge_pages = advise_huge_pages(map);
+    }
+    //Faulting the pages in ourselves instead of remapping with MAP_POPULATE keeps the advice
+    if options.wants_populate() {
+        let page_size = match sysconf(SysconfVar::PAGE_SIZE) {
+            Ok(Some(v)) => v as usize,
+            _ => 4096,
+        };
+        touch_pages(map.map_ptr, map.map_size, page_size);
+        outcome.populated = true;
+    }
+    if options.wants_lock() {
+        outcome.locked = unsafe { mlock(map.map_ptr as *const _, map.map_size) }.is_ok();
+    }
+    outcome
+}
+
+#[cfg(target_os = "linux")]
+fn advise_huge_pages(map: &MapData) -> HugePages {
+    use ::nix::sys::mman::{madvise, MmapAdvise};
+    use ::nix::sys::statfs::{fstatfs, TMPFS_MAGIC};
+
+    if huge_page_size(map.map_fd).is_some() {
+        return HugePages::Explicit;
+    }
+    //Shared mappings of regular files don't get transparent huge pages
+    match fstatfs(&map.map_fd) {
+        Ok(v) if v.filesystem_type() == TMPFS_MAGIC => {}
+        _ => return HugePages::Unavailable,
+    };
+    if unsafe {
+        madvise(
+            map.map_ptr as *mut _,
+            map.map_size,
+            MmapAdvise::MADV_HUGEPAGE,
+        )
+    }
+    .is_err()
+    {
+        return HugePages::Unavailable;
+    }
+    //The advice is silently ignored unless huge pages are enabled for shared memory
+    let enabled = std::fs::read_to_string("/sys/kernel/mm/transparent_hugepage/shmem_enabled")
+        .unwrap_or_default();
+    let selected = enabled
+        .split_whitespace()
+        .find(|v| v.starts_with('['))
+        .unwrap_or("[never]");
+    match selected {
+        "[always]" | "[within_size]" | "[advise]" | "[force]" => HugePages::Transparent,
+        _ => HugePages::Unavailable,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn advise_huge_pages(_map: &MapData) -> HugePages {
+    HugePages::Unavailable
+}
+
+/// Maps the shared memory object behind a file descriptor we inherited or received.
+/// The mapping takes ownership of `fd`
+pub fn open_fd_mapping(fd: RawFd, writable: bool) -> Result<MapData, ShmemError> {
+    let mut new_map: MapData = MapData {
+        owner: false,
+        named: false,
+        writable,
+        unique_id: format!("fd:{}", fd),
+        map_fd: fd,
+        map_size: 0,
+        map_ptr: null_mut(),
+    };
+
+    //Get mmap size
+    new_map.map_size = match fstat(new_map.map_fd) {
+        Ok(v) => v.st_size as usize,
+        Err(e) => return Err(ShmemError::MapOpenFailed(new_map.os_error("fstat", e))),
+    };
+
+    new_map.map_ptr = match map_whole_fd(new_map.map_fd, new_map.map_size, writable) {
+        Ok(v) => v,
+        Err(e) => return Err(ShmemError::MapOpenFailed(new_map.os_error("mmap", e))),
+    };
+
+    Ok(new_map)
+}
+
+/// Maps a regular file, creating it if needed, and sets its size to `map_size`. The file is
+/// left in place when the mapping is dropped
+pub fn create_file_mapping(path: &Path, map_size: usize) -> Result<MapData, ShmemError> {
+    let file = match OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false) //Keep the data of a previous owner
+        .open(path)
+    {
+        Ok(f) => f,
+        Err(e) => return Err(ShmemError::FileOpenFailed(e)),
+    };
+    if let Err(e) = file.set_len(map_size as u64) {
+        return Err(ShmemError::FileOpenFailed(e));
+    }
+
+    let mut new_map = open_fd_mapping(file.into_raw_fd(), true)?;
+    new_map.owner = true;
+    new_map.unique_id = path.to_string_lossy().into_owned();
+    Ok(new_map)
+}
+
+/// Maps an existing regular file
+pub fn open_file_mapping(path: &Path, writable: bool) -> Result<MapData, ShmemError> {
+    let file = match OpenOptions::new().read(true).write(writable).open(path) {
+        Ok(f) => f,
+        Err(e) => return Err(ShmemError::FileOpenFailed(e)),
+    };
+
+    let mut new_map = open_fd_mapping(file.into_raw_fd(), writable)?;
+    new_map.unique_id = path.to_string_lossy().into_owned();
+    Ok(new_map)
+}
+
+/// Sends a file descriptor (e.g. the one of a memfd mapping) to the process at the other end of `socket`
+pub fn send_fd(socket: &UnixStream, fd: RawFd) -> Result<(), ShmemError> {
+    //Stream sockets can't carry ancillary data without at least one byte of payload
+    let payload = [0u8];
+    let fds = [fd];
+    match sendmsg(
+        socket.as_raw_fd(),
+        &[IoVec::from_slice(&payload)],
+        &[ControlMessage::ScmRights(&fds)],
+        MsgFlags::empty(),
+        None,
+    ) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ShmemError::FdPassingFailed(os_error(
+            "sendmsg",
+            &format!("fd:{}", fd),
+            0,
+            e,
+        ))),
+    }
+}
+
+/// Receives a file descriptor sent with `send_fd()`
+pub fn recv_fd(socket: &UnixStream) -> Result<RawFd, ShmemError> {
+    let mut payload = [0u8];
+    let mut cmsg_buffer = nix::cmsg_space!([RawFd; 1]);
+    //Don't leak the received fd into children unless asked to (see `set_inheritable()`)
+    #[cfg(not(target_os = "macos"))]
+    let flags = MsgFlags::MSG_CMSG_CLOEXEC;
+    #[cfg(target_os = "macos")]
+    let flags = MsgFlags::empty();
+    let msg = match recvmsg(
+        socket.as_raw_fd(),
+        &[IoVec::from_mut_slice(&mut payload)],
+        Some(&mut cmsg_buffer),
+        flags,
+    ) {
+        Ok(v) => v,
+        Err(e) => {
+            return Err(ShmemError::FdPassingFailed(os_error(
+                "recvmsg",
+                &format!("socket:{}", socket.as_raw_fd()),
+                0,
+                e,
+            )))
+        }
+    };
+    for cmsg in msg.cmsgs() {
+        if let ControlMessageOwned::ScmRights(fds) = cmsg {
+            if let Some(fd) = fds.first() {
+                return Ok(*fd);
+            }
+        }
+    }
+    Err(ShmemError::FdPassingFailed(OsError::new(
+        "recvmsg",
+        &format!("socket:{}", socket.as_raw_fd()),
+        0,
+        Errno::EBADMSG as i32,
+    )))
+}
@@ -7,24 +7,32 @@ use ::winapi::{
         errhandlingapi::GetLastError,
         handleapi::{CloseHandle, INVALID_HANDLE_VALUE},
         memoryapi::{
-            CreateFileMappingW, MapViewOfFile, OpenFileMappingW, UnmapViewOfFile, VirtualQuery,
-            FILE_MAP_READ, FILE_MAP_WRITE,
+            CreateFileMappingW, FlushViewOfFile, MapViewOfFile, OpenFileMappingW, UnmapViewOfFile,
+            VirtualLock, VirtualQuery, FILE_MAP_READ, FILE_MAP_WRITE,
         },
-        winnt::{HANDLE, MEMORY_BASIC_INFORMATION, PAGE_READWRITE},
+        winnt::{HANDLE, MEMORY_BASIC_INFORMATION, PAGE_READONLY, PAGE_READWRITE},
     },
 };
 
-use crate::ShmemError;
+use crate::memory::touch_pages;
+use crate::{HugePages, MemoryOptions, MemoryOutcome, OsError, ShmemError};
 
 use std::ffi::OsStr;
+use std::fs::{File, OpenOptions};
 use std::iter::once;
 use std::mem::size_of;
 use std::os::windows::ffi::OsStrExt;
+use std::os::windows::io::AsRawHandle;
+use std::path::Path;
 use std::ptr::null_mut;
 
 pub struct MapData {
     ///The handle to our open mapping
     map_handle: HANDLE,
+    ///The file backing the mapping, if any. Must outlive the mapping handle
+    file: Option<File>,
+    ///Whether the view is mapped with FILE_MAP_WRITE
+    writable: bool,
 
     //Shared mapping uid
     pub unique_id: String,
@@ -53,12 +61,44 @@ impl Drop for MapData {
     }
 }
 
+impl MapData {
+    pub fn is_writable(&self) -> bool {
+        self.writable
+    }
+
+    //Writes the modified pages of the mapping back to the file backing it
+    pub fn flush(&self, wait: bool) -> Result<(), ShmemError> {
+        if unsafe { FlushViewOfFile(self.map_ptr as *const _, 0) } == 0 {
+            return Err(ShmemError::FlushFailed(self.last_error("FlushViewOfFile")));
+        }
+        if let (true, Some(file)) = (wait, self.file.as_ref()) {
+            if let Err(e) = file.sync_data() {
+                return Err(ShmemError::FlushFailed(OsError::new(
+                    "FlushFileBuffers",
+                    &self.unique_id,
+                    self.map_size,
+                    e.raw_os_error().unwrap_or(0),
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    //Describes the call that just failed on this mapping
+    fn last_error(&self, syscall: &'static str) -> OsError {
+        let last_error = unsafe { GetLastError() };
+        OsError::new(syscall, &self.unique_id, self.map_size, last_error as i32)
+    }
+}
+
 //Creates a mapping specified by the uid and size
 pub fn create_mapping(unique_id: &str, map_size: usize) -> Result<MapData, ShmemError> {
     //In addition to being the return value, the Drop impl of this helps clean up on failure
     let mut new_map: MapData = MapData {
         unique_id: String::from(unique_id),
         map_handle: NULL,
+        file: None,
+        writable: true,
         map_size,
         map_ptr: null_mut(),
     };
@@ -80,7 +120,12 @@ pub fn create_mapping(unique_id: &str, map_size: usize) -> Result<MapData, Shmem
     let last_error = unsafe { GetLastError() };
 
     if new_map.map_handle == NULL {
-        return Err(ShmemError::MapCreateFailed(last_error));
+        return Err(ShmemError::MapCreateFailed(OsError::new(
+            "CreateFileMappingW",
+            unique_id,
+            map_size,
+            last_error as i32,
+        )));
     } else if last_error == ERROR_ALREADY_EXISTS {
         return Err(ShmemError::MappingIdExists);
     }
@@ -89,19 +134,31 @@ pub fn create_mapping(unique_id: &str, map_size: usize) -> Result<MapData, Shmem
     new_map.map_ptr =
         unsafe { MapViewOfFile(new_map.map_handle, FILE_MAP_READ | FILE_MAP_WRITE, 0, 0, 0) } as _;
     if new_map.map_ptr.is_null() {
-        let last_error = unsafe { GetLastError() };
-        return Err(ShmemError::MapCreateFailed(last_error));
+        return Err(ShmemError::MapCreateFailed(
+            new_map.last_error("MapViewOfFile"),
+        ));
     }
 
     Ok(new_map)
 }
 
+//Access requested when opening/viewing a mapping
+fn map_access(writable: bool) -> u32 {
+    if writable {
+        FILE_MAP_READ | FILE_MAP_WRITE
+    } else {
+        FILE_MAP_READ
+    }
+}
+
 //Opens an existing mapping specified by its uid
-pub fn open_mapping(unique_id: &str) -> Result<MapData, ShmemError> {
+pub fn open_mapping(unique_id: &str, writable: bool) -> Result<MapData, ShmemError> {
     //In addition to being the return value, the Drop impl of this helps clean up on failure
     let mut new_map: MapData = MapData {
         unique_id: String::from(unique_id),
         map_handle: NULL,
+        file: None,
+        writable,
         map_size: 0,
         map_ptr: null_mut(),
     };
@@ -109,23 +166,21 @@ pub fn open_mapping(unique_id: &str) -> Result<MapData, ShmemError> {
     //Open existing mapping
     new_map.map_handle = unsafe {
         let unique_id: Vec<u16> = OsStr::new(unique_id).encode_wide().chain(once(0)).collect();
-        OpenFileMappingW(
-            FILE_MAP_READ | FILE_MAP_WRITE,
-            FALSE as _,
-            unique_id.as_ptr(),
-        )
+        OpenFileMappingW(map_access(writable), FALSE as _, unique_id.as_ptr())
     };
     if new_map.map_handle as *mut _ == NULL {
-        let last_error = unsafe { GetLastError() };
-        return Err(ShmemError::MapOpenFailed(last_error));
+        return Err(ShmemError::MapOpenFailed(
+            new_map.last_error("OpenFileMappingW"),
+        ));
     }
 
     //Map mapping into address space
     new_map.map_ptr =
-        unsafe { MapViewOfFile(new_map.map_handle, FILE_MAP_READ | FILE_MAP_WRITE, 0, 0, 0) } as _;
+        unsafe { MapViewOfFile(new_map.map_handle, map_access(writable), 0, 0, 0) } as _;
     if new_map.map_ptr.is_null() {
-        let last_error = unsafe { GetLastError() };
-        return Err(ShmemError::MapOpenFailed(last_error));
+        return Err(ShmemError::MapOpenFailed(
+            new_map.last_error("MapViewOfFile"),
+        ));
     }
 
     //Get the size of our mapping
@@ -147,8 +202,9 @@ pub fn open_mapping(unique_id: &str) -> Result<MapData, ShmemError> {
 
         //Couldnt get mapping size
         if ret_val == 0 {
-            let last_error = GetLastError();
-            return Err(ShmemError::UnknownOsError(last_error));
+            return Err(ShmemError::UnknownOsError(
+                new_map.last_error("VirtualQuery"),
+            ));
         }
 
         mem_ba.RegionSize
@@ -156,3 +212,120 @@ pub fn open_mapping(unique_id: &str) -> Result<MapData, ShmemError> {
 
     Ok(new_map)
 }
+
+//File mappings backed by the paging file can't change size once created
+pub fn resize_mapping(_map: &mut MapData, _map_size: usize) -> Result<(), ShmemError> {
+    Err(ShmemError::ResizeUnsupported)
+}
+
+pub fn remap(_map: &mut MapData, _map_size: usize) -> Result<(), ShmemError> {
+    Err(ShmemError::ResizeUnsupported)
+}
+
+//The object never changes size, so it is as large as its view
+pub fn object_size(map: &MapData) -> Result<usize, ShmemError> {
+    Ok(map.map_size)
+}
+
+//Maps a regular file, creating it if needed, and sets its size to map_size
+pub fn create_file_mapping(path: &Path, map_size: usize) -> Result<MapData, ShmemError> {
+    let file = match OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false) //Keep the data of a previous owner
+        .open(path)
+    {
+        Ok(f) => f,
+        Err(e) => return Err(ShmemError::FileOpenFailed(e)),
+    };
+    if let Err(e) = file.set_len(map_size as u64) {
+        return Err(ShmemError::FileOpenFailed(e));
+    }
+    map_file(path, file, map_size, true).map_err(|e| match e {
+        ShmemError::MapOpenFailed(err) => ShmemError::MapCreateFailed(err),
+        e => e,
+    })
+}
+
+//Maps an existing regular file
+pub fn open_file_mapping(path: &Path, writable: bool) -> Result<MapData, ShmemError> {
+    let file = match OpenOptions::new().read(true).write(writable).open(path) {
+        Ok(f) => f,
+        Err(e) => return Err(ShmemError::FileOpenFailed(e)),
+    };
+    let map_size = match file.metadata() {
+        Ok(m) => m.len() as usize,
+        Err(e) => return Err(ShmemError::FileOpenFailed(e)),
+    };
+    map_file(path, file, map_size, writable)
+}
+
+fn map_file(
+    path: &Path,
+    file: File,
+    map_size: usize,
+    writable: bool,
+) -> Result<MapData, ShmemError> {
+    //In addition to being the return value, the Drop impl of this helps clean up on failure
+    let mut new_map: MapData = MapData {
+        unique_id: path.to_string_lossy().into_owned(),
+        map_handle: NULL,
+        file: None,
+        writable,
+        map_size,
+        map_ptr: null_mut(),
+    };
+
+    //Create an unnamed mapping of the whole file
+    new_map.map_handle = unsafe {
+        CreateFileMappingW(
+            file.as_raw_handle() as _,
+            null_mut(),
+            if writable {
+                PAGE_READWRITE
+            } else {
+                PAGE_READONLY
+            },
+            0,
+            0,
+            null_mut(),
+        )
+    };
+    new_map.file = Some(file);
+    if new_map.map_handle == NULL {
+        return Err(ShmemError::MapOpenFailed(
+            new_map.last_error("CreateFileMappingW"),
+        ));
+    }
+
+    //Map mapping into address space
+    new_map.map_ptr =
+        unsafe { MapViewOfFile(new_map.map_handle, map_access(writable), 0, 0, 0) } as _;
+    if new_map.map_ptr.is_null() {
+        return Err(ShmemError::MapOpenFailed(
+            new_map.last_error("MapViewOfFile"),
+        ));
+    }
+
+    Ok(new_map)
+}
+
+//Large pages need SEC_LARGE_PAGES when creating the mapping and the SeLockMemoryPrivilege,
+//so only populating and locking are supported
+pub fn apply_memory_options(map: &MapData, options: MemoryOptions) -> MemoryOutcome {
+    let mut outcome = MemoryOutcome::default();
+    if options.wants_huge_pages() {
+        outcome.huge_pages = HugePages::Unavailable;
+    }
+    if options.wants_populate() {
+        //Every architecture Windows runs on uses 4KiB pages
+        touch_pages(map.map_ptr, map.map_size, 4096);
+        outcome.populated = true;
+    }
+    if options.wants_lock() {
+        //Limited by the working set size of the process
+        outcome.locked = unsafe { VirtualLock(map.map_ptr as *mut _, map.map_size) } != 0;
+    }
+    outcome
+}